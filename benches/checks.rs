@@ -0,0 +1,69 @@
+//! `box` arms against the `is` + `downcast` chain they replace.
+//!
+//! A hand-written chain checks the type twice for the arm that matches:
+//! once in `is`, and again in `downcast`. A `box` arm checks it once in
+//! release builds, and twice in debug builds, where the second check is a
+//! `debug_assert!`. Each switch has ten arms, and the subject matches the
+//! last one. The arm types are zero-sized, so the boxes never allocate.
+//!
+//! Run with `cargo bench --bench checks`, and with `--profile dev` for a
+//! debug build. On one machine (nanoseconds per dispatch):
+//!
+//! | profile | `typeswitch!` | `is` + `downcast` |
+//! |---------|---------------|-------------------|
+//! | dev     | 197           | 168               |
+//! | bench   | 17            | 27                |
+//!
+//! Without optimizations the switch also pays for the helper calls that
+//! its arms go through, which inlining removes in release builds.
+
+use std::any::Any;
+use std::hint::black_box;
+use std::time::Instant;
+use typeswitch::typeswitch;
+
+/// A distinct type per arm.
+struct Arm<const N: usize>;
+
+const ITERS: u32 = 2_000_000;
+
+macro_rules! switch {
+    ($x:expr; $($n:literal)+) => {
+        typeswitch!($x {
+            $(box v: Arm<$n> => { let Arm = v; $n })+
+            _ => { usize::MAX }
+        })
+    };
+}
+
+macro_rules! by_hand {
+    ($x:expr; $head:literal $($n:literal)*) => {{
+        let x: Box<dyn Any> = $x;
+        if x.is::<Arm<$head>>() {
+            let Arm = *x.downcast::<Arm<$head>>().unwrap();
+            $head
+        } else {
+            by_hand!(x; $($n)*)
+        }
+    }};
+    ($x:expr;) => {{
+        drop($x);
+        usize::MAX
+    }};
+}
+
+/// Nanoseconds per dispatch.
+fn time(f: impl Fn(Box<dyn Any>) -> usize) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ITERS {
+        black_box(f(black_box(Box::new(Arm::<9>))));
+    }
+    start.elapsed().as_secs_f64() * 1e9 / f64::from(ITERS)
+}
+
+fn main() {
+    let switch = time(|x| switch!(x; 0 1 2 3 4 5 6 7 8 9));
+    let by_hand = time(|x| by_hand!(x; 0 1 2 3 4 5 6 7 8 9));
+    println!("{:>14} {:>18}", "typeswitch!", "is + downcast");
+    println!("{switch:>12.2}ns {by_hand:>16.2}ns");
+}
@@ -0,0 +1,60 @@
+//! Linear `typeswitch!` against `typeswitch!(match ...)`.
+//!
+//! Each switch has `n` arms and the subject always matches the last one,
+//! the worst case for both forms. Run with `cargo bench`.
+
+use std::any::Any;
+use std::hint::black_box;
+use std::time::Instant;
+use typeswitch::typeswitch;
+
+/// A distinct type per arm.
+struct Arm<const N: usize>;
+
+const ITERS: u32 = 2_000_000;
+
+macro_rules! linear {
+    ($x:expr; $($n:literal)+) => {
+        typeswitch!(v as $x {
+            $(Arm<$n> => { let _ = v; $n })+
+            _ => { usize::MAX }
+        })
+    };
+}
+
+macro_rules! jump {
+    ($x:expr; $($n:literal)+) => {
+        typeswitch!(match v as $x {
+            $(Arm<$n> => { let _ = v; $n })+
+            _ => { usize::MAX }
+        })
+    };
+}
+
+/// Nanoseconds per dispatch.
+fn time(f: impl Fn(&dyn Any) -> usize, x: &dyn Any) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ITERS {
+        black_box(f(black_box(x)));
+    }
+    start.elapsed().as_secs_f64() * 1e9 / f64::from(ITERS)
+}
+
+macro_rules! row {
+    ($last:literal; $($n:literal)+) => {{
+        let x: &dyn Any = &Arm::<$last>;
+        let linear = time(|x| linear!(x; $($n)+), x);
+        let jump = time(|x| jump!(x; $($n)+), x);
+        println!("{:>5} {:>10.1}ns {:>10.1}ns", $last + 1, linear, jump);
+    }};
+}
+
+fn main() {
+    println!("{:>5} {:>12} {:>12}", "arms", "linear", "match");
+    row!(0; 0);
+    row!(1; 0 1);
+    row!(3; 0 1 2 3);
+    row!(7; 0 1 2 3 4 5 6 7);
+    row!(15; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+    row!(31; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
+}
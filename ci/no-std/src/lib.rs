@@ -0,0 +1,47 @@
+//! Built in CI for a bare-metal target, with and without `alloc`, to make sure
+//! the expansion never reaches for `std`.
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use core::any::Any;
+use typeswitch::typeswitch;
+
+pub fn describe(x: &dyn Any) -> u32 {
+    typeswitch! { x {
+        n: u32 if *n > 10 => { *n }
+        n: u8 => { u32::from(*n) }
+        u16 | u64 => { 1 }
+        _ => { 0 }
+    }}
+}
+
+pub fn bump(x: &mut dyn Any) {
+    typeswitch!(mut v as x {
+        u32 => { *v += 1 }
+        _ => {}
+    });
+}
+
+pub fn check(x: &dyn Any) -> Result<u32, &dyn Any> {
+    typeswitch!(try x {
+        n: u32 => { *n }
+    })
+}
+
+#[cfg(feature = "alloc")]
+pub fn take(x: alloc::boxed::Box<dyn Any>) -> u32 {
+    use alloc::rc::Rc;
+
+    let mut shared: Rc<dyn Any> = Rc::new(0u32);
+    typeswitch! { shared {
+        rc_mut n: u32 => { *n += 1 }
+        _ => {}
+    }}
+
+    typeswitch! { x {
+        box n: u32 => { n }
+        _ => { 0 }
+    }}
+}
@@ -0,0 +1,186 @@
+//! A runtime counterpart to [`typeswitch!`](crate::typeswitch) for dispatch
+//! tables that are only known at runtime, such as plugin registries.
+
+use std::any::{Any, TypeId};
+use std::boxed::Box;
+use std::collections::HashMap;
+
+/// A type switch whose cases are registered at runtime.
+///
+/// Each case handles exactly one type and is stored under its `TypeId`, so
+/// [`eval`](TypeSwitch::eval) is a single hash lookup followed by one downcast.
+///
+/// # Ordering
+///
+/// The macro tries its arms from top to bottom and takes the first one that
+/// matches. A `TypeSwitch` has no such order: at most one case can exist per
+/// type, and registering a second case for the same type replaces the first,
+/// whatever its kind. The default only runs when no case for the value's exact
+/// type applies.
+///
+/// # Example
+///
+/// ```rust
+/// use typeswitch::TypeSwitch;
+/// use std::any::Any;
+///
+/// let describe = TypeSwitch::new()
+///     .case(|n: &i32| format!("int {n}"))
+///     .case(|s: &String| format!("string {s:?}"))
+///     .default(|_| String::from("unknown"));
+///
+/// assert_eq!(describe.eval(&42i32), "int 42");
+/// assert_eq!(describe.eval(&1.5f64), "unknown");
+/// ```
+pub struct TypeSwitch<R> {
+    cases: HashMap<TypeId, Case<R>>,
+    default: Option<RefFn<R>>,
+}
+
+type RefFn<R> = Box<dyn Fn(&dyn Any) -> R>;
+type MutFn<R> = Box<dyn Fn(&mut dyn Any) -> R>;
+type OwnedFn<R> = Box<dyn Fn(Box<dyn Any>) -> R>;
+
+/// How a registered case wants to receive the value.
+enum Case<R> {
+    Ref(RefFn<R>),
+    Mut(MutFn<R>),
+    Owned(OwnedFn<R>),
+}
+
+const DOWNCAST_FAILED: &str = "typeswitch: type check passed but downcast failed";
+
+impl<R> TypeSwitch<R> {
+    /// Creates a switch with no cases and no default.
+    pub fn new() -> Self {
+        TypeSwitch { cases: HashMap::new(), default: None }
+    }
+
+    /// Handles `T` by shared reference. Applies to every `eval*` method.
+    pub fn case<T: Any, F: Fn(&T) -> R + 'static>(mut self, f: F) -> Self {
+        let case = Case::Ref(Box::new(move |v: &dyn Any| f(v.downcast_ref::<T>().expect(DOWNCAST_FAILED))));
+        self.cases.insert(TypeId::of::<T>(), case);
+        self
+    }
+
+    /// Handles `T` by mutable reference. Applies to [`eval_mut`](Self::eval_mut)
+    /// and [`eval_owned`](Self::eval_owned).
+    pub fn case_mut<T: Any, F: Fn(&mut T) -> R + 'static>(mut self, f: F) -> Self {
+        let case = Case::Mut(Box::new(move |v: &mut dyn Any| f(v.downcast_mut::<T>().expect(DOWNCAST_FAILED))));
+        self.cases.insert(TypeId::of::<T>(), case);
+        self
+    }
+
+    /// Handles `T` by value. Only applies to [`eval_owned`](Self::eval_owned).
+    pub fn case_owned<T: Any, F: Fn(T) -> R + 'static>(mut self, f: F) -> Self {
+        let case = Case::Owned(Box::new(move |v: Box<dyn Any>| f(*v.downcast::<T>().expect(DOWNCAST_FAILED))));
+        self.cases.insert(TypeId::of::<T>(), case);
+        self
+    }
+
+    /// Runs when no case applies, like the `_` arm of the macro.
+    pub fn default<F: Fn(&dyn Any) -> R + 'static>(mut self, f: F) -> Self {
+        self.default = Some(Box::new(f));
+        self
+    }
+
+    /// Dispatches on a shared reference. Only [`case`](Self::case) handlers apply.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no case applies and no default was set.
+    pub fn eval(&self, value: &dyn Any) -> R {
+        match self.cases.get(&(*value).type_id()) {
+            Some(Case::Ref(f)) => f(value),
+            _ => self.fallback(value),
+        }
+    }
+
+    /// Dispatches on a mutable reference. [`case`](Self::case) and
+    /// [`case_mut`](Self::case_mut) handlers apply.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no case applies and no default was set.
+    pub fn eval_mut(&self, value: &mut dyn Any) -> R {
+        match self.cases.get(&(*value).type_id()) {
+            Some(Case::Ref(f)) => f(value),
+            Some(Case::Mut(f)) => f(value),
+            _ => self.fallback(value),
+        }
+    }
+
+    /// Dispatches on an owned box. Every kind of case applies.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no case applies and no default was set.
+    pub fn eval_owned(&self, mut value: Box<dyn Any>) -> R {
+        // `value.type_id()` would be the id of the `Box` itself.
+        match self.cases.get(&(*value).type_id()) {
+            Some(Case::Ref(f)) => f(&*value),
+            Some(Case::Mut(f)) => f(&mut *value),
+            Some(Case::Owned(f)) => f(value),
+            None => self.fallback(&*value),
+        }
+    }
+
+    fn fallback(&self, value: &dyn Any) -> R {
+        match &self.default {
+            Some(f) => f(value),
+            None => panic!("typeswitch: no case matched and no default was set"),
+        }
+    }
+}
+
+impl<R> Default for TypeSwitch<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypeSwitch;
+    use std::any::Any;
+
+    #[test]
+    fn test_case_kinds() {
+        let switch = TypeSwitch::new()
+            .case(|n: &i32| *n)
+            .case_mut(|n: &mut u8| {
+                *n += 1;
+                i32::from(*n)
+            })
+            .case_owned(|s: String| s.len() as i32)
+            .default(|_| -1);
+
+        assert_eq!(switch.eval(&5i32), 5);
+        // Mutable and owned cases need a subject that can give that access.
+        assert_eq!(switch.eval(&5u8), -1);
+        assert_eq!(switch.eval(&String::from("abc")), -1);
+
+        let mut byte = 5u8;
+        assert_eq!(switch.eval_mut(&mut byte), 6);
+        assert_eq!(byte, 6);
+
+        assert_eq!(switch.eval_owned(Box::new(String::from("abc"))), 3);
+        assert_eq!(switch.eval_owned(Box::new(7i32)), 7);
+        assert_eq!(switch.eval_owned(Box::new(1.5f64)), -1);
+    }
+
+    #[test]
+    fn test_later_case_replaces_earlier() {
+        let switch = TypeSwitch::new().case(|_: &i32| "first").case(|_: &i32| "second");
+        assert_eq!(switch.eval(&1i32), "second");
+
+        let boxed: Box<dyn Any> = Box::new(1i32);
+        assert_eq!(switch.eval(&*boxed), "second");
+    }
+
+    #[test]
+    #[should_panic(expected = "no case matched")]
+    fn test_missing_default_panics() {
+        TypeSwitch::new().case(|_: &i32| ()).eval(&1u8);
+    }
+}
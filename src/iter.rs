@@ -0,0 +1,319 @@
+//! Iterator adapters that filter a stream of type-erased values down to one
+//! type, the single-type counterpart of [`typeswitch_each!`](crate::typeswitch_each).
+//! [`all_same_type`] and [`is_all`] check a whole slice of them at once.
+
+use core::any::{Any, TypeId};
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::vec::Vec;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// An item of a type-erased iterator: a `&dyn Any`, `&mut dyn Any` or
+/// `Box<dyn Any>`, each also with `+ Send`, `+ Sync` or `+ Send + Sync`.
+pub trait AnyItem: sealed::Sealed {
+    /// The `TypeId` of the value behind the item, not of the item itself.
+    fn value_type_id(&self) -> TypeId;
+}
+
+/// A reference item, which [`AnyIterExt::downcast_filter_ref`] downcasts.
+pub trait AnyRef: AnyItem {
+    /// `&'a T` for an item borrowed for `'a`.
+    type Ref<T: Any>;
+
+    /// The value as a `&T`, if it is one.
+    fn downcast_value_ref<T: Any>(self) -> Option<Self::Ref<T>>;
+}
+
+/// A boxed item, which [`AnyIterExt::downcast_filter`] moves the value out of.
+#[cfg(feature = "alloc")]
+pub trait AnyBox: AnyItem {
+    /// The value itself, if it is a `T`.
+    fn downcast_value<T: Any>(self) -> Option<T>;
+}
+
+macro_rules! any_items {
+    ($($object:ty),+) => {$(
+        impl sealed::Sealed for &$object {}
+        impl sealed::Sealed for &mut $object {}
+
+        impl AnyItem for &$object {
+            fn value_type_id(&self) -> TypeId {
+                (**self).type_id()
+            }
+        }
+
+        impl AnyItem for &mut $object {
+            fn value_type_id(&self) -> TypeId {
+                (**self).type_id()
+            }
+        }
+
+        impl<'a> AnyRef for &'a $object {
+            type Ref<T: Any> = &'a T;
+
+            fn downcast_value_ref<T: Any>(self) -> Option<&'a T> {
+                crate::downcast_ref_any::<T>(self)
+            }
+        }
+
+        impl<'a> AnyRef for &'a mut $object {
+            type Ref<T: Any> = &'a T;
+
+            fn downcast_value_ref<T: Any>(self) -> Option<&'a T> {
+                // Only shared access is handed out, so reborrow for the whole `'a`.
+                let value: &'a $object = self;
+                crate::downcast_ref_any::<T>(value)
+            }
+        }
+
+        #[cfg(feature = "alloc")]
+        impl sealed::Sealed for Box<$object> {}
+
+        #[cfg(feature = "alloc")]
+        impl AnyItem for Box<$object> {
+            fn value_type_id(&self) -> TypeId {
+                // `self.type_id()` would be the id of the `Box` itself.
+                (**self).type_id()
+            }
+        }
+
+        #[cfg(feature = "alloc")]
+        impl AnyBox for Box<$object> {
+            fn downcast_value<T: Any>(self) -> Option<T> {
+                crate::downcast_owned::<T>(self).ok()
+            }
+        }
+    )+};
+}
+
+any_items!(dyn Any, dyn Any + Send, dyn Any + Sync, dyn Any + Send + Sync);
+
+/// Adapters for iterators over type-erased values.
+///
+/// Implemented for every iterator; each method is available when the items
+/// are of the kind it needs.
+///
+/// # Example
+///
+/// ```rust
+/// # #[cfg(feature = "std")] {
+/// use typeswitch::AnyIterExt;
+/// use std::any::{Any, TypeId};
+///
+/// let items: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new("skip"), Box::new(2i32)];
+/// let refs: Vec<i32> = items.iter().map(|b| &**b).downcast_filter_ref::<i32>().copied().collect();
+/// assert_eq!(refs, [1, 2]);
+///
+/// let buckets = items.iter().map(|b| &**b).partition_by_type();
+/// assert_eq!(buckets[&TypeId::of::<i32>()].len(), 2);
+///
+/// let ints: Vec<i32> = items.into_iter().downcast_filter::<i32>().collect();
+/// assert_eq!(ints, [1, 2]);
+/// # }
+/// ```
+pub trait AnyIterExt: Iterator + Sized {
+    /// Moves every `T` out of its box, and drops the other items.
+    #[cfg(feature = "alloc")]
+    fn downcast_filter<T: Any>(self) -> DowncastFilter<Self, T>
+    where
+        Self::Item: AnyBox,
+    {
+        DowncastFilter { iter: self, marker: PhantomData }
+    }
+
+    /// Yields a `&T` for every item that is a `T`, and skips the others.
+    fn downcast_filter_ref<T: Any>(self) -> DowncastFilterRef<Self, T>
+    where
+        Self::Item: AnyRef,
+    {
+        DowncastFilterRef { iter: self, marker: PhantomData }
+    }
+
+    /// Splits the items into buckets keyed by the `TypeId` of their values.
+    /// Each bucket keeps its items in iteration order.
+    #[cfg(feature = "std")]
+    fn partition_by_type(self) -> HashMap<TypeId, Vec<Self::Item>>
+    where
+        Self::Item: AnyItem,
+    {
+        let mut buckets: HashMap<TypeId, Vec<Self::Item>> = HashMap::new();
+        for item in self {
+            buckets.entry(item.value_type_id()).or_default().push(item);
+        }
+        buckets
+    }
+}
+
+impl<I: Iterator> AnyIterExt for I {}
+
+/// The one `TypeId` that every value in `items` has, or `None` if they differ
+/// or there are none. Stops at the first value of another type.
+///
+/// ```rust
+/// # #[cfg(feature = "alloc")] {
+/// use std::any::{Any, TypeId};
+///
+/// let ints: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new(2i32)];
+/// assert_eq!(typeswitch::all_same_type(&ints), Some(TypeId::of::<i32>()));
+///
+/// let mixed: [&dyn Any; 2] = [&1i32, &2u8];
+/// assert_eq!(typeswitch::all_same_type(&mixed), None);
+/// # }
+/// ```
+pub fn all_same_type<I: AnyItem>(items: &[I]) -> Option<TypeId> {
+    let (first, rest) = items.split_first()?;
+    let id = first.value_type_id();
+    rest.iter().all(|item| item.value_type_id() == id).then_some(id)
+}
+
+/// Whether every value in `items` is a `T`, so a bulk operation can take the
+/// fast path. Stops at the first value that is not; an empty slice is all `T`s.
+///
+/// ```rust
+/// use std::any::Any;
+///
+/// let values: [&dyn Any; 3] = [&1i32, &2i32, &3i32];
+/// if typeswitch::is_all::<i32, _>(&values) {
+///     let sum: i32 = values.iter().map(|v| v.downcast_ref::<i32>().unwrap()).sum();
+///     assert_eq!(sum, 6);
+/// }
+/// ```
+pub fn is_all<T: Any, I: AnyItem>(items: &[I]) -> bool {
+    items.iter().all(|item| item.value_type_id() == TypeId::of::<T>())
+}
+
+/// The iterator returned by [`AnyIterExt::downcast_filter`].
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug)]
+pub struct DowncastFilter<I, T> {
+    iter: I,
+    marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "alloc")]
+impl<I: Iterator<Item: AnyBox>, T: Any> Iterator for DowncastFilter<I, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.iter.find_map(AnyBox::downcast_value::<T>)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I: DoubleEndedIterator<Item: AnyBox>, T: Any> DoubleEndedIterator for DowncastFilter<I, T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.iter.by_ref().rev().find_map(AnyBox::downcast_value::<T>)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I: FusedIterator<Item: AnyBox>, T: Any> FusedIterator for DowncastFilter<I, T> {}
+
+/// The iterator returned by [`AnyIterExt::downcast_filter_ref`].
+#[derive(Clone, Debug)]
+pub struct DowncastFilterRef<I, T> {
+    iter: I,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<I: Iterator<Item: AnyRef>, T: Any> Iterator for DowncastFilterRef<I, T> {
+    type Item = <I::Item as AnyRef>::Ref<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find_map(AnyRef::downcast_value_ref::<T>)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<I: DoubleEndedIterator<Item: AnyRef>, T: Any> DoubleEndedIterator for DowncastFilterRef<I, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.by_ref().rev().find_map(AnyRef::downcast_value_ref::<T>)
+    }
+}
+
+impl<I: FusedIterator<Item: AnyRef>, T: Any> FusedIterator for DowncastFilterRef<I, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::AnyIterExt;
+    #[cfg(feature = "alloc")]
+    use super::{all_same_type, is_all};
+    use std::any::Any;
+    #[cfg(feature = "alloc")]
+    use std::any::TypeId;
+
+    fn items() -> Vec<Box<dyn Any>> {
+        vec![Box::new(1i32), Box::new(String::from("a")), Box::new(2i32), Box::new(3u8)]
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_downcast_filter() {
+        let ints: Vec<i32> = items().into_iter().downcast_filter::<i32>().collect();
+        assert_eq!(ints, [1, 2]);
+
+        let last: Option<String> = items().into_iter().downcast_filter::<String>().next_back();
+        assert_eq!(last.as_deref(), Some("a"));
+
+        let sendable: Vec<Box<dyn Any + Send>> = vec![Box::new(1u8), Box::new(2u16)];
+        assert_eq!(sendable.into_iter().downcast_filter::<u16>().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn test_downcast_filter_ref() {
+        let mut items = items();
+        let refs: Vec<&i32> = items.iter().map(|b| &**b).downcast_filter_ref::<i32>().collect();
+        assert_eq!(refs, [&1, &2]);
+
+        let muts = items.iter_mut().map(|b| &mut **b);
+        assert_eq!(muts.downcast_filter_ref::<u8>().rev().collect::<Vec<_>>(), [&3]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_partition_by_type() {
+        let buckets = items().into_iter().partition_by_type();
+        assert_eq!(buckets.len(), 3);
+
+        let ints = &buckets[&TypeId::of::<i32>()];
+        let values: Vec<i32> = ints.iter().map(|b| *b.downcast_ref::<i32>().unwrap()).collect();
+        assert_eq!(values, [1, 2]);
+        assert!(!buckets.contains_key(&TypeId::of::<Box<dyn Any>>()));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_homogeneous_slices() {
+        let ints: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new(2i32)];
+        assert_eq!(all_same_type(&ints), Some(TypeId::of::<i32>()));
+        assert!(is_all::<i32, _>(&ints));
+        assert!(!is_all::<Box<dyn Any>, _>(&ints));
+
+        let mixed = items();
+        assert_eq!(all_same_type(&mixed), None);
+        assert!(!is_all::<i32, _>(&mixed));
+
+        let refs: Vec<&(dyn Any + Send)> = vec![&1u8, &2u8];
+        assert_eq!(all_same_type(&refs), Some(TypeId::of::<u8>()));
+
+        let empty: [&dyn Any; 0] = [];
+        assert_eq!(all_same_type(&empty), None);
+        assert!(is_all::<u8, _>(&empty));
+    }
+}
@@ -11,368 +11,7332 @@
 //! - **Owned Consumption**: Move values out of a `Box<dyn Any>`.
 //! - **Go-style Binding**: Automatically bind the downcasted value to a variable for all branches.
 //! - **Or-Patterns**: Match against multiple types in a single branch.
+//!
+//!
+//! For dispatch tables that are only known at runtime, `TypeSwitch` offers the
+//! same idea as a builder.
+//! To pick one type out of a stream of type-erased values, [`AnyIterExt`] adds
+//! `downcast_filter` and `downcast_filter_ref` to every iterator.
+//!
+//! The crate is `no_std`: the generated code only names `::core::any::Any`.
+//! `box`, `replace`, `arc`, `rc`, `rc_mut` and `weak` arms need the `alloc` feature, and `TypeSwitch`
+//! needs `std`. Both are on by default.
+//! For values that borrow, and so can't be `Any`, the `unsafe-scoped` feature
+//! adds `ScopedAny` and `scoped_typeswitch!`.
+//! The `debug` feature, which needs `std`, makes every switch print its type tests
+//! to stderr.
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(all(feature = "std", not(test)))]
+extern crate std;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::any::{Any, TypeId};
+use core::fmt;
+
+#[cfg(feature = "std")]
+mod builder;
+
+#[cfg(feature = "std")]
+pub use builder::TypeSwitch;
+
+mod iter;
+#[cfg(feature = "unsafe-scoped")]
+mod scoped;
 
+#[cfg(feature = "alloc")]
+pub use iter::{AnyBox, DowncastFilter};
+pub use iter::{AnyItem, AnyIterExt, AnyRef, DowncastFilterRef, all_same_type, is_all};
+#[cfg(feature = "unsafe-scoped")]
+pub use scoped::{ScopedAny, ScopedType};
 
-/// A powerful macro to emulate a type switch statement for `dyn Any` trait objects.
+#[doc = include_str!("typeswitch.md")]
+#[cfg(not(feature = "proc-macro"))]
+#[macro_export]
+macro_rules! typeswitch {
+    ($($rest:tt)*) => {
+        $crate::__typeswitch!($($rest)*)
+    };
+}
+
+#[doc = include_str!("typeswitch.md")]
 ///
-/// This macro allows you to match on the concrete type of a `Box<dyn Any>` or `&dyn Any`,
-/// similar to a `match` statement but for runtime types. It supports both immutable and
-/// mutable downcasting, attributes, and default fallbacks.
+/// With the `proc-macro` feature, `typeswitch!` is a procedural front end that
+/// checks every arm, reports mistakes at the offending tokens and then expands
+/// through the exact same rules as the default `macro_rules!` version.
+#[cfg(feature = "proc-macro")]
+pub use typeswitch_macros::typeswitch;
+
+/// Derives a `dispatch` method for an enum whose variants carry a `Box<dyn Any>`
+/// or `Arc<dyn Any>` payload, so a [`typeswitch!`] can run on whichever variant
+/// the enum holds. Variants without a payload are dispatched as `()`.
 ///
-/// # Syntax
+/// ```rust
+/// use typeswitch::{typeswitch, TypeDispatch};
+/// use std::any::Any;
+/// use std::sync::Arc;
 ///
-/// ```text
-/// typeswitch! {
-///     [modifiers] subject {
-///         [attributes] binding: Type => { block }
-///         TypeA | TypeB => { block }
-///         ...
-///         _ => { fallback block }
-///     }
+/// #[derive(TypeDispatch)]
+/// enum Message {
+///     Local(Box<dyn Any>),
+///     Remote { from: u32, payload: Arc<dyn Any + Send + Sync> },
+///     Quit,
 /// }
-/// ```
 ///
-/// - **subject**: The variable to switch on. It must be an identifier.
-///   - Use `x` for immutable access (bindings will be `&T`).
-///   - Use `mut x` for mutable access (bindings will be `&mut T`).
-/// - **binding**: The name to bind the downcasted value to.
-/// - **Type**: The concrete type to check for.
+/// fn describe(message: &Message) -> String {
+///     message.dispatch(|v| typeswitch! { v as v {
+///         i32 => format!("int {v}"),
+///         String => format!("string {v:?}"),
+///         () => String::from("no payload"),
+///         _ => String::from("unknown"),
+///     }})
+/// }
 ///
-/// # Examples
+/// assert_eq!(describe(&Message::Local(Box::new(7i32))), "int 7");
+/// assert_eq!(describe(&Message::Remote { from: 1, payload: Arc::new(String::from("hi")) }), "string \"hi\"");
+/// assert_eq!(describe(&Message::Quit), "no payload");
+/// ```
+#[cfg(feature = "proc-macro")]
+pub use typeswitch_macros::TypeDispatch;
+
+/// Turns a function whose body is a `match` on its `&dyn Any` parameter into
+/// a [`typeswitch!`] on that parameter, keeping the `match` shape that rustfmt
+/// and editors already understand.
 ///
-/// ## 1. Basic Immutable Switch
+/// An arm names its type like a tuple struct pattern: `i32(n)` binds the
+/// value, `String` or `String(_)` only tests for it, and `_` is the default.
+/// Or-patterns, guards and attributes carry over, and generic types use a
+/// turbofish, as in `Vec::<u8>(bytes)`. A `&mut dyn Any` parameter binds
+/// `&mut` values and a `Box<dyn Any>` one owned values; for other arm kinds
+/// or types that aren't paths, use the macro.
 ///
 /// ```rust
-/// # use typeswitch::typeswitch;
-/// # use std::any::Any;
-/// let b: Box<dyn Any> = Box::new(42i32);
+/// use typeswitch::cases;
+/// use std::any::Any;
 ///
-/// let res = typeswitch! { b {
-///         num: i32 => { format!("Integer: {}", num) }
-///         s: String => { format!("String: {}", s) }
-///         _ => { "Unknown".to_string() }
+/// #[cases]
+/// fn describe(value: &dyn Any) -> String {
+///     match value {
+///         i32(n) if *n < 0 => format!("negative {n}"),
+///         i32(n) => format!("int {n}"),
+///         String(s) => format!("string {s:?}"),
+///         u8 | u16 => String::from("small"),
+///         Vec::<u8>(bytes) => format!("{} bytes", bytes.len()),
+///         _ => String::from("unknown"),
 ///     }
-/// };
+/// }
+///
+/// assert_eq!(describe(&-1i32), "negative -1");
+/// assert_eq!(describe(&String::from("hi")), "string \"hi\"");
+/// assert_eq!(describe(&7u16), "small");
+/// assert_eq!(describe(&vec![1u8, 2]), "2 bytes");
+/// assert_eq!(describe(&1.5f64), "unknown");
 /// ```
+#[cfg(feature = "proc-macro")]
+pub use typeswitch_macros::cases;
+
+// Lets the proc-macro's `::typeswitch::__typeswitch!` path resolve in this crate's own tests.
+#[cfg(feature = "proc-macro")]
+extern crate self as typeswitch;
+
+/// [`typeswitch!`] with every binding borrowed as `&T`.
 ///
-/// ## 2. Bound Mutable Switch
-/// Prefix the binding with `mut` or use the `mut v as x` syntax to gain mutable access.
+/// The arms take no access modifier, so a stray `mut` or `box` is an error
+/// instead of quietly changing how one arm sees the subject.
 ///
 /// ```rust
-/// # use typeswitch::typeswitch;
+/// # use typeswitch::typeswitch_ref;
+/// # use std::any::Any;
+/// let x: &dyn Any = &5i32;
+/// let double = typeswitch_ref!(x {
+///     n: i32 => n * 2,
+///     _ => 0,
+/// });
+/// assert_eq!(double, 10);
+/// ```
+#[macro_export]
+macro_rules! typeswitch_ref {
+    ($($rest:tt)+) => {
+        $crate::__typeswitch!(@fixed [] [] $($rest)+)
+    };
+}
+
+/// [`typeswitch!`] with every binding borrowed as `&mut T`, as if each arm
+/// (and a `v as x` pre-binding) was written with `mut`. `typeswitch!(mut x { ... })`
+/// is the same switch.
+///
+/// ```rust
+/// # use typeswitch::typeswitch_mut;
 /// # use std::any::Any;
-/// let mut x: Box<dyn Any> = Box::new(10i32);
+/// let mut x: Box<dyn Any> = Box::new(5i32);
+/// typeswitch_mut!(v as x {
+///     i32 => *v += 1,
+///     String => v.push('!'),
+/// });
+/// assert_eq!(x.downcast_ref::<i32>(), Some(&6));
+/// ```
+#[macro_export]
+macro_rules! typeswitch_mut {
+    ($($rest:tt)+) => {
+        $crate::__typeswitch!(@fixed [mut] [] $($rest)+)
+    };
+}
+
+/// [`typeswitch!`] that moves every binding out of a `Box<dyn Any>`, as if each
+/// arm (and a `v as x` pre-binding) was written with `box`.
 ///
-/// typeswitch!(mut v as x {
-///     i32 => { *v += 5; }
-///     _ => {},
+/// ```rust
+/// # #[cfg(feature = "alloc")] {
+/// # use typeswitch::typeswitch_owned;
+/// # use std::any::Any;
+/// let x: Box<dyn Any> = Box::new(String::from("hi"));
+/// let s: String = typeswitch_owned!(x {
+///     s: String => s,
+///     n: i32 => n.to_string(),
+///     _ => String::new(),
 /// });
+/// assert_eq!(s, "hi");
+/// # }
 /// ```
+#[macro_export]
+macro_rules! typeswitch_owned {
+    ($($rest:tt)+) => {
+        $crate::__typeswitch!(@fixed [box] [] $($rest)+)
+    };
+}
+
+/// Runs a [`typeswitch!`] on every item of an iterable.
 ///
-/// ## 3. Owned Consumption (Moving out of Box)
-/// Use the `box` keyword to take ownership of the value. This branch will only 
-/// execute if the type matches, and it will consume the `Box`.
+/// `typeswitch_each!(items { ... })` is `for item in items { typeswitch!(item { ... }) }`,
+/// so how `items` is passed decides what the arms can do. A `Vec<Box<dyn Any>>`
+/// is consumed and `box` arms move values out, while `&items` and `&mut items`
+/// only borrow each box. A `v as items` pre-binding applies to every item. The arms
+/// evaluate to `()`, and `break` and `continue` act on the loop.
 ///
 /// ```rust
-/// # use typeswitch::typeswitch;
+/// # #[cfg(feature = "alloc")] {
+/// # use typeswitch::typeswitch_each;
 /// # use std::any::Any;
-/// let x: Box<dyn Any> = Box::new(String::from("Move me"));
+/// let mut items: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new(String::from("a")), Box::new(2i32)];
 ///
-/// typeswitch! { x {
-///     box s: String => { println!("Consumed: {}", s) } // s is String (owned)
+/// let mut sum = 0;
+/// typeswitch_each!(mut v as &mut items {
+///     i32 => { *v *= 10; sum += *v }
 ///     _ => {}
-/// }}
+/// });
+/// assert_eq!(sum, 30);
+///
+/// let mut strings = Vec::new();
+/// typeswitch_each!(items {
+///     box s: String => strings.push(s),
+///     i32 => continue,
+///     _ => break,
+/// });
+/// assert_eq!(strings, ["a"]);
+/// # }
 /// ```
+#[macro_export]
+macro_rules! typeswitch_each {
+    ($($rest:tt)+) => {
+        $crate::__typeswitch!(@each [] [] $($rest)+)
+    };
+}
+
+/// Checks whether a subject holds one of the listed types, without a switch.
 ///
-/// ## 4. Piped Switch
+/// The subject is anything [`typeswitch!`] borrows from: `&dyn Any`,
+/// `&mut dyn Any` or `Box<dyn Any>`. It is only borrowed, and evaluated once.
 ///
 /// ```rust
-/// # use typeswitch::typeswitch;
+/// # use typeswitch::matches_type;
 /// # use std::any::Any;
-/// let b: Box<dyn Any> = Box::new(10i32);
+/// let x: Box<dyn Any> = Box::new(1.5f64);
+/// assert!(matches_type!(x, i32 | i64 | f64));
+/// assert!(!matches_type!(&*x, String));
+/// ```
+#[macro_export]
+macro_rules! matches_type {
+    ($subject:expr, $($ty:ty)|+ $(,)?) => {
+        match $crate::__typeswitch!(@as_any $subject) {
+            subject => $(<dyn ::core::any::Any>::is::<$ty>(subject))||+,
+        }
+    };
+}
+
+/// The subject as `Option<&T>`: `Some` if it holds a `T`.
 ///
-/// typeswitch! { b {
-///         String | &str => {println!("string")}
-///         i32 => {println!("Number!")}
-///     }
+/// A one-arm switch for the common extract-or-default case. The subject is
+/// borrowed like in [`matches_type!`].
+///
+/// ```rust
+/// # use typeswitch::from_any;
+/// # use std::any::Any;
+/// let x: Box<dyn Any> = Box::new(7i32);
+/// assert_eq!(from_any!(x, i32).copied().unwrap_or(0), 7);
+/// assert_eq!(from_any!(x, String).map_or(0, String::len), 0);
+/// ```
+#[macro_export]
+macro_rules! from_any {
+    ($subject:expr, $ty:ty $(,)?) => {
+        $crate::downcast_ref_any::<$ty>($crate::__typeswitch!(@as_any $subject))
+    };
+}
+
+/// The subject as `Option<&mut T>`, like [`from_any!`] but mutably borrowed.
+///
+/// ```rust
+/// # use typeswitch::from_any_mut;
+/// # use std::any::Any;
+/// let mut x: Box<dyn Any> = Box::new(7i32);
+/// if let Some(n) = from_any_mut!(x, i32) {
+///     *n += 1;
 /// }
+/// assert_eq!(x.downcast_ref::<i32>(), Some(&8));
 /// ```
+#[macro_export]
+macro_rules! from_any_mut {
+    ($subject:expr, $ty:ty $(,)?) => {
+        $crate::downcast_mut_any::<$ty>($crate::__typeswitch!(@as_any_mut $subject))
+    };
+}
+
+/// The value moved out of a `Box<dyn Any>` subject as `Option<T>`, like a
+/// `box` arm. The box is consumed, and dropped if it holds something else.
 ///
-/// ## 5. Automatic Binding (Go-style)
-/// By providing a variable name before the block, that name is automatically 
-/// bound to the downcasted type in every branch.
 /// ```rust
-/// # use typeswitch::typeswitch;
+/// # #[cfg(feature = "alloc")] {
+/// # use typeswitch::from_any_owned;
 /// # use std::any::Any;
-/// let x: &dyn Any = &100i32;
+/// let x: Box<dyn Any> = Box::new(String::from("hi"));
+/// assert_eq!(from_any_owned!(x, String).as_deref(), Some("hi"));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! from_any_owned {
+    ($subject:expr, $ty:ty $(,)?) => {
+        $crate::typeswitch!($subject {
+            box value: $ty => ::core::option::Option::Some(value),
+            _ => ::core::option::Option::None,
+        })
+    };
+}
+
+/// Borrows a value as `&dyn Any`, without writing out the coercion.
 ///
-/// typeswitch!(val as x {
-///     i32 => {println!("Double is: {}", val * 2)}
-///     f64 => {println!("Half is: {}", val / 2.0)}
-///     _ => {}
+/// Takes any expression and evaluates it once. A box or reference to a `dyn Any`
+/// gives the value inside it, like a [`typeswitch!`] subject, rather than being
+/// wrapped itself.
+///
+/// ```rust
+/// # use typeswitch::{as_any, typeswitch};
+/// # use std::any::Any;
+/// let items = vec![1u8, 2];
+/// assert!(as_any!(items).is::<Vec<u8>>());
+/// assert!(as_any!(items[0]).is::<u8>());
+///
+/// let boxed: Box<dyn Any> = Box::new(1.5f64);
+/// assert!(as_any!(boxed).is::<f64>());
+/// ```
+#[macro_export]
+macro_rules! as_any {
+    ($value:expr $(,)?) => {
+        match &$value {
+            value => $crate::__typeswitch!(@as_any value),
+        }
+    };
+}
+
+/// The `&mut dyn Any` counterpart of [`as_any!`].
+///
+/// ```rust
+/// # use typeswitch::as_any_mut;
+/// let mut count = 1u32;
+/// *as_any_mut!(count).downcast_mut::<u32>().unwrap() += 1;
+/// assert_eq!(count, 2);
+/// ```
+#[macro_export]
+macro_rules! as_any_mut {
+    ($value:expr $(,)?) => {
+        match &mut $value {
+            value => $crate::__typeswitch!(@as_any_mut value),
+        }
+    };
+}
+
+/// Boxes a value as `Box<dyn Any>`. A `Box<dyn Any>` (or `+ Send`, `+ Sync`, `+ Send + Sync`)
+/// is passed on as it is instead of being boxed again.
+///
+/// ```rust
+/// # use typeswitch::into_any;
+/// # use std::any::Any;
+/// let boxed: Box<dyn Any> = into_any!(String::from("hi"));
+/// assert!(boxed.is::<String>());
+/// assert!(into_any!(boxed).is::<String>());
+/// ```
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! into_any {
+    ($value:expr $(,)?) => {
+        match $value {
+            value => {
+                #[allow(unused_imports)]
+                use $crate::__private::{CoerceKind as _, DirectKind as _, PointerKind as _};
+                (&&&$crate::__private::Probe(&value)).kind().into_box(value)
+            }
+        }
+    };
+}
+
+/// The subject as `Result<&T, TypeMismatch>`, for functions that pass a wrong
+/// type on with `?`.
+///
+/// Borrows the subject like [`from_any!`]. Without a type, `T` is inferred
+/// from how the result is used.
+///
+/// ```rust
+/// # use typeswitch::{downcast_or_bail, TypeMismatch};
+/// # use std::any::Any;
+/// fn double(x: &dyn Any) -> Result<i32, TypeMismatch> {
+///     let n: &i32 = downcast_or_bail!(x)?;
+///     Ok(n * 2)
+/// }
+/// assert_eq!(double(&4i32), Ok(8));
+/// assert_eq!(double(&4u8).unwrap_err().expected, "i32");
+/// ```
+#[macro_export]
+macro_rules! downcast_or_bail {
+    ($subject:expr, $ty:ty $(,)?) => {
+        $crate::__private::downcast_ref_or_mismatch::<$ty>($crate::__typeswitch!(@as_any $subject))
+    };
+    ($subject:expr $(,)?) => {
+        $crate::__private::downcast_ref_or_mismatch($crate::__typeswitch!(@as_any $subject))
+    };
+}
+
+/// The subject as `Result<&mut T, TypeMismatch>`, like [`downcast_or_bail!`] but
+/// mutably borrowed.
+///
+/// ```rust
+/// # use typeswitch::{downcast_or_bail_mut, TypeMismatch};
+/// # use std::any::Any;
+/// fn bump(x: &mut dyn Any) -> Result<(), TypeMismatch> {
+///     *downcast_or_bail_mut!(x, u32)? += 1;
+///     Ok(())
+/// }
+/// let mut n = 1u32;
+/// assert!(bump(&mut n).is_ok());
+/// assert_eq!(n, 2);
+/// ```
+#[macro_export]
+macro_rules! downcast_or_bail_mut {
+    ($subject:expr, $ty:ty $(,)?) => {
+        $crate::__private::downcast_mut_or_mismatch::<$ty>($crate::__typeswitch!(@as_any_mut $subject))
+    };
+    ($subject:expr $(,)?) => {
+        $crate::__private::downcast_mut_or_mismatch($crate::__typeswitch!(@as_any_mut $subject))
+    };
+}
+
+/// The value moved out of a `Box<dyn Any>` subject as `Result<T, TypeMismatch>`,
+/// like [`downcast_or_bail!`] but by value.
+///
+/// The box is consumed, and dropped if it holds something else. To get it
+/// back instead, use a `try` switch.
+///
+/// ```rust
+/// # use typeswitch::{downcast_or_bail_owned, TypeMismatch};
+/// # use std::any::Any;
+/// fn name(x: Box<dyn Any>) -> Result<String, TypeMismatch> {
+///     let s: String = downcast_or_bail_owned!(x)?;
+///     Ok(s.to_uppercase())
+/// }
+/// assert_eq!(name(Box::new(String::from("ada"))).as_deref(), Ok("ADA"));
+/// assert!(name(Box::new(1i32)).is_err());
+/// ```
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! downcast_or_bail_owned {
+    ($subject:expr, $ty:ty $(,)?) => {
+        $crate::__private::downcast_owned_or_mismatch::<$ty>($subject)
+    };
+    ($subject:expr $(,)?) => {
+        $crate::__private::downcast_owned_or_mismatch($subject)
+    };
+}
+
+/// Names a closed set of types for [`typeswitch_set!`].
+///
+/// `define_any_set!(Name => [A, B, C])` defines a macro called `Name`, which
+/// holds the list. Like any `macro_rules!` macro it can be used below its
+/// definition, in the same module and the ones declared after it.
+///
+/// ```rust
+/// use typeswitch::{define_any_set, typeswitch_set};
+/// use std::any::Any;
+///
+/// struct Add(i32);
+/// struct Neg;
+/// struct Halt;
+///
+/// define_any_set!(Op => [Add, Neg, Halt]);
+///
+/// fn run(acc: i32, op: &dyn Any) -> Option<i32> {
+///     typeswitch_set!(Op, op {
+///         a: Add => Some(acc + a.0),
+///         Neg => Some(-acc),
+///         Halt => None,
+///     })
+/// }
+/// assert_eq!(run(2, &Add(3)), Some(5));
+/// assert_eq!(run(2, &Halt), None);
+/// ```
+#[macro_export]
+macro_rules! define_any_set {
+    ($name:ident => [$($ty:ty),* $(,)?]) => {
+        $crate::define_any_set!(@define ($) $name [$($ty),*]);
+    };
+
+    (@define ($d:tt) $name:ident [$($ty:ty),*]) => {
+        #[allow(unused_macros)]
+        macro_rules! $name {
+            ($d($d switch:tt)+) => {
+                $crate::__typeswitch!(@set [$($ty),*] [] $d($d switch)+)
+            };
+        }
+    };
+}
+
+/// A [`typeswitch!`] over a set from [`define_any_set!`] that must handle
+/// every member of the set, like a `match` on an enum.
+///
+/// Each member needs an arm of its own type without a guard, unless there is a
+/// default. A missing one is a compile error that names it. Arms for types
+/// outside the set are allowed, and `not (...)` arms count for nothing. The
+/// check only sees concrete types: a generic parameter never counts as a member.
+///
+/// Without a default, the switch is `exhaustive`: a subject of a type outside
+/// the set panics, or with `try` is handed back as `Err`.
+///
+/// ```rust,compile_fail
+/// # use typeswitch::{define_any_set, typeswitch_set};
+/// # use std::any::Any;
+/// define_any_set!(Num => [i32, f64]);
+///
+/// let x: &dyn Any = &1i32;
+/// typeswitch_set!(Num, x {
+///     i32 => println!("int"),  // error: `f64` is in the set, but no arm handles it
 /// });
 /// ```
 #[macro_export]
-macro_rules! typeswitch {
+macro_rules! typeswitch_set {
+    ($set:ident, $($switch:tt)+) => {
+        $set!($($switch)+)
+    };
+}
+
+/// Switches on a [`TypeId`](core::any::TypeId) rather than on a value.
+///
+/// For code that only has the id, such as a dispatch table keyed by it. The
+/// arms are those of [`typeswitch!`] minus everything that needs a value: a
+/// type or or-pattern, `not (...)`, or `_`, each with optional attributes and
+/// `fallthrough;`. There is nothing to bind, so arms have no bindings or guards.
+/// The id is any expression of type `TypeId`, evaluated once.
+///
+/// ```rust
+/// # use typeswitch::typeswitch_id;
+/// use std::any::TypeId;
+///
+/// fn kind(id: TypeId) -> &'static str {
+///     typeswitch_id!(id {
+///         i8 | i16 | i32 | i64 => "signed",
+///         String | &str => "text",
+///         _ => "other",
+///     })
+/// }
+/// assert_eq!(kind(TypeId::of::<i32>()), "signed");
+/// assert_eq!(kind(TypeId::of::<f64>()), "other");
+/// ```
+#[macro_export]
+macro_rules! typeswitch_id {
+    ($($rest:tt)+) => {
+        $crate::__typeswitch!(@id_subject [] $($rest)+)
+    };
+}
+
+/// The type an arm tested for, as written, for tagging logs and errors from
+/// inside the arm's block.
+///
+/// It works in the block of an arm that names its type, in any of the switch
+/// macros. An or-pattern arm that binds the subject gives all of its types
+/// (`"f32 | f64"`), `dyn Trait` arms give `"dyn Trait"`, and elsewhere, in
+/// a default, a `not (...)` or a tuple arm, there is no such type and it does
+/// not compile. The string is made at compile time, so this costs nothing.
+///
+/// ```rust
+/// # use typeswitch::typeswitch;
+/// # use std::any::Any;
+/// let x: &dyn Any = &String::from("config");
+///
+/// let err: Result<(), String> = typeswitch! { x {
+///     s: String => Err(format!("cannot parse {}: {s:?}", typeswitch::arm_type!())),
+///     _ => Ok(()),
+/// }};
+/// assert_eq!(err.unwrap_err(), "cannot parse String: \"config\"");
+/// ```
+#[macro_export]
+macro_rules! arm_type {
+    () => {
+        __TYPESWITCH_ARM_TYPE
+    };
+}
+
+/// The `macro_rules!` engine behind [`typeswitch!`]. Not public API.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __typeswitch {
     // ============================================================
     // ENTRY POINTS
     // ============================================================
 
     // 1. Pre-binding syntax: typeswitch!(v as x; ...)
     // This shadows 'v' inside the branches automatically.
-    ($bind:ident as $var:ident { $($rest:tt)* } ) => {{
-        $crate::typeswitch!(@step $var; $bind $($rest)*)
-    }};
-
-    // 2. Modified pre-binding syntax: typeswitch!(mut v as x; ...)
-    ($modifier:ident $bind:ident as $var:ident { $($rest:tt)* } ) => {{
-        $crate::typeswitch!(@step $var; $bind $modifier $($rest)*)
-    }};
+    ($bind:ident as $var:ident { $($rest:tt)* } ) => {
+        $crate::__typeswitch!(@entry [] $bind as $var { $($rest)* })
+    };
 
-    // 3. Standard syntax: typeswitch!(x; ...)
+    // 2. Standard syntax: typeswitch!(x; ...)
     // No automatic binding is applied unless explicitly stated in cases.
-    ($var:ident { $($rest:tt)* } ) => {{
-        $crate::typeswitch!(@step $var; $($rest)*)
-    }};
+    ($var:ident { $($rest:tt)* } ) => {
+        $crate::__typeswitch!(@entry [] $var { $($rest)* })
+    };
 
-    // ============================================================
-    // NORMALIZATION (Redistributors)
-    // ============================================================
+    // 3. Fallible syntax: typeswitch!(try x; ...)
+    // Evaluates to `Ok(value)` when an arm matched and hands the subject
+    // back as `Err(x)` when none did.
+    (try $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [try] $($rest)+)
+    };
 
-    // 1. Handle `_` explicitly before capturing it as $ty.
-    // This prevents `_` from turning into an opaque Type AST node.
-    (@step $var:expr; $auto:ident _ => $block:block $($rest:tt)*) => {
-        $crate::typeswitch!{@step $var; $auto : _ => $block $auto $($rest)*}
+    // 3b. Routing syntax: typeswitch!(route x { ... })
+    // A `try` switch whose `Result` becomes a `Routed`.
+    (route $subject:tt $($rest:tt)+) => {
+        match $crate::__typeswitch!(@entry [try] $subject $($rest)+) {
+            ::core::result::Result::Ok(value) => $crate::Routed::Matched(value),
+            ::core::result::Result::Err(subject) => $crate::Routed::Unmatched(subject),
+        }
     };
 
-    // 2. Handle `modifier _` explicitly as well
-    (@step $var:expr; $auto:ident $modifier:ident _ => $block:block $($rest:tt)*) => {
-        $crate::typeswitch!{@step $var; $modifier $auto : _ => $block $auto $($rest)*}
+    // 4. Indexed syntax: typeswitch!(indexed x { ... })
+    // Evaluates to `(value, index)`, see INDEXED SWITCHES below. Comes
+    // before 5., which would take `indexed` for a modifier.
+    (indexed $($rest:tt)+) => {
+        $crate::__typeswitch!(@indexed index [] $($rest)+)
     };
 
-    // FIXME: We think $modifier is what we added but it could be from this specific
-    // 3. Generic redistributor for modifiers (mut, box)
-    (@step $var:expr; $auto:ident $modifier:ident $ty:ty => $block:block $($rest:tt)*) => {
-        $crate::typeswitch!{@step $var; $modifier $auto : $ty => $block $auto $modifier $($rest)*}
+    // 5. TypeId syntax: typeswitch!(with_id x { ... })
+    // Evaluates to `(value, TypeId)`, see SUBJECT TYPE IDS below. Also
+    // ahead of the modifier rule.
+    (with_id $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id [] [] [] $($rest)+)
     };
 
-    // 4. Generic redistributor for standard types
-    (@step $var:expr; $auto:ident $ty:ty => $block:block $($rest:tt)*) => {
-        $crate::typeswitch!{@step $var; $auto : $ty => $block $auto $($rest)*}
+    // 6. Modified pre-binding syntax: typeswitch!(mut v as x; ...)
+    ($modifier:ident $bind:ident as $var:ident { $($rest:tt)* } ) => {
+        $crate::__typeswitch!(@entry [] $modifier $bind as $var { $($rest)* })
     };
 
-    // ============================================================
-    // ARM COLLECTION (The "Muncher")
-    // ============================================================
+    // 7. Mutable subject syntax: typeswitch!(mut x { ... })
+    // Every arm borrows mutably, exactly like `typeswitch_mut!(x { ... })`.
+    (mut $var:ident { $($rest:tt)* } ) => {
+        $crate::__typeswitch!(@fixed [mut] [] $var { $($rest)* })
+    };
+
+    // 8. Expression subjects: typeswitch!(make_box() { ... })
+    // See the catch-all at the very end of this macro.
 
     // ----------------------------------------------------------------
-    // PATTERN: _ => { ... } (Default case)
+    // Every entry point lands here with its mode in brackets.
     // ----------------------------------------------------------------
+    // typeswitch!(match x { ... }): look the type up once, see `@start`.
+    (@entry [$($mode:tt)*] match $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [$($mode)* match] $($rest)+)
+    };
 
-    // 1. Default case with modifier (e.g. mut v: _)
-    (@step $var:expr; $modifier:ident $auto:ident : _ => $block:block $($rest:tt)*) => {
-        $block
+    // typeswitch!(err_source e { ... }): search an error's `source()` chain.
+    (@entry [$($mode:tt)*] err_source $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [$($mode)* err_source] $subject $($rest)+)
     };
 
-    // 2. Default case standard (e.g. v: _)
-    (@step $var:expr; $auto:ident : _ => $block:block $($rest:tt)*) => {
-        $block
+    // typeswitch!(opt x { none => ..., ... }): switch on the inside of an `Option`.
+    (@entry [$($mode:tt)*] opt $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [$($mode)* opt] $subject $($rest)+)
     };
 
-    // 3. Default case no-binding (e.g. _ =>)
-    (@step $var:expr; _ => $block:block $($rest:tt)*) => {
-        $block
+    // typeswitch!(via_any x { ... }): switch on a trait object through `AsAny`.
+    (@entry [$($mode:tt)*] via_any $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [$($mode)* via_any] $subject $($rest)+)
     };
 
-    // 4.
-    // ----------------------------------------------------------------
-    // PATTERN: box binding: Type => { ... }
-    // Requirement: $var must be Box<dyn Any>
-    // ----------------------------------------------------------------
-    (@step $var:expr; box $bind:ident : $ty:ty => $block:block $($rest:tt)*) => {
-        // We check 'is' first to avoid consuming the box if the type doesn't match.
-        // If it does match, we unwrap.
-        if $var.is::<$ty>() {
-            // We must cast to the concrete type.
-            // Note: downcast returns Result<Box<T>, Box<dyn Any>>
-            let $bind = *$var.downcast::<$ty>().expect("typeswitch: type check passed but downcast failed");
-            $block
-        } else {
-            $crate::typeswitch!{@step $var; $($rest)*}
-        }
+    // typeswitch!(all x { ... }): run every arm that matches, see ALL MODE.
+    (@entry [$($mode:tt)*] all $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@all_head [$($mode)*] [] $subject $($rest)+)
     };
 
-    // 5.
-    // ----------------------------------------------------------------
-    // PATTERN: mut binding @ Type => { ... }
-    // Requirement: $var must be &mut dyn Any (or Box)
-    // ----------------------------------------------------------------
-    (@step $var:expr; mut $bind:ident : $ty:ty => $block:block $($rest:tt)*) => {
-        if let Some($bind) = <dyn std::any::Any>::downcast_mut::<$ty>(&mut *$var) {
-            $block
-        } else {
-            $crate::typeswitch!{@step $var; $($rest)*}
-        }
+    // typeswitch!(flow x { ... }): arms are `ControlFlow`s, see FLOW SWITCHES.
+    (@entry [$($mode:tt)*] flow $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@flow [$($mode)*] [] $subject $($rest)+)
     };
 
-    // 6.
-    // ----------------------------------------------------------------
-    // PATTERN: binding: Type => { ... }
-    // Requirement: $var must be &dyn Any (or &mut/Box)
-    // ----------------------------------------------------------------
-    (@step $var:expr; $bind:ident : $ty:ty => $block:block $($rest:tt)*) => {
-        if let Some($bind) = <dyn std::any::Any>::downcast_ref::<$ty>(&*$var) {
-            $block
-        } else {
-            $crate::typeswitch!{@step $var; $($rest)*}
-        }
+    // typeswitch!(cell x { ... }): switch on the value in a `RefCell`, see CELL SUBJECTS.
+    (@entry [$($mode:tt)*] cell $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@cell [$($mode)*] $subject $($rest)+)
     };
 
-    // 7.
-    // ----------------------------------------------------------------
-    // PATTERN: Type => { ... } (No binding, just check)
-    // ----------------------------------------------------------------
-    (@step $var:expr; $ty:ty => $block:block $($rest:tt)*) => {
-        if <dyn std::any::Any>::is::<$ty>(&*$var as _) {
-            $block
-        } else {
-            $crate::typeswitch!{@step $var; $($rest)*}
-        }
+    // typeswitch!(exhaustive x { ... }): panic instead of yielding `()`.
+    // It needs a subject after it, so a variable named `exhaustive` still works.
+    (@entry [$($mode:tt)*] exhaustive $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [$($mode)* exhaustive] $subject $($rest)+)
     };
 
-    // 8.
-    // ----------------------------------------------------------------
-    // PATTERN: Type | Type => { ... } (Or pattern)
-    // ----------------------------------------------------------------
-    (@step $var:expr; $head:ty | $($tail:ty)|+ => $block:block $($rest:tt)*) => {
-        if <dyn std::any::Any>::is::<$head>(&$var as _) $(|| <dyn std::any::Any>::is::<$tail>(&* $var))+ {
-            $block
-        } else {
-            $crate::typeswitch!{@step $var; $($rest)*}
-        }
+    // typeswitch!(strict x { ... }): another name for `exhaustive`. Neither
+    // name is in the panic message, so it reads the same for both.
+    (@entry [$($mode:tt)*] strict $subject:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [$($mode)* exhaustive] $subject $($rest)+)
     };
 
-    // TODO: Support attributes on arms..
+    // typeswitch!(&x { ... }), (&mut x { ... }), (box x { ... }): the subject
+    // sets how every arm takes the value, like `typeswitch_ref!`,
+    // `typeswitch_mut!` and `typeswitch_owned!`. The modes so far go ahead
+    // of the subject again. Any other `&expr` is an expression subject, and
+    // so is the reborrow passed on, which `try` hands back unchanged.
+    (@entry [$($mode:tt)*] & mut $var:ident { $($rest:tt)* }) => {
+        $crate::__typeswitch!(@fixed [mut] [$($mode)*] &mut *&mut $var { $($rest)* })
+    };
 
-    // ----------------------------------------------------------------
-    // Base Case: No more patterns
-    // ----------------------------------------------------------------
-    (@step $var:expr;) => {};
+    (@entry [$($mode:tt)*] & $var:ident { $($rest:tt)* }) => {
+        $crate::__typeswitch!(@fixed [] [$($mode)*] &*&$var { $($rest)* })
+    };
 
-    // ----------------------------------------------------------------
-    // Base Case: No more patterns auto
-    // ----------------------------------------------------------------
-    (@step $var:expr; $auto:ident) => {};
-}
+    (@entry [$($mode:tt)*] box $var:ident { $($rest:tt)* }) => {
+        $crate::__typeswitch!(@fixed [box] [$($mode)*] $var { $($rest)* })
+    };
 
-#[cfg(test)]
-mod tests {
-    use std::any::Any;
+    (@entry [$($mode:tt)*] $bind:ident as & mut $var:ident { $($rest:tt)* }) => {
+        $crate::__typeswitch!(@fixed [mut] [$($mode)*] $bind as &mut *&mut $var { $($rest)* })
+    };
+
+    (@entry [$($mode:tt)*] $bind:ident as & $var:ident { $($rest:tt)* }) => {
+        $crate::__typeswitch!(@fixed [] [$($mode)*] $bind as &*&$var { $($rest)* })
+    };
+
+    (@entry [$($mode:tt)*] $bind:ident as box $var:ident { $($rest:tt)* }) => {
+        $crate::__typeswitch!(@fixed [box] [$($mode)*] $bind as $var { $($rest)* })
+    };
+
+    // typeswitch!((a, b) { (i32, String) => ..., ... }): see TUPLE SUBJECTS.
+    (@entry $mode:tt ($($subjects:tt)*) { $($rest:tt)* }) => {{
+        $crate::__typeswitch!(@tuple $mode [] [$($subjects)*] { $($rest)* })
+    }};
+
+    (@entry [$($mode:tt)*] $bind:ident as $var:ident { $($rest:tt)* } ) => {{
+        $crate::__typeswitch!(@start $var; [$($mode)*] [$bind] [] $($rest)*)
+    }};
+
+    (@entry [$($mode:tt)*] $modifier:ident $bind:ident as $var:ident { $($rest:tt)* } ) => {{
+        $crate::__typeswitch!(@start $var; [$($mode)*] [$bind $modifier] [] $($rest)*)
+    }};
+
+    (@entry [$($mode:tt)*] $var:ident { $($rest:tt)* } ) => {{
+        $crate::__typeswitch!(@start $var; [$($mode)*] [] [] $($rest)*)
+    }};
+
+    // Anything else is an expression: munch it up to the trailing `{ ... }`.
+    (@entry [$($mode:tt)*] $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@subject [$($mode)*] [$bind] [] $($rest)+)
+    };
+
+    (@entry [$($mode:tt)*] $modifier:ident $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@subject [$($mode)*] [$bind $modifier] [] $($rest)+)
+    };
+
+    (@entry [$($mode:tt)*] $($rest:tt)+) => {
+        $crate::__typeswitch!(@subject [$($mode)*] [] [] $($rest)+)
+    };
+
+    // The value is bound by a `match` rather than a `let`, so temporaries
+    // in the subject expression live until the switch is done. It is bound
+    // `mut` so that `mut` and `box` arms can use it.
+    (@subject [$($mode:tt)*] [$($auto:tt)*] [$($subject:tt)+] { $($rest:tt)* }) => {
+        match $($subject)+ {
+            #[allow(unused_mut)]
+            mut subject => $crate::__typeswitch!(@start subject; [$($mode)*] [$($auto)*] [] $($rest)*),
+        }
+    };
+
+    // `subject -> Type { ... }`: the switch is evaluated into a local of that
+    // type, so every arm is inferred and coerced against it. Without the
+    // `-> Type`, the head goes back through the entry rules as it was.
+    (@subject [$($mode:tt)*] [] [$($subject:tt)+] -> $ret:ty { $($rest:tt)* }) => {{
+        let value: $crate::__typeswitch!(@ret_ty [$($mode)*] $ret) = $crate::__typeswitch!($($mode)* $($subject)+ { $($rest)* });
+        value
+    }};
+
+    (@subject [$($mode:tt)*] [$bind:ident] [$($subject:tt)+] -> $ret:ty { $($rest:tt)* }) => {{
+        let value: $crate::__typeswitch!(@ret_ty [$($mode)*] $ret) = $crate::__typeswitch!($($mode)* $bind as $($subject)+ { $($rest)* });
+        value
+    }};
+
+    (@subject [$($mode:tt)*] [$bind:ident $modifier:ident] [$($subject:tt)+] -> $ret:ty { $($rest:tt)* }) => {{
+        let value: $crate::__typeswitch!(@ret_ty [$($mode)*] $ret) = $crate::__typeswitch!($($mode)* $modifier $bind as $($subject)+ { $($rest)* });
+        value
+    }};
+
+    // `subject { ... } @types NAME`: also defines `const NAME: &[&str]`, the
+    // types the arms name, next to the switch. See ARM TYPE LISTS.
+    (@subject [$($mode:tt)*] [] [$($subject:tt)+] { $($arms:tt)* } @types $name:ident) => {
+        const $name: &[&str] = $crate::__typeswitch!(@types [list] [] $($arms)*);
+        $crate::__typeswitch!($($mode)* $($subject)+ { $($arms)* })
+    };
+
+    (@subject [$($mode:tt)*] [$bind:ident] [$($subject:tt)+] { $($arms:tt)* } @types $name:ident) => {
+        const $name: &[&str] = $crate::__typeswitch!(@types [list] [] $($arms)*);
+        $crate::__typeswitch!($($mode)* $bind as $($subject)+ { $($arms)* })
+    };
+
+    (@subject [$($mode:tt)*] [$bind:ident $modifier:ident] [$($subject:tt)+] { $($arms:tt)* } @types $name:ident) => {
+        const $name: &[&str] = $crate::__typeswitch!(@types [list] [] $($arms)*);
+        $crate::__typeswitch!($($mode)* $modifier $bind as $($subject)+ { $($arms)* })
+    };
+
+    (@subject [$($mode:tt)*] [$($auto:tt)*] [$($subject:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@subject [$($mode)*] [$($auto)*] [$($subject)* $next] $($rest)*)
+    };
+
+    (@subject [$($mode:tt)*] [$($auto:tt)*] [$($subject:tt)*]) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after the subject `", stringify!($($subject)*), "`"))
+    };
+
+    // The type of the whole switch for an arm type of `$ret`.
+    (@ret_ty [try $($mode:tt)*] $ret:ty) => { ::core::result::Result<$ret, _> };
+    (@ret_ty [$first:tt $($mode:tt)*] $ret:ty) => { $crate::__typeswitch!(@ret_ty [$($mode)*] $ret) };
+    (@ret_ty [] $ret:ty) => { $ret };
+
+    // ============================================================
+    // TUPLE SUBJECTS
+    // Each subject gets its own name, then every arm is a tuple of
+    // positions, one per subject: `_`, `Type`, `name: Type` or
+    // `mut name: Type`. The positions of an arm are checked left to
+    // right with `&&`, so a subject is only looked at once every
+    // subject before it has matched.
+    // ============================================================
+
+    // Variables are used as they are, anything else is bound like `@subject` does.
+    (@tuple $mode:tt [$($var:ident)*] [$next:ident $(, $($rest:tt)*)?] $arms:tt) => {
+        $crate::__typeswitch!(@tuple $mode [$($var)* $next] [$($($rest)*)?] $arms)
+    };
+
+    (@tuple $mode:tt [$($var:ident)*] [$next:expr $(, $($rest:tt)*)?] $arms:tt) => {
+        match $next {
+            #[allow(unused_mut)]
+            mut subject => $crate::__typeswitch!(@tuple $mode [$($var)* subject] [$($($rest)*)?] $arms),
+        }
+    };
+
+    (@tuple $mode:tt $vars:tt [] {}) => {
+        compile_error!("typeswitch: at least one arm required")
+    };
+
+    // `(x) { ... }` is just a parenthesized subject.
+    (@tuple [$($mode:tt)*] [$var:ident] [] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@start $var; [$($mode)*] [] [] $($arms)*)
+    };
+
+    (@tuple [] [$($var:ident)+] [] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@tuple_step [$($var)+] [] [] $($arms)*)
+    };
+
+    (@tuple [try] [$($var:ident)+] [] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@tuple_step [$($var)+] [try] [] $($arms)*)
+    };
+
+    (@tuple [exhaustive] [$($var:ident)+] [] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@tuple_step [$($var)+] [exhaustive] [] $($arms)*)
+    };
+
+    (@tuple [$($mode:tt)*] [] [] $arms:tt) => {
+        compile_error!("typeswitch: expected a subject inside `( ... )`")
+    };
+
+    (@tuple [$($mode:tt)*] [$($var:ident)+] [] $arms:tt) => {
+        compile_error!(concat!("typeswitch: tuple subjects only support the `try` and `exhaustive` modes, not `", stringify!($($mode)*), "`"))
+    };
+
+    // Arms: `#[attrs] (positions) [if guard] => body`, then `_ => body`.
+    (@tuple_step $vars:tt $mode:tt [$($attrs:tt)*] #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@tuple_step $vars $mode [$($attrs)* #[$($attr)*]] $($rest)*)
+    };
+
+    (@tuple_step $vars:tt $mode:tt [] , $($rest:tt)*) => {
+        $crate::__typeswitch!(@tuple_step $vars $mode [] $($rest)*)
+    };
+
+    (@tuple_step $vars:tt $mode:tt [] ; $($rest:tt)*) => {
+        $crate::__typeswitch!(@tuple_step $vars $mode [] $($rest)*)
+    };
+
+    (@tuple_step $vars:tt $mode:tt [] _ => $block:block $(,)?) => {
+        $crate::__typeswitch!(@wrap $mode $block)
+    };
+
+    (@tuple_step $vars:tt $mode:tt [] _ => $block:block ;) => {
+        $crate::__typeswitch!(@wrap $mode $block)
+    };
+
+    (@tuple_step $vars:tt $mode:tt [] _ => $e:expr $(,)?) => {
+        $crate::__typeswitch!(@wrap $mode { $e })
+    };
+
+    (@tuple_step $vars:tt $mode:tt [$($attrs:tt)*] ($($pos:tt)*) $(if $guard:expr)? => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@tuple_cond $vars $vars $mode [$($attrs)*] [true] [$($pos)*] [$(&& $guard)?] { $($body)* } $($rest)*}
+    };
+
+    (@tuple_step $vars:tt $mode:tt [$($attrs:tt)*] ($($pos:tt)*) $(if $guard:expr)? => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@tuple_step $vars $mode [$($attrs)*] ($($pos)*) $(if $guard)? => { $e } $($($rest)*)?}
+    };
+
+    (@tuple_step [$($var:ident)+] [] []) => { () };
+
+    (@tuple_step [$($var:ident)+] [try] []) => {
+        ::core::result::Result::Err(($($var),+))
+    };
+
+    (@tuple_step [$($var:ident)+] [exhaustive] []) => {
+        ::core::panic!("typeswitch: no arm matched the subjects' types")
+    };
+
+    (@tuple_step $vars:tt $mode:tt [$($attrs:tt)*] $($rest:tt)+) => {
+        compile_error!(concat!("typeswitch: expected `(Type, ...) => ...` or `_ => ...`, found `", stringify!($($rest)+), "`"))
+    };
+
+    // One position per subject, each adding its check to the condition.
+    // The full list of subjects rides along for the arms after this one.
+    (@tuple_cond $vars:tt [$var:ident $($left:ident)*] $mode:tt $attrs:tt [$($cond:tt)*] [_ $(, $($pos:tt)*)?] $($rest:tt)*) => {
+        $crate::__typeswitch!{@tuple_cond $vars [$($left)*] $mode $attrs [$($cond)*] [$($($pos)*)?] $($rest)*}
+    };
+
+    (@tuple_cond $vars:tt [$var:ident $($left:ident)*] $mode:tt $attrs:tt [$($cond:tt)*] [mut $bind:ident : $ty:ty $(, $($pos:tt)*)?] $($rest:tt)*) => {
+        $crate::__typeswitch!{@tuple_cond $vars [$($left)*] $mode $attrs
+            [$($cond)* && let ::core::option::Option::Some($bind) = $crate::downcast_mut_any::<$ty>($crate::__typeswitch!(@as_any_mut $var))]
+            [$($($pos)*)?] $($rest)*}
+    };
+
+    (@tuple_cond $vars:tt [$var:ident $($left:ident)*] $mode:tt $attrs:tt [$($cond:tt)*] [$bind:ident : $ty:ty $(, $($pos:tt)*)?] $($rest:tt)*) => {
+        $crate::__typeswitch!{@tuple_cond $vars [$($left)*] $mode $attrs
+            [$($cond)* && let ::core::option::Option::Some($bind) = $crate::downcast_ref_any::<$ty>($crate::__typeswitch!(@as_any $var))]
+            [$($($pos)*)?] $($rest)*}
+    };
+
+    (@tuple_cond $vars:tt [$var:ident $($left:ident)*] $mode:tt $attrs:tt [$($cond:tt)*] [$ty:ty $(, $($pos:tt)*)?] $($rest:tt)*) => {
+        $crate::__typeswitch!{@tuple_cond $vars [$($left)*] $mode $attrs
+            [$($cond)* && <dyn ::core::any::Any>::is::<$ty>($crate::__typeswitch!(@as_any $var))]
+            [$($($pos)*)?] $($rest)*}
+    };
+
+    (@tuple_cond $vars:tt [] $mode:tt $attrs:tt [$($cond:tt)*] [] [$($guard:tt)*] { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode $attrs [$($cond)* $($guard)*] [] { $($body)* }
+            {$crate::__typeswitch!{@tuple_step $vars $mode [] $($rest)*}}
+        }
+    };
+
+    (@tuple_cond $vars:tt [] $mode:tt $attrs:tt $cond:tt [$($pos:tt)+] $($rest:tt)*) => {
+        compile_error!(concat!("typeswitch: more positions than subjects at `", stringify!($($pos)+), "`"))
+    };
+
+    (@tuple_cond $vars:tt [$($left:ident)+] $mode:tt $attrs:tt $cond:tt [] $($rest:tt)*) => {
+        compile_error!("typeswitch: an arm has fewer positions than there are subjects")
+    };
+
+    // ============================================================
+    // MODES
+    // `@wrap` shapes the value of a matched arm and the base case
+    // decides what happens when nothing matched. Once `@start` has run,
+    // the mode is `[$(match id | err_source)? $(try | exhaustive)?]`.
+    // ============================================================
+    (@wrap [$(match $id:tt)? $(err_source)? try] $block:block) => {
+        ::core::result::Result::Ok($block)
+    };
+
+    (@wrap [$(match $id:tt)? $(err_source)? $(exhaustive)?] $block:block) => {
+        $block
+    };
+
+    // The trait object the subject is seen as, for bindings of the subject itself.
+    (@dyn [err_source $($mode:tt)*]) => { dyn ::core::error::Error };
+    (@dyn [$($mode:tt)*]) => { dyn ::core::any::Any };
+
+    // One arm: `[attrs] [condition] [statements run before the block] block {rest}`.
+    // An arm whose block starts with `fallthrough;` runs like a statement
+    // and then carries on with the remaining arms instead of stopping.
+    (@arm $mode:tt [$($attrs:tt)*] [$($cond:tt)+] [$($prelude:tt)*] { fallthrough; $($body:tt)* } $next:tt) => {{
+        match () {
+            $($attrs)*
+            () if $($cond)+ => { $($prelude)* $($body)* }
+            _ => {}
+        }
+        $next
+    }};
+
+    // A `switch { ... }` body is already a switch in this mode; wrapping it
+    // again would wrap its value twice.
+    (@arm $mode:tt [$($attrs:tt)*] [$($cond:tt)+] [] { @resume $($switch:tt)* } $next:tt) => {
+        match () {
+            $($attrs)*
+            () if $($cond)+ => { $($switch)* }
+            _ => $next,
+        }
+    };
+
+    (@arm $mode:tt [$($attrs:tt)*] [$($cond:tt)+] [$($prelude:tt)*] $block:block $next:tt) => {
+        match () {
+            $($attrs)*
+            () if $($cond)+ => { $($prelude)* $crate::__typeswitch!{@wrap $mode $block} }
+            _ => $next,
+        }
+    };
+
+    // Put in front of an arm's block for `arm_type!()` to read. An inner
+    // switch's arm shadows it.
+    (@arm_type $($ty:tt)+) => {
+        #[allow(dead_code)]
+        const __TYPESWITCH_ARM_TYPE: &str = ::core::stringify!($($ty)+);
+    };
+
+    // An empty switch would quietly do nothing. A lone `_ =>` is still fine.
+    (@start $var:expr; $mode:tt $auto:tt []) => {
+        compile_error!("typeswitch: at least one arm required")
+    };
+
+    // `opt` unwraps the subject before anything else happens. `None` runs
+    // the leading `none` arm if there is one, and otherwise counts as no
+    // arm matching. `Some` switches on the inner value with the remaining
+    // modes; under `try` the unmatched value comes back as `Err(Some(..))`.
+    (@start $var:expr; [$first:ident opt $($mode:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [opt $first $($mode)*] $($rest)*)
+    };
+
+    (@start $var:expr; [$first:ident $second:ident opt $($mode:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [opt $first $second $($mode)*] $($rest)*)
+    };
+
+    (@start $var:expr; [opt $($mode:tt)*] [$($auto:tt)*] [] none => $block:block $($rest:tt)*) => {
+        match $var {
+            ::core::option::Option::None => $crate::__typeswitch!(@none [$($mode)*] $block),
+            #[allow(unused_mut)]
+            ::core::option::Option::Some(mut inner) => $crate::__typeswitch!(@some inner; [$($mode)*] [$($auto)*] $($rest)*),
+        }
+    };
+
+    (@start $var:expr; [opt $($mode:tt)*] [$($auto:tt)*] [] none => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@start $var; [opt $($mode)*] [$($auto)*] [] none => { $e } $($($rest)*)?)
+    };
+
+    (@start $var:expr; [opt $($mode:tt)*] [$($auto:tt)*] [] $($rest:tt)*) => {
+        match $var {
+            ::core::option::Option::None => $crate::__typeswitch!(@none [$($mode)*]),
+            #[allow(unused_mut)]
+            ::core::option::Option::Some(mut inner) => $crate::__typeswitch!(@some inner; [$($mode)*] [$($auto)*] $($rest)*),
+        }
+    };
+
+    // `via_any` comes after `opt`: the subject is wrapped in a `ViaAny`, which
+    // derefs to `dyn Any` through `AsAny`, so every arm works unchanged.
+    // Under `try` the wrapper is taken off again before it is handed back.
+    (@start $var:expr; [$first:ident via_any $($mode:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [via_any $first $($mode)*] $($rest)*)
+    };
+
+    (@start $var:expr; [$first:ident $second:ident via_any $($mode:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [via_any $first $second $($mode)*] $($rest)*)
+    };
+
+    (@start $var:expr; [$first:ident $second:ident $third:ident via_any $($mode:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [via_any $first $second $third $($mode)*] $($rest)*)
+    };
+
+    (@start $var:expr; [via_any try $($mode:tt)*] $($rest:tt)*) => {
+        ::core::result::Result::map_err(
+            match $crate::__private::ViaAny($var) {
+                #[allow(unused_mut)]
+                mut subject => $crate::__typeswitch!(@start subject; [try $($mode)*] $($rest)*),
+            },
+            $crate::__private::ViaAny::into_inner,
+        )
+    };
+
+    (@start $var:expr; [via_any $($mode:tt)*] $($rest:tt)*) => {
+        match $crate::__private::ViaAny($var) {
+            #[allow(unused_mut)]
+            mut subject => $crate::__typeswitch!(@start subject; [$($mode)*] $($rest)*),
+        }
+    };
+
+    // `match` mode reads the subject's `TypeId` once, up front. Each arm
+    // then compares it against a constant instead of asking the vtable
+    // again. The id is carried in the mode so every arm sees the same
+    // binding despite hygiene.
+    (@start $var:expr; [try $(match)? $(err_source)? exhaustive $(match)? $(err_source)?] $($rest:tt)*) => {
+        compile_error!("typeswitch: `try` cannot be combined with `exhaustive` or `strict`")
+    };
+
+    (@start $var:expr; [$(try)? $(exhaustive)? match $(exhaustive)? err_source $(exhaustive)?] $($rest:tt)*) => {
+        compile_error!("typeswitch: `match` and `err_source` cannot be combined")
+    };
+
+    (@start $var:expr; [$(try)? $(exhaustive)? err_source $(exhaustive)? match $(exhaustive)?] $($rest:tt)*) => {
+        compile_error!("typeswitch: `match` and `err_source` cannot be combined")
+    };
+
+    (@start $var:expr; [err_source $($flag:ident)?] $($rest:tt)*) => {
+        $crate::__typeswitch!(@step $var; [err_source $($flag)?] $($rest)*)
+    };
+
+    (@start $var:expr; [$flag:ident err_source] $($rest:tt)*) => {
+        $crate::__typeswitch!(@step $var; [err_source $flag] $($rest)*)
+    };
+
+    (@start $var:expr; [match $($flag:ident)?] $($rest:tt)*) => {{
+        // Unused when every arm is a default or has a `via` extractor.
+        #[allow(unused_variables)]
+        let type_id = <dyn ::core::any::Any>::type_id($crate::__typeswitch!(@as_any $var));
+        $crate::__typeswitch!(@step $var; [match type_id $($flag)?] $($rest)*)
+    }};
+
+    (@start $var:expr; [$flag:ident match] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [match $flag] $($rest)*)
+    };
+
+    (@start $var:expr; [$($mode:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@step $var; [$($mode)*] $($rest)*)
+    };
+
+    // The `None` side of `opt`, before `@start` has seen the other modes.
+    // There is nothing for `via_any` to wrap.
+    (@none [via_any $($mode:tt)*] $($block:block)?) => { $crate::__typeswitch!(@none [$($mode)*] $($block)?) };
+    (@none [$first:ident via_any $($mode:tt)*] $($block:block)?) => { $crate::__typeswitch!(@none [$first $($mode)*] $($block)?) };
+    (@none [$first:ident $second:ident via_any $($mode:tt)*] $($block:block)?) => {
+        $crate::__typeswitch!(@none [$first $second $($mode)*] $($block)?)
+    };
+    (@none [try $($mode:tt)*] $block:block) => { ::core::result::Result::Ok($block) };
+    (@none [try $($mode:tt)*]) => { ::core::result::Result::Err(::core::option::Option::None) };
+    (@none [$($mode:tt)*] $block:block) => { $block };
+    (@none [$(match)? $(err_source)? exhaustive $(match)? $(err_source)?]) => {
+        ::core::panic!("typeswitch: no arm matched, the `opt` subject was `None`")
+    };
+    (@none [$($mode:tt)*]) => { () };
+
+    // The `Some` side of `opt`.
+    (@some $var:ident; [try $($mode:tt)*] [$($auto:tt)*] $($rest:tt)*) => {
+        ::core::result::Result::map_err(
+            $crate::__typeswitch!(@start $var; [try $($mode)*] [$($auto)*] [] $($rest)*),
+            ::core::option::Option::Some,
+        )
+    };
+
+    (@some $var:ident; [$($mode:tt)*] [$($auto:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@start $var; [$($mode)*] [$($auto)*] [] $($rest)*)
+    };
+
+    // ============================================================
+    // TYPE CHECKS
+    // Every arm asks through `@is` (bool), `@ref` (Option<&T>) or
+    // `@mut` (Option<&mut T>), so that the mode decides how the
+    // type is looked up.
+    //
+    // `@as_any` and `@as_any_mut` find the `dyn Any` behind the subject.
+    // Plain `&*$var` would be wrong for a `&mut Box<dyn Any>`, where the
+    // `&Box` itself coerces to `&dyn Any`. See `__private::Probe`.
+    // ============================================================
+    (@as_any $var:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::{CoerceKind as _, DirectKind as _, PointerKind as _};
+        (&&&$crate::__private::Probe(&*$var)).kind().any_ref(&*$var)
+    }};
+
+    (@as_any_mut $var:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::{CoerceKind as _, DirectKind as _, PointerKind as _};
+        (&&&$crate::__private::Probe(&*$var)).kind().any_mut(&mut *$var)
+    }};
+
+    // The subject as the `@dyn` of its mode.
+    (@as_dyn [err_source $($mode:tt)*] $var:expr) => { &*$var };
+    (@as_dyn $mode:tt $var:expr) => { $crate::__typeswitch!(@as_any $var) };
+
+    (@is [match $id:tt $($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!($var, Some($id), [$($ty)+] $id == ::core::any::TypeId::of::<$($ty)+>())
+    };
+
+    // `err_source` tries each level of the chain in turn, see `find_source`.
+    (@is [err_source $($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!($var, None, [$($ty)+] $crate::__private::find_source::<$($ty)+>(&*$var).is_some())
+    };
+
+    (@is [$($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!(
+            $var,
+            Some(<dyn ::core::any::Any>::type_id($crate::__typeswitch!(@as_any $var))),
+            [$($ty)+] <dyn ::core::any::Any>::is::<$($ty)+>($crate::__typeswitch!(@as_any $var))
+        )
+    };
+
+    // Whether the subject is any of the types, for or-patterns and `not`. The
+    // subject is coerced once, and the checks stop at the first hit.
+    (@is_any [match $id:tt $($mode:tt)*] $var:expr; $($ty:ty),+) => {
+        ($($crate::__typeswitch_trace!($var, Some($id), [$ty] $id == ::core::any::TypeId::of::<$ty>()))||+)
+    };
+
+    (@is_any [err_source $($mode:tt)*] $var:expr; $($ty:ty),+) => {
+        ($($crate::__typeswitch_trace!($var, None, [$ty] $crate::__private::find_source::<$ty>(&*$var).is_some()))||+)
+    };
+
+    (@is_any [$($mode:tt)*] $var:expr; $($ty:ty),+) => {{
+        let __any: &dyn ::core::any::Any = $crate::__typeswitch!(@as_any $var);
+        $($crate::__typeswitch_trace!($var, Some(<dyn ::core::any::Any>::type_id(__any)), [$ty] __any.is::<$ty>()))||+
+    }};
+
+    (@ref [match $id:tt $($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!($var, Some($id), [$($ty)+] if $id == ::core::any::TypeId::of::<$($ty)+>() {
+            $crate::downcast_ref_any::<$($ty)+>($crate::__typeswitch!(@as_any $var))
+        } else {
+            ::core::option::Option::None
+        })
+    };
+
+    (@ref [err_source $($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!($var, None, [$($ty)+] $crate::__private::find_source::<$($ty)+>(&*$var))
+    };
+
+    (@ref [$($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!(
+            $var,
+            Some(<dyn ::core::any::Any>::type_id($crate::__typeswitch!(@as_any $var))),
+            [$($ty)+] $crate::downcast_ref_any::<$($ty)+>($crate::__typeswitch!(@as_any $var))
+        )
+    };
+
+    // Moves the value out of a `box` arm's subject once `@is` has held.
+    // `match` reads the id before any arm runs, and an arm that falls
+    // through may have replaced the subject since, so it checks again.
+    (@take [match $id:tt $($mode:tt)*] $var:expr; $ty:ty) => {
+        $crate::__private::BoxSubject::take::<$ty>($var)
+    };
+
+    (@take [$($mode:tt)*] $var:expr; $ty:ty) => {
+        // SAFETY: the arm's condition was `@is` on this same subject, and
+        // nothing runs between it and this line.
+        unsafe { $crate::__private::BoxSubject::take_unchecked::<$ty>($var) }
+    };
+
+    // The same for `arc` and `rc` arms, which end up with an `Arc<T>` or `Rc<T>`.
+    (@take_shared [match $id:tt $($mode:tt)*] $var:expr; $ty:ty) => {
+        $var.downcast::<$ty>().expect("typeswitch: type check passed but downcast failed")
+    };
+
+    (@take_shared [$($mode:tt)*] $var:expr; $ty:ty) => {
+        // SAFETY: as for `@take`.
+        unsafe { $crate::__private::SharedSubject::cast_unchecked::<$ty>($var) }
+    };
+
+    // The same for `replace` arms, which leave a `()` in the box meanwhile.
+    // The arm brings `BoxSlot` into scope.
+    (@replace_take [match $id:tt $($mode:tt)*] $var:expr; $ty:ty) => {
+        $var.replace_take::<$ty>()
+    };
+
+    (@replace_take [$($mode:tt)*] $var:expr; $ty:ty) => {
+        // SAFETY: as for `@take`.
+        unsafe { $var.replace_take_unchecked::<$ty>() }
+    };
+
+    (@mut [match $id:tt $($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!($var, Some($id), [$($ty)+] if $id == ::core::any::TypeId::of::<$($ty)+>() {
+            $crate::downcast_mut_any::<$($ty)+>($crate::__typeswitch!(@as_any_mut $var))
+        } else {
+            ::core::option::Option::None
+        })
+    };
+
+    (@mut [$($mode:tt)*] $var:expr; $($ty:tt)+) => {
+        $crate::__typeswitch_trace!(
+            $var,
+            Some(<dyn ::core::any::Any>::type_id($crate::__typeswitch!(@as_any $var))),
+            [$($ty)+] $crate::downcast_mut_any::<$($ty)+>($crate::__typeswitch!(@as_any_mut $var))
+        )
+    };
+
+    // ============================================================
+    // ARM COLLECTION (The "Muncher")
+    //
+    // Every `@step` carries the auto-binding context in brackets:
+    // `[]` for the standard syntax, `[v]` or `[v mut]` for pre-binding,
+    // followed by the attributes collected for the current arm.
+    //
+    // Each arm expands, through `@arm`, to
+    // `match () { #[attrs] () if <downcast> => .., _ => <rest> }`.
+    // Attributes land on the first match arm, so an arm that is
+    // `#[cfg]`'d out simply disappears and control falls to `<rest>`.
+    // ============================================================
+
+    // ----------------------------------------------------------------
+    // SEPARATORS: a block body may be followed by `,` or `;`, as in a
+    // `match`. The arm rules leave it in place and it is dropped here.
+    // ----------------------------------------------------------------
+    (@step $var:expr; $mode:tt $auto:tt [] , $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt [] ; $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}
+    };
+
+    // ----------------------------------------------------------------
+    // ATTRIBUTES: #[...] before any arm
+    // ----------------------------------------------------------------
+    // Kept as raw tokens so an attributed default can still pick out `cfg`.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)* #[$($attr)*]] $($rest)*}
+    };
+
+    // ----------------------------------------------------------------
+    // EXPLICIT BORROW FORMS: `ref`, `ref mut` and `move` spell out what
+    // the plain, `mut` and `box` arms do, and are rewritten into them.
+    // ----------------------------------------------------------------
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt ref mut $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs mut $bind : $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt ref $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $bind : $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt move $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs box $bind : $($rest)*}
+    };
+
+    // ----------------------------------------------------------------
+    // GROUPED TYPES: `v: (impl Trait) for [A, B] => { ... }` is one arm per
+    // type, each with the block and `v` bound as that type. The types are
+    // taken off the front one at a time, each leaving the rest as the same
+    // kind of arm, so attributes and guards apply to every one of them.
+    // ----------------------------------------------------------------
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $kind:ident $bind:ident : (impl $($bound:tt)+) for $($rest:tt)+) => {
+        $crate::__typeswitch!{@for_types [$var; $mode $auto $attrs] [$kind $bind] [$($bound)+] $($rest)+}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $bind:ident : (impl $($bound:tt)+) for $($rest:tt)+) => {
+        $crate::__typeswitch!{@for_types [$var; $mode $auto $attrs] [$bind] [$($bound)+] $($rest)+}
+    };
+
+    // An error's sources can only be borrowed, and only shared.
+    (@step $var:expr; [err_source $($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] $bind:ident : dyn $($rest:tt)*) => {
+        compile_error!("typeswitch: `dyn Trait` arms are not supported with `err_source`")
+    };
+
+    (@step $var:expr; [err_source $($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] $kind:ident $bind:ident : $($rest:tt)*) => {
+        compile_error!(concat!("typeswitch: `", stringify!($kind), "` arms are not supported with `err_source`"))
+    };
+
+    // ----------------------------------------------------------------
+    // PATTERN: _ => { ... } (Default case)
+    //          _ @ name => { ... } (Default case with the subject as &dyn Any)
+    //          _ name => { ... } (Default case with the subject's type name)
+    // ----------------------------------------------------------------
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] _ @ $name:ident => $block:block $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*]
+            _ => {
+                let $name: &$crate::__typeswitch!(@dyn [$($mode)*]) = $crate::__typeswitch!(@as_dyn [$($mode)*] $var);
+                $block
+            }
+            $($rest)*
+        }
+    };
+
+    // The name is that of the static type behind the subject: a generic
+    // `&T` names `T`, but a `dyn Any` only knows its `TypeId`.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] _ $name:ident => $block:block $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*]
+            _ => {
+                let $name: &'static str = ::core::any::type_name_of_val(&*$var);
+                $block
+            }
+            $($rest)*
+        }
+    };
+
+    // box _ as rest => { ... }: the default of a consuming switch, with the
+    // box that no arm took. `box` arms only move out once their type check
+    // passed, so the box is still whole here.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] box _ as $name:ident => $block:block $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*]
+            _ => {
+                let $name = $crate::__typeswitch_alloc!($crate::__private::BoxSubject::into_box($var));
+                $block
+            }
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] box _ as $name:ident => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] box _ as $name => { $e } $($($rest)*)?}
+    };
+
+    // arc _ as rest / rc _ as rest: the same for a shared subject. `arc` and
+    // `rc` arms only cast the pointer once their type check passed, so it
+    // is handed back with its count untouched.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] arc _ as $name:ident => $block:block $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*]
+            _ => {
+                let $name = $crate::__typeswitch_alloc!($crate::__private::SharedSubject::into_shared($var));
+                $block
+            }
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] arc _ as $name:ident => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] arc _ as $name => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt rc _ as $name:ident => $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs arc _ as $name => $($rest)*}
+    };
+
+    // `_ as dyn Trait` / `_ @ name as dyn Trait`: whatever is left that was
+    // registered for `Trait`, see `register_dyn!`. Arms may still follow.
+    (@step $var:expr; $mode:tt [$auto:ident] [$($attrs:tt)*] _ as dyn $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode [$auto] [$($attrs)*] $auto : dyn $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt [$auto:ident $modifier:ident] [$($attrs:tt)*] _ as dyn $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode [$auto $modifier] [$($attrs)*] $modifier $auto : dyn $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt [$($attrs:tt)*] _ @ $name:ident as dyn $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [$($attrs)*] $name : dyn $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt [] [$($attrs:tt)*] _ as dyn $($rest:tt)*) => {
+        compile_error!("typeswitch: `_ as dyn Trait` needs a name, write `_ @ name as dyn Trait`")
+    };
+
+    // `=> expr,` arms are rewritten to `=> { expr }` and go through the
+    // block rules. Each arm kind has one of these, placed after its block
+    // rules so that a block body is never parsed as an expression.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] _ @ $name:ident => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] _ @ $name => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] _ $name:ident => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] _ $name => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [] _ => $block:block $(,)?) => {
+        $crate::__typeswitch!{@wrap [$($mode)*] $block}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [] _ => $block:block ;) => {
+        $crate::__typeswitch!{@wrap [$($mode)*] $block}
+    };
+
+    // Nothing after a plain default can ever run.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [] _ => $block:block $($rest:tt)+) => {
+        compile_error!(concat!(
+            "typeswitch: unreachable arm `", stringify!($($rest)+),
+            "`: the `_` default above already matches everything"
+        ))
+    };
+
+    // An attributed default only short-circuits when its `cfg`s keep it. The
+    // remaining arms go under the opposite `cfg`, so exactly one of the two
+    // survives and they never have to agree on a type.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)+] _ => $block:block $($rest:tt)*) => {
+        $crate::__typeswitch!{@default $var; [$($mode)*] [$($auto)*] [] [] $($attrs)+ $block $($rest)*}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] _ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] _ => { $e } $($($rest)*)?}
+    };
+
+    // `default` spells `_` out. It only means the default as the last arm,
+    // so a type that happens to be called `default` still works elsewhere.
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt default $(@ $name:ident)? => $block:block $(,)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs _ $(@ $name)? => $block}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt default $(@ $name:ident)? => $block:block ;) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs _ $(@ $name)? => $block}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt default $(@ $name:ident)? => $e:expr $(,)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs _ $(@ $name)? => { $e }}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box default as $name:ident => $block:block $(,)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs box _ as $name => $block}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box default as $name:ident => $e:expr $(,)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs box _ as $name => { $e }}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt arc default as $name:ident => $block:block $(,)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs arc _ as $name => $block}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt arc default as $name:ident => $e:expr $(,)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs arc _ as $name => { $e }}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt rc default as $name:ident => $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs arc default as $name => $($rest)*}
+    };
+
+    // `not (A | B) => { ... }`: anything that is none of the listed types.
+    // It is tried in order like any arm, so it also takes types that an arm
+    // further down names. It binds nothing, and arms may follow.
+    (@step $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] not ($($ty:ty)|+) $(if $guard:expr)? => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [!$crate::__typeswitch!(@is_any $mode $var; $($ty),+) $(&& $guard)?]
+            []
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt [$($attrs:tt)*] not ($($ty:ty)|+) $(if $guard:expr)? => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@reswitch $var; $mode $auto [$($attrs)*]
+            [!$crate::__typeswitch!(@is_any $mode $var; $($ty),+) $(&& $guard)?]
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt [$($attrs:tt)*] not ($($ty:ty)|+) $(if $guard:expr)? => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [$($attrs)*] not ($($ty)|+) $(if $guard)? => { $e } $($($rest)*)?}
+    };
+
+    (@default $var:expr; $mode:tt $auto:tt [$($cfg:tt)*] [$($other:tt)*] #[cfg($($pred:tt)*)] $($rest:tt)*) => {
+        $crate::__typeswitch!{@default $var; $mode $auto [$($cfg)* $($pred)*,] [$($other)*] $($rest)*}
+    };
+    (@default $var:expr; $mode:tt $auto:tt [$($cfg:tt)*] [$($other:tt)*] #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!{@default $var; $mode $auto [$($cfg)*] [$($other)* #[$($attr)*]] $($rest)*}
+    };
+    (@default $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($cfg:tt)*] [$($other:tt)*] $block:block $($rest:tt)*) => {
+        match () {
+            $($other)*
+            #[cfg(all($($cfg)*))]
+            () => $crate::__typeswitch!{@wrap [$($mode)*] $block},
+            #[cfg(not(all($($cfg)*)))]
+            _ => $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*},
+        }
+    };
+
+    // ----------------------------------------------------------------
+    // DESTRUCTURING: [modifier] Pattern: Type => { ... }
+    // A struct, tuple struct, tuple or slice pattern in place of the
+    // binding. The arm binds the value under a hidden name and the
+    // pattern is applied with a `let` (and inside the guard, if any),
+    // so it must be irrefutable. A `pat` fragment can't be followed by
+    // `:`, which is why the shapes are spelled out. `box` and `mut` come
+    // first so that `box (a, b)` is not read as a tuple struct named `box`.
+    // ----------------------------------------------------------------
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box $($path:ident)::+ { $($fields:tt)* } : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [box] [$($path)::+ { $($fields)* }] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box $($path:ident)::+ ( $($fields:tt)* ) : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [box] [$($path)::+ ( $($fields)* )] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box ( $($fields:tt)* ) : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [box] [( $($fields)* )] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box [ $($fields:tt)* ] : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [box] [[ $($fields)* ]] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt mut $($path:ident)::+ { $($fields:tt)* } : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [mut] [$($path)::+ { $($fields)* }] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt mut $($path:ident)::+ ( $($fields:tt)* ) : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [mut] [$($path)::+ ( $($fields)* )] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt mut ( $($fields:tt)* ) : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [mut] [( $($fields)* )] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt mut [ $($fields:tt)* ] : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [mut] [[ $($fields)* ]] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $($path:ident)::+ { $($fields:tt)* } : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [] [$($path)::+ { $($fields)* }] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $($path:ident)::+ ( $($fields:tt)* ) : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [] [$($path)::+ ( $($fields)* )] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt ( $($fields:tt)* ) : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [] [( $($fields)* )] [] $($rest)+}
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt [ $($fields:tt)* ] : $($rest:tt)+) => {
+        $crate::__typeswitch!{@pat $var; value; $mode $auto $attrs [] [[ $($fields)* ]] [] $($rest)+}
+    };
+
+    // ----------------------------------------------------------------
+    // PATTERN: Type => switch { ... }
+    //          dyn Trait => switch { ... }
+    // ----------------------------------------------------------------
+    // Once the type check passes, the inner arms switch on the same subject
+    // as part of the same switch: they share its modes and its pre-binding,
+    // and if none of them matches, the arms below are not tried either. A
+    // binding is dropped, so the inner arms are free to borrow the subject.
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt mut $bind:ident : dyn $tr:path => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs dyn $tr => switch $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $bind:ident : dyn $tr:path => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs dyn $tr => switch $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt dyn $tr:path => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@reswitch $var; $mode $auto $attrs
+            [let ::core::option::Option::Some(_) = <dyn $tr as $crate::DynRegistry>::cast_ref($crate::__typeswitch!(@as_any $var))]
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt mut $bind:ident : $($ty:ty)|+ => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $($ty)|+ => switch $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $bind:ident : $($ty:ty)|+ => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $($ty)|+ => switch $($rest)*}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $($ty:ty)|+ => switch $($rest:tt)*) => {
+        $crate::__typeswitch!{@reswitch $var; $mode $auto $attrs [$crate::__typeswitch!(@is_any $mode $var; $($ty),+)] $($rest)*}
+    };
+
+    (@reswitch $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] [$($cond:tt)+] { $($arms:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*] [$($cond)+] []
+            { @resume $crate::__typeswitch!{@step $var; $mode $auto [] $($arms)*} }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@reswitch $var:expr; $mode:tt $auto:tt $attrs:tt $cond:tt $($rest:tt)*) => {
+        compile_error!("typeswitch: expected `{ ... }` arms after `switch`")
+    };
+
+    // 1.
+    // ----------------------------------------------------------------
+    // PATTERN: box binding: Type => { ... }
+    // Requirement: $var must be Box<dyn Any>
+    // ----------------------------------------------------------------
+    // Once the value is moved out there is no subject left for the arms below.
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt box $bind:ident : $($ty:ty)|+ => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: a `box` arm moves the subject, so it cannot `fallthrough`")
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt arc $bind:ident : $($ty:ty)|+ => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: an `arc` or `rc` arm moves the subject, so it cannot `fallthrough`")
+    };
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt rc $bind:ident : $($ty:ty)|+ => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: an `arc` or `rc` arm moves the subject, so it cannot `fallthrough`")
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] box $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            // We check 'is' first to avoid consuming the box if the type doesn't match.
+            // If it does match, we unwrap.
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [$crate::__typeswitch!(@is [$($mode)*] $var; $ty)]
+                // Moving out goes through `BoxSubject`, so a subject that doesn't
+                // own its value is reported as such rather than as a missing method.
+                [$crate::__typeswitch!{@arm_type $ty} let $bind = $crate::__typeswitch!(@take [$($mode)*] $var; $ty);]
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    // Or-pattern: each alternative becomes its own `box` arm sharing the
+    // block, so the binding is always the owned concrete value. The block
+    // must therefore compile (and evaluate to the same type) for every type.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] box $bind:ident : $head:ty | $($tail:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] []
+            $(#[$attr])* box $bind : $head => { $($body)* }
+            $(#[$attr])* box $bind : $($tail)|+ => { $($body)* }
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] box $bind:ident : $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] box $bind : $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    // Anything else starting with `box binding:` carries a guard.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] box $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [box $bind] [] $($rest)*}
+    };
+
+    // ----------------------------------------------------------------
+    // PATTERN: replace binding: Type => { ... }
+    // Requirement: $var must be Box<dyn Any> or &mut Box<dyn Any>
+    // ----------------------------------------------------------------
+    // The value is moved out (a `()` keeps its place meanwhile) and the
+    // block's value is boxed in its stead. The box itself stays put.
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt replace $bind:ident : $ty:ty => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: a `replace` arm moves the value out, so it cannot `fallthrough`")
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] replace $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [$crate::__typeswitch!(@is [$($mode)*] $var; $ty)]
+                [
+                    $crate::__typeswitch!{@arm_type $ty}
+                    #[allow(unused_imports)]
+                    use $crate::__private::BoxSlot as _;
+                    let $bind = $crate::__typeswitch!(@replace_take [$($mode)*] $var; $ty);
+                ]
+                {
+                    let value = { $($body)* };
+                    *$var.replace_slot() = $crate::__private::Box::new(value);
+                }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] replace $bind:ident : $ty:ty => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] replace $bind : $ty => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt replace $bind:ident : $($rest:tt)*) => {
+        compile_error!(concat!(
+            "typeswitch: expected `replace ", stringify!($bind),
+            ": Type => ...`, `replace` arms take a single type and no guard"
+        ))
+    };
+
+    // 2.
+    // ----------------------------------------------------------------
+    // PATTERN: arc binding: Type => { ... }
+    //          rc binding: Type => { ... }
+    // Requirement: $var must be Arc<dyn Any + Send + Sync> or Rc<dyn Any>
+    // ----------------------------------------------------------------
+
+    // `Rc::downcast` has the same shape as `Arc::downcast`, and the
+    // expansion below only calls methods on the subject, so `rc` arms
+    // reuse the `arc` rules as they are.
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] rc $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] arc $bind : $($rest)*}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] arc $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            // Same as `box`: only give the Arc away once we know it will be accepted,
+            // so a mismatch neither clones nor drops it.
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [$crate::__typeswitch!(@is [$($mode)*] $var; $ty)]
+                [$crate::__typeswitch!{@arm_type $ty} let $bind = $crate::__typeswitch!(@take_shared [$($mode)*] $var; $ty);]
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] arc $bind:ident : $head:ty | $($tail:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] []
+            $(#[$attr])* arc $bind : $head => { $($body)* }
+            $(#[$attr])* arc $bind : $($tail)|+ => { $($body)* }
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] arc $bind:ident : $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] arc $bind : $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] arc $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [arc $bind] [] $($rest)*}
+    };
+
+    // 3.
+    // ----------------------------------------------------------------
+    // PATTERN: rc_mut binding: Type => { ... }
+    // Requirement: $var must be a mutable Rc<dyn Any>
+    // ----------------------------------------------------------------
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] rc_mut $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            // `Rc::get_mut` only succeeds while no other `Rc` or `Weak` points
+            // at the value; a shared value falls through like a type mismatch.
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [let Some($bind) = $crate::__private::Rc::get_mut(&mut $var).and_then(|any| any.downcast_mut::<$ty>())]
+                [$crate::__typeswitch!{@arm_type $ty}]
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] rc_mut $bind:ident : $head:ty | $($tail:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] []
+            $(#[$attr])* rc_mut $bind : $head => { $($body)* }
+            $(#[$attr])* rc_mut $bind : $($tail)|+ => { $($body)* }
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] rc_mut $bind:ident : $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] rc_mut $bind : $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] rc_mut $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [rc_mut $bind] [] $($rest)*}
+    };
+
+    // 4.
+    // ----------------------------------------------------------------
+    // PATTERN: weak binding: Type => { ... }
+    //          weak_dead => { ... }
+    // Requirement: $var must be a sync::Weak<dyn Any + Send + Sync> or rc::Weak<dyn Any>
+    // ----------------------------------------------------------------
+    // The first `weak` arm upgrades the subject and hands the result on as
+    // the subject of the arms below it, so later `weak` arms test the same
+    // pointer rather than upgrading again.
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt weak $bind:ident : $($ty:ty)|+ => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: a `weak` arm moves the upgraded pointer, so it cannot `fallthrough`")
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] weak $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            match $crate::__typeswitch!(@weak_state $var) {
+                mut state => $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                    [state.is::<$ty>()]
+                    [
+                        $crate::__typeswitch!{@arm_type $ty}
+                        // SAFETY: the condition checked this state, which only this arm can see.
+                        let $bind = unsafe { state.take_unchecked::<$ty>() };
+                    ]
+                    { $($body)* }
+                    {$crate::__typeswitch!{@step state; [$($mode)*] [$($auto)*] [] $($rest)*}}
+                },
+            }
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] weak $bind:ident : $head:ty | $($tail:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] []
+            $(#[$attr])* weak $bind : $head => { $($body)* }
+            $(#[$attr])* weak $bind : $($tail)|+ => { $($body)* }
+            $($rest)*
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] weak $bind:ident : $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] weak $bind : $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] weak $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [weak $bind] [] $($rest)*}
+    };
+
+    (@weak_state $var:expr) => {{
+        use $crate::__private::WeakSubject as _;
+        $var.weak_state()
+    }};
+
+    (@weak_dead $var:expr) => {{
+        use $crate::__private::WeakDead as _;
+        $var.weak_is_dead()
+    }};
+
+    // A dead `Weak` fails every `weak` arm, and is what this one is for.
+    (@step $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] weak_dead => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+                [$crate::__typeswitch!(@weak_dead $var)]
+                []
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+            }
+        }
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt weak_dead => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs weak_dead => { $e } $($($rest)*)?}
+    };
+
+    // 5.
+    // ----------------------------------------------------------------
+    // PATTERN: binding: dyn Trait => { ... }
+    //          mut binding: dyn Trait => { ... }
+    //          dyn Trait => { ... }
+    // Requirement: Trait must be registered with `register_dyn!`
+    // ----------------------------------------------------------------
+    (@step $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] $bind:ident : dyn $tr:path => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [let ::core::option::Option::Some($bind) = <dyn $tr as $crate::DynRegistry>::cast_ref($crate::__typeswitch!(@as_any $var))]
+            [$crate::__typeswitch!{@arm_type dyn $tr}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] mut $bind:ident : dyn $tr:path => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [let ::core::option::Option::Some($bind) = <dyn $tr as $crate::DynRegistry>::cast_mut($crate::__typeswitch!(@as_any_mut $var))]
+            [$crate::__typeswitch!{@arm_type dyn $tr}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    // A bare `dyn Trait` without a pre-binding only checks that the type was
+    // registered, like a `Type =>` arm.
+    (@step $var:expr; $mode:tt [] [$(#[$attr:meta])*] dyn $tr:path => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [let ::core::option::Option::Some(_) = <dyn $tr as $crate::DynRegistry>::cast_ref($crate::__typeswitch!(@as_any $var))]
+            [$crate::__typeswitch!{@arm_type dyn $tr}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode [] [] $($rest)*}}
+        }
+    };
+
+    (@step $var:expr; $mode:tt [] [$($attrs:tt)*] dyn $tr:path => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode [] [$($attrs)*] dyn $tr => { $e } $($($rest)*)?}
+    };
+
+    // Ahead of the `ref` and `mut` normalizers, which would hide `dyn` inside a `ty`.
+    (@step $var:expr; $mode:tt $auto:tt [$($attrs:tt)*] $bind:ident : dyn $tr:path => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [$($attrs)*] $bind : dyn $tr => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; $mode:tt $auto:tt [$($attrs:tt)*] mut $bind:ident : dyn $tr:path => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [$($attrs)*] mut $bind : dyn $tr => { $e } $($($rest)*)?}
+    };
+
+    // 6.
+    // ----------------------------------------------------------------
+    // PATTERN: mut binding: Type => { ... }
+    // Requirement: $var must be &mut dyn Any (or Box)
+    // ----------------------------------------------------------------
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] mut $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [let Some($bind) = $crate::__typeswitch!(@mut [$($mode)*] $var; $ty)]
+            [$crate::__typeswitch!{@arm_type $ty}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    // The alternatives have different types, so an or-pattern binds the
+    // subject itself as `&mut dyn Any`.
+    // The mode is taken as a single `[...]` tree here so that it can be
+    // repeated once per alternative.
+    (@step $var:expr; $mode:tt [$($auto:tt)*] [$(#[$attr:meta])*] mut $bind:ident : $head:ty | $($tail:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [$crate::__typeswitch!(@is_any $mode $var; $head $(, $tail)+)]
+            [$crate::__typeswitch!{@arm_type $head $(| $tail)+} let $bind: &mut dyn ::core::any::Any = $crate::__typeswitch!(@as_any_mut $var);]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] mut $bind:ident : $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] mut $bind : $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] mut $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [mut $bind] [] $($rest)*}
+    };
+
+    // 7.
+    // ----------------------------------------------------------------
+    // PATTERN: binding: Type => { ... }
+    // Requirement: $var must be &dyn Any (or &mut/Box)
+    // ----------------------------------------------------------------
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] $bind:ident : $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [let Some($bind) = $crate::__typeswitch!(@ref [$($mode)*] $var; $ty)]
+            [$crate::__typeswitch!{@arm_type $ty}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    // The alternatives have different types, so an or-pattern binds the
+    // subject itself as `&dyn Any`.
+    (@step $var:expr; $mode:tt [$($auto:tt)*] [$(#[$attr:meta])*] $bind:ident : $head:ty | $($tail:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [$crate::__typeswitch!(@is_any $mode $var; $head $(, $tail)+)]
+            [$crate::__typeswitch!{@arm_type $head $(| $tail)+} let $bind: &$crate::__typeswitch!(@dyn $mode) = $crate::__typeswitch!(@as_dyn $mode $var);]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] $bind:ident : $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [$($attrs)*] $bind : $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [ref $bind] [] $($rest)*}
+    };
+
+    // Or-patterns, with or without an automatic binding (see 8. below).
+    (@step $var:expr; $mode:tt $auto:tt $attrs:tt $head:ty | $($rest:tt)+) => {
+        $crate::__typeswitch!{@or [$var; $mode $auto $attrs] [$head] $($rest)+}
+    };
+
+    // ----------------------------------------------------------------
+    // PATTERN: Type => |binding| expr (Projection)
+    // The closure's parameter becomes the arm's binding, with the access
+    // of a pre-binding if there is one, and its body the arm's value.
+    // ----------------------------------------------------------------
+    (@step $var:expr; $mode:tt [$($auto:ident $($modifier:ident)?)?] $attrs:tt $ty:ty => |$param:ident : $pty:ty| $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode [] $attrs $($($modifier)?)? projected : $ty => {
+            let $param: $pty = projected;
+            $e
+        } $($($rest)*)?}
+    };
+
+    (@step $var:expr; $mode:tt [$($auto:ident $($modifier:ident)?)?] $attrs:tt $ty:ty => |$param:pat_param| $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; $mode [] $attrs $($($modifier)?)? projected : $ty => {
+            let $param = projected;
+            $e
+        } $($($rest)*)?}
+    };
+
+    // ============================================================
+    // AUTO-BINDING (Redistributors)
+    // Every arm reaching here without an explicit binding gets the
+    // pre-bound name (and modifier) prepended, then is re-dispatched
+    // to the explicit rules above. The name is the caller's own token, so
+    // a nested `v as y` switch shadows an outer `v` only inside its arms.
+    // ============================================================
+
+    (@step $var:expr; [$($mode:tt)*] [$auto:ident] [$($attrs:tt)*] $($rest:tt)+) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$auto] [$($attrs)*] $auto : $($rest)+}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$auto:ident $modifier:ident] [$($attrs:tt)*] $($rest:tt)+) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [$auto $modifier] [$($attrs)*] $modifier $auto : $($rest)+}
+    };
+
+    // 8.
+    // ----------------------------------------------------------------
+    // PATTERN: Type => { ... } (No binding, just check)
+    // ----------------------------------------------------------------
+    (@step $var:expr; [$($mode:tt)*] [] [$(#[$attr:meta])*] $ty:ty => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [$crate::__typeswitch!(@is [$($mode)*] $var; $ty)]
+            [$crate::__typeswitch!{@arm_type $ty}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [] [] $($rest)*}}
+        }
+    };
+
+    // 9.
+    // ----------------------------------------------------------------
+    // PATTERN: Type | Type => { ... } (Or pattern)
+    // `_` is a type too, so `$($ty:ty)|+` can't stop in front of a trailing
+    // `| _`. The alternatives are taken one at a time instead: a `_` turns
+    // the arm into the default, otherwise it goes on as an or-pattern.
+    // ----------------------------------------------------------------
+    (@or [$var:expr; $mode:tt $auto:tt $attrs:tt] $tys:tt _ => $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs _ => $($rest)*}
+    };
+
+    (@or $ctx:tt $tys:tt _ $($rest:tt)*) => {
+        compile_error!("typeswitch: `_` in an or-pattern must be the last alternative, without a guard")
+    };
+
+    (@or $ctx:tt [$($ty:ty),+] $next:ty | $($rest:tt)+) => {
+        $crate::__typeswitch!{@or $ctx [$($ty,)+ $next] $($rest)+}
+    };
+
+    (@or $ctx:tt [$($ty:ty),+] $next:ty => $($rest:tt)*) => {
+        $crate::__typeswitch!{@or_done $ctx [$($ty,)+ $next] => $($rest)*}
+    };
+
+    // A `ty` can't be followed by `if`, so a guarded arm keeps the tokens of its
+    // last alternative as they are.
+    (@or $ctx:tt [$($ty:ty),+] $($rest:tt)*) => {
+        $crate::__typeswitch!{@or_done $ctx [$($ty),+] | $($rest)*}
+    };
+
+    (@or_done [$var:expr; $mode:tt [$auto:ident] $attrs:tt] [$($ty:ty),+] $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode [$auto] $attrs $auto : $($ty)|+ $($rest)*}
+    };
+
+    (@or_done [$var:expr; $mode:tt [$auto:ident $modifier:ident] $attrs:tt] [$($ty:ty),+] $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode [$auto $modifier] $attrs $modifier $auto : $($ty)|+ $($rest)*}
+    };
+
+    (@or_done [$var:expr; $mode:tt [] [$(#[$attr:meta])*]] [$head:ty $(, $tail:ty)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [$crate::__typeswitch!(@is_any $mode $var; $head $(, $tail)+)]
+            [$crate::__typeswitch!{@arm_type $head $(| $tail)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode [] [] $($rest)*}}
+        }
+    };
+
+    (@or_done $ctx:tt $tys:tt => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@or_done $ctx $tys => { $e } $($($rest)*)?}
+    };
+
+    (@or_done [$var:expr; $mode:tt [] $attrs:tt] [$($ty:ty),+] | $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode [] $attrs [is] [] $($ty)|+ | $($rest)*}
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [] [$($attrs:tt)*] $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@step $var; [$($mode)*] [] [$($attrs)*] $($ty)|+ => { $e } $($($rest)*)?}
+    };
+
+    // ----------------------------------------------------------------
+    // Base Case: No more patterns
+    // ----------------------------------------------------------------
+    (@step $var:expr; [$(match $id:tt)? $(err_source)?] [$($auto:tt)*] []) => { () };
+
+    (@step $var:expr; [$(match $id:tt)? $(err_source)? try] [$($auto:tt)*] []) => {
+        ::core::result::Result::Err($var)
+    };
+
+    // A `dyn Any` doesn't know its type's name, so the panic shows its id,
+    // and the static type of the subject, which names it for a generic `&T`.
+    (@step $var:expr; [err_source exhaustive] [$($auto:tt)*] []) => {
+        ::core::panic!("typeswitch: no arm matched the subject's type")
+    };
+
+    (@step $var:expr; [$(match $id:tt)? exhaustive] [$($auto:tt)*] []) => {
+        ::core::panic!(
+            "typeswitch: no arm matched the subject's type: {:?} behind `{}`",
+            <dyn ::core::any::Any>::type_id($crate::__typeswitch!(@as_any $var)),
+            ::core::any::type_name_of_val(&$var),
+        )
+    };
+
+    (@step $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)+]) => {
+        compile_error!(concat!("typeswitch: attribute `", stringify!($($attrs)+), "` is not followed by an arm"))
+    };
+
+    // Anything else is a bare type with a guard.
+    (@step $var:expr; [$($mode:tt)*] [] [$($attrs:tt)*] $($rest:tt)+) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [] [$($attrs)*] [is] [] $($rest)+}
+    };
+
+    // ============================================================
+    // DESTRUCTURING, continued: collect the type up to `=>` or `if`,
+    // then rewrite the arm with the hidden `value` binding.
+    // ============================================================
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt [$($kind:ident)?] [$($pat:tt)+] [$($ty:tt)+] => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $($kind)? $value : $($ty)+ => { fallthrough; let $($pat)+ = $value; $($body)* } $($rest)*}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt [$($kind:ident)?] [$($pat:tt)+] [$($ty:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $($kind)? $value : $($ty)+ => { let $($pat)+ = $value; $($body)* } $($rest)*}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt $kind:tt $pat:tt [$($ty:tt)+] => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@pat $var; $value; $mode $auto $attrs $kind $pat [$($ty)+] => { $e } $($($rest)*)?}
+    };
+
+    // The pattern's bindings would only be in scope inside a block around
+    // the guard, and a `let` guard can't be put in one.
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt $kind:tt [$($pat:tt)+] $ty:tt if let $($rest:tt)*) => {
+        compile_error!("typeswitch: a destructuring arm can't have an `if let` guard; bind the value and destructure it in the block")
+    };
+
+    // The guard sees the value the way the guard of a plain binding would.
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt $kind:tt [$($pat:tt)+] $ty:tt if $guard:expr => $($rest:tt)*) => {
+        $crate::__typeswitch!{@pat $var; $value; $mode $auto $attrs $kind [$($pat)+] $ty [if { let $($pat)+ = $value; $guard }] => $($rest)*}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt [$($kind:ident)?] [$($pat:tt)+] [$($ty:tt)+] [if $guard:expr] => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $($kind)? $value : $($ty)+ if $guard => { fallthrough; let $($pat)+ = $value; $($body)* } $($rest)*}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt [$($kind:ident)?] [$($pat:tt)+] [$($ty:tt)+] [if $guard:expr] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto $attrs $($kind)? $value : $($ty)+ if $guard => { let $($pat)+ = $value; $($body)* } $($rest)*}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt $kind:tt $pat:tt $ty:tt [if $guard:expr] => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@pat $var; $value; $mode $auto $attrs $kind $pat $ty [if $guard] => { $e } $($($rest)*)?}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt $kind:tt $pat:tt [$($ty:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!{@pat $var; $value; $mode $auto $attrs $kind $pat [$($ty)* $next] $($rest)*}
+    };
+
+    (@pat $var:expr; $value:ident; $mode:tt $auto:tt $attrs:tt $kind:tt [$($pat:tt)+] [$($ty:tt)*]) => {
+        compile_error!(concat!("typeswitch: expected `=>` after `", stringify!($($pat)+ : $($ty)*), "`"))
+    };
+
+    // ============================================================
+    // GUARDS: [binding] Type if cond => { ... }
+    //
+    // A `ty` fragment cannot be followed by `if`, so the type is
+    // munched token by token until the guard is found. The guard is
+    // chained onto the downcast; when it fails we fall through to the
+    // remaining arms exactly like a failed downcast does.
+    // ============================================================
+
+    // A guard is an expression or, like an `if let` match guard, a chain
+    // starting with `let PAT = EXPR`, whose bindings reach the block. Either
+    // way it is chained onto the downcast's `let` chain, so both are
+    // bracketed after `@guard` here. A `let` chain is taken as it is, up to `=>`.
+    // The types are complete by then, and `@alts` marks them so that only
+    // a full list is ever parsed as an or-pattern.
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt [$($ty:tt)+] if let $($rest:tt)+) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs $kind [$($ty)+] if @let [let] $($rest)+}
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt [$($ty:tt)+] if @let [$($guard:tt)+] => $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs $kind @alts [$($ty)+] if @guard [$($guard)+] => $($rest)*}
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt [$($ty:tt)+] if @let [$($guard:tt)+] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs $kind [$($ty)+] if @let [$($guard)+ $next] $($rest)*}
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt [$($ty:tt)+] if $guard:expr => $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs $kind @alts [$($ty)+] if @guard [$guard] => $($rest)*}
+    };
+
+    // For `box` and `arc` (and so `rc`) arms the guard sees the value by reference; the
+    // subject is only consumed once both the type check and the guard have passed.
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt [box $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: a `box` arm moves the subject, so it cannot `fallthrough`")
+    };
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt [arc $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: an `arc` or `rc` arm moves the subject, so it cannot `fallthrough`")
+    };
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt [weak $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: a `weak` arm moves the upgraded pointer, so it cannot `fallthrough`")
+    };
+
+    // Guarded or-patterns. As without a guard, a shared or `mut` binding is
+    // the subject itself as `dyn Any`, bound before the guard runs so that it
+    // can use it, and the owned kinds become one arm per type.
+    (@guard $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] [is] @alts [$head:ty $(| $tail:ty)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [$crate::__typeswitch!(@is_any $mode $var; $head $(, $tail)+) && $($guard)+]
+            [$crate::__typeswitch!{@arm_type $head $(| $tail)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] [ref $bind:ident] @alts [$head:ty $(| $tail:ty)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [
+                $crate::__typeswitch!(@is_any $mode $var; $head $(, $tail)+)
+                    && let $bind = ::core::convert::identity::<&$crate::__typeswitch!(@dyn $mode)>($crate::__typeswitch!(@as_dyn $mode $var))
+                    && $($guard)+
+            ]
+            [$crate::__typeswitch!{@arm_type $head $(| $tail)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] [mut $bind:ident] @alts [$head:ty $(| $tail:ty)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [
+                $crate::__typeswitch!(@is_any $mode $var; $head $(, $tail)+)
+                    && let $bind = ::core::convert::identity::<&mut dyn ::core::any::Any>($crate::__typeswitch!(@as_any_mut $var))
+                    && $($guard)+
+            ]
+            [$crate::__typeswitch!{@arm_type $head $(| $tail)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt [$($attrs:tt)*] [$kind:ident $bind:ident] @alts [$head:ty $(| $tail:ty)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto []
+            $($attrs)* $kind $bind : $head if $($guard)+ => { $($body)* }
+            $($attrs)* $kind $bind : $($tail)|+ if $($guard)+ => { $($body)* }
+            $($rest)*
+        }
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt @alts $tys:tt if @guard $guard:tt => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs $kind $tys if @guard $guard => { $($body)* } $($rest)*}
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt @alts $tys:tt if @guard $guard:tt => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs $kind @alts $tys if @guard $guard => { $e } $($($rest)*)?}
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [box $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [let Some($bind) = $crate::__typeswitch!(@ref [$($mode)*] $var; $($ty)+) && $($guard)+]
+                [
+                    $crate::__typeswitch!{@arm_type $($ty)+}
+                    // Like a `match` guard, using the binding only in the guard is fine.
+                    #[allow(unused_variables)]
+                    let $bind = $crate::__private::BoxSubject::take::<$($ty)+>($var);
+                ]
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [arc $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [let Some($bind) = $crate::__typeswitch!(@ref [$($mode)*] $var; $($ty)+) && $($guard)+]
+                [
+                    $crate::__typeswitch!{@arm_type $($ty)+}
+                    // Like a `match` guard, using the binding only in the guard is fine.
+                    #[allow(unused_variables)]
+                    let $bind = $var.downcast::<$($ty)+>().expect("typeswitch: type check passed but downcast failed");
+                ]
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [weak $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            match $crate::__typeswitch!(@weak_state $var) {
+                mut state => $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                    [let Some($bind) = state.peek::<$($ty)+>() && $($guard)+]
+                    [
+                        $crate::__typeswitch!{@arm_type $($ty)+}
+                        // SAFETY: `peek` found a `T` in the state, which the guard can't name.
+                        #[allow(unused_variables)]
+                        let $bind = unsafe { state.take_unchecked::<$($ty)+>() };
+                    ]
+                    { $($body)* }
+                    {$crate::__typeswitch!{@step state; [$($mode)*] [$($auto)*] [] $($rest)*}}
+                },
+            }
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [rc_mut $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch_alloc! {
+            $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+                [let Some($bind) = $crate::__private::Rc::get_mut(&mut $var).and_then(|any| any.downcast_mut::<$($ty)+>()) && $($guard)+]
+                [$crate::__typeswitch!{@arm_type $($ty)+}]
+                { $($body)* }
+                {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+            }
+        }
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] [mut $bind:ident] [dyn $tr:path] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [let ::core::option::Option::Some($bind) = <dyn $tr as $crate::DynRegistry>::cast_mut($crate::__typeswitch!(@as_any_mut $var)) && $($guard)+]
+            [$crate::__typeswitch!{@arm_type dyn $tr}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt [$(#[$attr:meta])*] [ref $bind:ident] [dyn $tr:path] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm $mode [$(#[$attr])*]
+            [let ::core::option::Option::Some($bind) = <dyn $tr as $crate::DynRegistry>::cast_ref($crate::__typeswitch!(@as_any $var)) && $($guard)+]
+            [$crate::__typeswitch!{@arm_type dyn $tr}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; $mode $auto [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [mut $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [let Some($bind) = $crate::__typeswitch!(@mut [$($mode)*] $var; $($ty)+) && $($guard)+]
+            [$crate::__typeswitch!{@arm_type $($ty)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [ref $bind:ident] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [let Some($bind) = $crate::__typeswitch!(@ref [$($mode)*] $var; $($ty)+) && $($guard)+]
+            [$crate::__typeswitch!{@arm_type $($ty)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [is] [$($ty:tt)+] if @guard [$($guard:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [$crate::__typeswitch!(@is [$($mode)*] $var; $($ty)+) && $($guard)+]
+            [$crate::__typeswitch!{@arm_type $($ty)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] [$($kind:tt)*] [$($ty:tt)+] if @guard [$($guard:tt)+] => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [$($kind)*] [$($ty)+] if @guard [$($guard)+] => { $e } $($($rest)*)?}
+    };
+
+    // `where pred` is a guard that calls `pred` with the bound value by
+    // reference. Without a binding, a hidden one is made for it.
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt [is] [$($ty:tt)+] where $pred:expr => $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs [ref value] [$($ty)+] if ($pred)(value) => $($rest)*}
+    };
+
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt [$kind:ident $bind:ident] [$($ty:tt)+] where $pred:expr => $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; $mode $auto $attrs [$kind $bind] [$($ty)+] if ($pred)(&*$bind) => $($rest)*}
+    };
+
+    // `via f` replaces the downcast with a call to `f`, see VIA EXTRACTORS.
+    (@guard $var:expr; $mode:tt $auto:tt $attrs:tt $kind:tt [$($ty:tt)+] via $($rest:tt)+) => {
+        $crate::__typeswitch!{@via [$var; $mode $auto $attrs $kind [$($ty)+]] [] $($rest)+}
+    };
+
+    // Reached `=>` without finding a guard: nothing above understood this arm.
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] [$($kind:tt)*] [$($ty:tt)*] => $($rest:tt)*) => {
+        compile_error!(concat!("typeswitch: unsupported arm `", stringify!($($ty)*), "`"))
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] [$($kind:tt)*] [$($ty:tt)*]) => {
+        compile_error!(concat!("typeswitch: expected `=>` after `", stringify!($($ty)*), "`"))
+    };
+
+    (@guard $var:expr; [$($mode:tt)*] [$($auto:tt)*] [$($attrs:tt)*] [$($kind:tt)*] [$($ty:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!{@guard $var; [$($mode)*] [$($auto)*] [$($attrs)*] [$($kind)*] [$($ty)* $next] $($rest)*}
+    };
+
+    // ============================================================
+    // VIA EXTRACTORS (`binding: Type via f`)
+    // `@via [$var; $mode $auto [attrs] [kind] [type]] [f] ...` collects the
+    // extractor up to the guard or `=>`. The arm then binds whatever `f`
+    // returns for the subject as `&dyn Any` (`&mut dyn Any` for `mut`),
+    // instead of downcasting it, so `match` mode's `TypeId` is not consulted.
+    // ============================================================
+    (@via $ctx:tt [$($f:tt)+] if let $($rest:tt)+) => {
+        $crate::__typeswitch!{@via $ctx [$($f)+] if @let [let] $($rest)+}
+    };
+
+    (@via $ctx:tt $f:tt if @let [$($guard:tt)+] => $($rest:tt)*) => {
+        $crate::__typeswitch!{@via_arm $ctx $f [&& $($guard)+] => $($rest)*}
+    };
+
+    (@via $ctx:tt $f:tt if @let [$($guard:tt)+] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!{@via $ctx $f if @let [$($guard)+ $next] $($rest)*}
+    };
+
+    (@via $ctx:tt [$($f:tt)+] if $guard:expr => $($rest:tt)*) => {
+        $crate::__typeswitch!{@via_arm $ctx [$($f)+] [&& $guard] => $($rest)*}
+    };
+
+    (@via $ctx:tt [$($f:tt)+] => $($rest:tt)*) => {
+        $crate::__typeswitch!{@via_arm $ctx [$($f)+] [] => $($rest)*}
+    };
+
+    (@via $ctx:tt [$($f:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!{@via $ctx [$($f)* $next] $($rest)*}
+    };
+
+    (@via $ctx:tt [$($f:tt)*]) => {
+        compile_error!(concat!("typeswitch: expected `=>` after `via ", stringify!($($f)*), "`"))
+    };
+
+    (@via_arm $ctx:tt $f:tt $guard:tt => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@via_arm $ctx $f $guard => { $e } $($($rest)*)?}
+    };
+
+    (@via_arm [$var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [ref $bind:ident] [$($ty:tt)+]] [$($f:tt)+] [$($guard:tt)*] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [let Some($bind) = $crate::__private::extract_ref::<$($ty)+>($($f)+, $crate::__typeswitch!(@as_any $var)) $($guard)*]
+            [$crate::__typeswitch!{@arm_type $($ty)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@via_arm [$var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [mut $bind:ident] [$($ty:tt)+]] [$($f:tt)+] [$($guard:tt)*] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [let Some($bind) = $crate::__private::extract_mut::<$($ty)+>($($f)+, $crate::__typeswitch!(@as_any_mut $var)) $($guard)*]
+            [$crate::__typeswitch!{@arm_type $($ty)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@via_arm [$var:expr; [$($mode:tt)*] [$($auto:tt)*] [$(#[$attr:meta])*] [is] [$($ty:tt)+]] [$($f:tt)+] [$($guard:tt)*] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [$($mode)*] [$(#[$attr])*]
+            [$crate::__private::extract_ref::<$($ty)+>($($f)+, $crate::__typeswitch!(@as_any $var)).is_some() $($guard)*]
+            [$crate::__typeswitch!{@arm_type $($ty)+}]
+            { $($body)* }
+            {$crate::__typeswitch!{@step $var; [$($mode)*] [$($auto)*] [] $($rest)*}}
+        }
+    };
+
+    (@via_arm [$var:expr; $mode:tt $auto:tt $attrs:tt [$($kind:tt)*] $ty:tt] $($rest:tt)*) => {
+        compile_error!(concat!(
+            "typeswitch: `via` works on plain, `mut` and unbound arms, not `",
+            stringify!($($kind)*), "` arms"
+        ))
+    };
+
+    // ============================================================
+    // GROUPED TYPES (`binding: (impl Trait) for [A, B]`)
+    // `@for_types [$var; $mode $auto [attrs]] [binding] [bound] ...` emits
+    // the arm for the first type, with a check that it implements the
+    // bound, followed by the arm again with the remaining types.
+    // ============================================================
+    (@for_types $ctx:tt $bind:tt $bound:tt [$($ty:ty),+ $(,)?] $(if $guard:expr)? => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!{@for_types $ctx $bind $bound [$($ty),+] $(if $guard)? => { $e } $($($rest)*)?}
+    };
+
+    (@for_types $ctx:tt $bind:tt $bound:tt [$head:ty $(, $tail:ty)* $(,)?] $(if $guard:expr)? => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@for_types $ctx $bind $bound [$head $(, $tail)*] $(if $guard)? => [fallthrough;] { $($body)* } $($rest)*}
+    };
+
+    (@for_types $ctx:tt $bind:tt $bound:tt [$head:ty $(, $tail:ty)* $(,)?] $(if $guard:expr)? => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@for_types $ctx $bind $bound [$head $(, $tail)*] $(if $guard)? => [] { $($body)* } $($rest)*}
+    };
+
+    (@for_types [$var:expr; $mode:tt $auto:tt [$($attrs:tt)*]] [$($bind:tt)+] [$($bound:tt)+] [$head:ty] $(if $guard:expr)? => [$($first:tt)*] { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@step $var; $mode $auto [$($attrs)*]
+            $($bind)+ : $head $(if $guard)? => {
+                $($first)*
+                const _: () = {
+                    fn implements<T: ?Sized + $($bound)+>() {}
+                    let _ = implements::<$head>;
+                };
+                $($body)*
+            }
+            $($rest)*
+        }
+    };
+
+    (@for_types [$var:expr; $mode:tt $auto:tt [$($attrs:tt)*]] [$($bind:tt)+] [$($bound:tt)+] [$head:ty, $($tail:ty),+] $(if $guard:expr)? => [$($first:tt)*] { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@for_types [$var; $mode $auto [$($attrs)*]] [$($bind)+] [$($bound)+] [$head] $(if $guard)? => [$($first)*] { $($body)* }
+            $($attrs)* $($bind)+ : (impl $($bound)+) for [$($tail),+] $(if $guard)? => { $($first)* $($body)* }
+            $($rest)*
+        }
+    };
+
+    (@for_types $ctx:tt $bind:tt $bound:tt $($rest:tt)*) => {
+        compile_error!(concat!("typeswitch: expected `[Type, ...] => ...` after `for`, found `", stringify!($($rest)*), "`"))
+    };
+
+    // ============================================================
+    // FIXED ACCESS (`typeswitch_ref!`, `typeswitch_mut!`, `typeswitch_owned!`)
+    // `@fixed [modifier] [head] ...` puts the modifier in front of a
+    // `v as x` pre-binding, then `@fixed_arm` puts it in front of every
+    // `binding:` arm. The result is handed to `typeswitch!` itself.
+    // ============================================================
+    (@fixed [$($m:ident)?] [$($head:tt)*] $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@fixed [$($m)?] [$($head)* $($m)? $bind as] $($rest)+)
+    };
+
+    (@fixed $m:tt [$($head:tt)*] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@fixed_arm $m [$($head)*] [] $($arms)*)
+    };
+
+    (@fixed $m:tt [$($head:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@fixed $m [$($head)* $next] $($rest)+)
+    };
+
+    (@fixed $m:tt [$($head:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `", stringify!($($head)* $($rest)?), "`"))
+    };
+
+    // Start of an arm.
+    (@fixed_arm $m:tt $head:tt [$($acc:tt)*] #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_arm $m $head [$($acc)* #[$($attr)*]] $($rest)*)
+    };
+
+    (@fixed_arm $m:tt $head:tt [$($acc:tt)*] $sep:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_sep $sep; $m $head [$($acc)*] $($rest)*)
+    };
+
+    (@fixed_arm [$($m:ident)?] [$($head:tt)*] [$($acc:tt)*]) => {
+        $crate::typeswitch!($($head)* { $($acc)* })
+    };
+
+    (@fixed_sep ,; $m:tt $head:tt [$($acc:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_arm $m $head [$($acc)* ,] $($rest)*)
+    };
+
+    (@fixed_sep ;; $m:tt $head:tt [$($acc:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_arm $m $head [$($acc)* ;] $($rest)*)
+    };
+
+    (@fixed_sep ref; [mut] $head:tt $acc:tt mut $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_sep $bind; [mut] $head $acc : $($rest)*)
+    };
+
+    (@fixed_sep $kind:ident; $m:tt $head:tt $acc:tt $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_kind $kind; $m $head $acc $bind : $($rest)*)
+    };
+
+    (@fixed_sep $bind:ident; [$($m:ident)?] $head:tt [$($acc:tt)*] : $($ty:ty)|+ => $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_body [$($m)?] $head [$($acc)* $($m)? $bind : $($ty)|+ =>] $($rest)*)
+    };
+
+    (@fixed_sep $bind:ident; [$($m:ident)?] $head:tt [$($acc:tt)*] : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_pat [$($m)?] $head [$($acc)* $($m)? $bind :] $($rest)*)
+    };
+
+    // `_`, a tuple arm, or a type without a binding: passed on as it is.
+    (@fixed_sep $first:tt; $m:tt $head:tt [$($acc:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_pat $m $head [$($acc)* $first] $($rest)*)
+    };
+
+    // Spelling out the modifier the macro already applies is harmless.
+    (@fixed_kind mut; [mut] $head:tt $acc:tt $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_sep $bind; [mut] $head $acc : $($rest)*)
+    };
+
+    (@fixed_kind ref; [] $head:tt $acc:tt $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_sep $bind; [] $head $acc : $($rest)*)
+    };
+
+    (@fixed_kind move; [box] $head:tt $acc:tt $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_sep $bind; [box] $head $acc : $($rest)*)
+    };
+
+    // `replace` needs the same `&mut` access, so it goes on as it is.
+    (@fixed_kind replace; [mut] $head:tt [$($acc:tt)*] $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_pat [mut] $head [$($acc)* replace $bind :] $($rest)*)
+    };
+
+    (@fixed_kind $kind:ident; $m:tt $head:tt $acc:tt $($rest:tt)*) => {
+        compile_error!(concat!(
+            "typeswitch: the macro already fixes how arms borrow the subject, so `",
+            stringify!($kind), "` arms are not allowed"
+        ))
+    };
+
+    // The rest of the pattern, up to `=>`.
+    (@fixed_pat $m:tt $head:tt [$($acc:tt)*] => $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_body $m $head [$($acc)* =>] $($rest)*)
+    };
+
+    (@fixed_pat $m:tt $head:tt [$($acc:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_pat $m $head [$($acc)* $next] $($rest)*)
+    };
+
+    (@fixed_pat $m:tt [$($head:tt)*] [$($acc:tt)*]) => {
+        $crate::typeswitch!($($head)* { $($acc)* })
+    };
+
+    // The body: a block, or an expression up to the next `,`. A parsed
+    // expression can't be taken apart again, so it goes on as a block.
+    (@fixed_body $m:tt $head:tt $acc:tt switch { $($inner:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: `switch` bodies can't be used when the macro fixes how arms borrow the subject")
+    };
+
+    (@fixed_body $m:tt $head:tt [$($acc:tt)*] { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@fixed_arm $m $head [$($acc)* { $($body)* }] $($rest)*)
+    };
+
+    (@fixed_body $m:tt $head:tt [$($acc:tt)*] $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@fixed_arm $m $head [$($acc)* { $e }] $($($rest)*)?)
+    };
+
+    (@fixed_body $m:tt [$($head:tt)*] [$($acc:tt)*]) => {
+        $crate::typeswitch!($($head)* { $($acc)* })
+    };
+
+    // ============================================================
+    // INDEXED SWITCHES (`indexed x { ... }`)
+    // Every arm except the default starts by storing its position in
+    // `$index`, which starts out as the number of such arms. The arms
+    // are counted as a list of `()`, so the count stays a plain length.
+    // The rewritten switch is handed to `typeswitch!` itself.
+    // ============================================================
+    (@indexed $index:ident [$($head:tt)*] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@indexed_arm $index [$($head)*] [] [] $($arms)*)
+    };
+
+    (@indexed $index:ident [$($head:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@indexed $index [$($head)* $next] $($rest)+)
+    };
+
+    (@indexed $index:ident [$($head:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `indexed ", stringify!($($head)* $($rest)?), "`"))
+    };
+
+    // Start of an arm.
+    (@indexed_arm $index:ident $head:tt $n:tt [$($acc:tt)*] #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_arm $index $head $n [$($acc)* #[$($attr)*]] $($rest)*)
+    };
+
+    (@indexed_arm $index:ident $head:tt $n:tt [$($acc:tt)*] , $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_arm $index $head $n [$($acc)* ,] $($rest)*)
+    };
+
+    (@indexed_arm $index:ident $head:tt $n:tt [$($acc:tt)*] ; $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_arm $index $head $n [$($acc)* ;] $($rest)*)
+    };
+
+    (@indexed_arm $index:ident $head:tt $n:tt [$($acc:tt)*] _ $(@ $name:ident)? => $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_body $index $head $n [$($acc)* _ $(@ $name)? =>] [] $($rest)*)
+    };
+
+    // `box _ as rest`, `arc _ as rest` and `rc _ as rest`.
+    (@indexed_arm $index:ident $head:tt $n:tt [$($acc:tt)*] $kind:ident _ as $name:ident => $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_body $index $head $n [$($acc)* $kind _ as $name =>] [] $($rest)*)
+    };
+
+    (@indexed_arm $index:ident $head:tt $n:tt $acc:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@indexed_pat $index $head $n $acc $($rest)+)
+    };
+
+    (@indexed_arm $index:ident [$($head:tt)*] [$($n:tt)*] [$($acc:tt)*]) => {{
+        #[allow(unused_assignments)]
+        let mut $index: usize = <[()]>::len(&[$($n),*]);
+        let value = $crate::typeswitch!($($head)* { $($acc)* });
+        (value, $index)
+    }};
+
+    // The rest of the pattern, up to `=>`.
+    (@indexed_pat $index:ident $head:tt [$($n:tt)*] [$($acc:tt)*] => $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_body $index $head [$($n)* ()] [$($acc)* =>] [$index = <[()]>::len(&[$($n),*]);] $($rest)*)
+    };
+
+    (@indexed_pat $index:ident $head:tt $n:tt [$($acc:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_pat $index $head $n [$($acc)* $next] $($rest)*)
+    };
+
+    (@indexed_pat $index:ident [$($head:tt)*] $n:tt [$($acc:tt)*]) => {
+        $crate::typeswitch!($($head)* { $($acc)* })
+    };
+
+    // The body, which stores the index before anything else runs.
+    (@indexed_body $index:ident $head:tt $n:tt $acc:tt $set:tt switch { $($inner:tt)* } $($rest:tt)*) => {
+        compile_error!("typeswitch: `switch` bodies can't be used in indexed switches")
+    };
+
+    (@indexed_body $index:ident $head:tt $n:tt [$($acc:tt)*] [$($set:tt)*] { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_arm $index $head $n [$($acc)* { fallthrough; $($set)* $($body)* }] $($rest)*)
+    };
+
+    (@indexed_body $index:ident $head:tt $n:tt [$($acc:tt)*] [$($set:tt)*] { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@indexed_arm $index $head $n [$($acc)* { $($set)* $($body)* }] $($rest)*)
+    };
+
+    (@indexed_body $index:ident $head:tt $n:tt [$($acc:tt)*] [$($set:tt)*] $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@indexed_arm $index $head $n [$($acc)* { $($set)* $e }] $($($rest)*)?)
+    };
+
+    (@indexed_body $index:ident [$($head:tt)*] $n:tt [$($acc:tt)*] $set:tt) => {
+        $crate::typeswitch!($($head)* { $($acc)* })
+    };
+
+    // ============================================================
+    // SUBJECT TYPE IDS (`with_id x { ... }`)
+    // `@with_id [modes] [pre-binding] [subject] ...` sorts the head. The
+    // id is read before the switch runs, so `box` arms can still move the
+    // subject. An expression subject is evaluated once into `subject`.
+    // ============================================================
+    (@with_id [$($modes:tt)*] [] [] indexed $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id [$($modes)* indexed] [] [] $next $($rest)+)
+    };
+
+    (@with_id [$($modes:tt)*] [] [] exhaustive $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id [$($modes)* exhaustive] [] [] $next $($rest)+)
+    };
+
+    (@with_id [$($modes:tt)*] [] [] strict $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id [$($modes)* exhaustive] [] [] $next $($rest)+)
+    };
+
+    (@with_id [$($modes:tt)*] [] [] match $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id [$($modes)* match] [] [] $($rest)+)
+    };
+
+    (@with_id [$($modes:tt)*] [] [] try $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id [$($modes)* try] [] [] $($rest)+)
+    };
+
+    (@with_id $modes:tt [] [] $modifier:ident $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id $modes [$modifier $bind as] [] $($rest)+)
+    };
+
+    (@with_id $modes:tt [] [] $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id $modes [$bind as] [] $($rest)+)
+    };
+
+    (@with_id [$($modes:tt)*] [$($bind:tt)*] [$var:ident] $(-> $ret:ty)? { $($arms:tt)* }) => {
+        {
+            let id = ::core::any::Any::type_id($crate::__typeswitch!(@as_any $var));
+            ($crate::typeswitch!($($modes)* $($bind)* $var $(-> $ret)? { $($arms)* }), id)
+        }
+    };
+
+    (@with_id [$($modes:tt)*] [$($bind:tt)*] [$($subject:tt)+] $(-> $ret:ty)? { $($arms:tt)* }) => {
+        match $($subject)+ {
+            subject => $crate::__typeswitch!(@with_id [$($modes)*] [$($bind)*] [subject] $(-> $ret)? { $($arms)* }),
+        }
+    };
+
+    (@with_id $modes:tt $bind:tt [$($subject:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@with_id $modes $bind [$($subject)* $next] $($rest)+)
+    };
+
+    (@with_id $modes:tt $bind:tt [$($subject:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `", stringify!($($subject)* $($rest)?), "`"))
+    };
+
+    // ============================================================
+    // TYPE ID SWITCHES (`typeswitch_id!`)
+    // `@id_step id; [attrs] ...` turns each arm into an `@arm` that
+    // compares `id`. There is no value, so nothing is ever bound.
+    // ============================================================
+    (@id_subject [$($subject:tt)+] { $($arms:tt)* }) => {
+        match $($subject)+ {
+            id => {
+                let id: ::core::any::TypeId = id;
+                $crate::__typeswitch!(@id_step id; [] $($arms)*)
+            }
+        }
+    };
+
+    (@id_subject [$($subject:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@id_subject [$($subject)* $next] $($rest)*)
+    };
+
+    (@id_subject [$($subject:tt)*]) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after the type id `", stringify!($($subject)*), "`"))
+    };
+
+    (@id_step $id:ident; [$($attrs:tt)*] #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@id_step $id; [$($attrs)* #[$($attr)*]] $($rest)*)
+    };
+
+    (@id_step $id:ident; [$($attrs:tt)*] , $($rest:tt)*) => {
+        $crate::__typeswitch!(@id_step $id; [$($attrs)*] $($rest)*)
+    };
+
+    (@id_step $id:ident; [$($attrs:tt)*] ; $($rest:tt)*) => {
+        $crate::__typeswitch!(@id_step $id; [$($attrs)*] $($rest)*)
+    };
+
+    (@id_step $id:ident; [] _ => $block:block $(,)?) => { $block };
+
+    (@id_step $id:ident; [] _ => $block:block ;) => { $block };
+
+    (@id_step $id:ident; [] _ => $block:block $($rest:tt)+) => {
+        compile_error!(concat!(
+            "typeswitch: unreachable arm `", stringify!($($rest)+),
+            "`: the `_` default above already matches everything"
+        ))
+    };
+
+    // An attributed default may be configured out, so it goes on like any arm.
+    (@id_step $id:ident; [$(#[$attr:meta])+] _ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [] [$(#[$attr])+] [true] [] { $($body)* } {$crate::__typeswitch!(@id_step $id; [] $($rest)*)}}
+    };
+
+    (@id_step $id:ident; $attrs:tt _ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@id_step $id; $attrs _ => { $e } $($($rest)*)?)
+    };
+
+    (@id_step $id:ident; [$(#[$attr:meta])*] not ($($ty:ty)|+) => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [] [$(#[$attr])*]
+            [!($($id == ::core::any::TypeId::of::<$ty>())||+)]
+            []
+            { $($body)* }
+            {$crate::__typeswitch!(@id_step $id; [] $($rest)*)}
+        }
+    };
+
+    (@id_step $id:ident; $attrs:tt not ($($ty:ty)|+) => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@id_step $id; $attrs not ($($ty)|+) => { $e } $($($rest)*)?)
+    };
+
+    (@id_step $id:ident; $attrs:tt $kind:ident $bind:ident : $($rest:tt)*) => {
+        $crate::__typeswitch!(@id_step $id; $attrs $bind : $($rest)*)
+    };
+
+    (@id_step $id:ident; $attrs:tt $bind:ident : $($rest:tt)*) => {
+        compile_error!(concat!(
+            "typeswitch: `typeswitch_id!` has no value to bind, so `", stringify!($bind),
+            "` can't be bound; write the type alone"
+        ))
+    };
+
+    (@id_step $id:ident; [$(#[$attr:meta])*] $($ty:ty)|+ => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!{@arm [] [$(#[$attr])*]
+            [$($id == ::core::any::TypeId::of::<$ty>())||+]
+            [$crate::__typeswitch!{@arm_type $($ty)|+}]
+            { $($body)* }
+            {$crate::__typeswitch!(@id_step $id; [] $($rest)*)}
+        }
+    };
+
+    (@id_step $id:ident; $attrs:tt $($ty:ty)|+ => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@id_step $id; $attrs $($ty)|+ => { $e } $($($rest)*)?)
+    };
+
+    (@id_step $id:ident; []) => { () };
+
+    (@id_step $id:ident; [$($attrs:tt)+]) => {
+        compile_error!(concat!("typeswitch: attribute `", stringify!($($attrs)+), "` is not followed by an arm"))
+    };
+
+    (@id_step $id:ident; $attrs:tt $($rest:tt)+) => {
+        compile_error!(concat!("typeswitch: expected `Type => ...`, `not (...) => ...` or `_ => ...`, found `", stringify!($($rest)+), "`"))
+    };
+
+    // ============================================================
+    // ALL MODE (`all x`)
+    // Every arm is made to fall through, so each one whose condition holds
+    // runs, in order. `@all_arms [ctx] [[pattern] {body} ...] [pattern] ...`
+    // splits the arms at their `=>`, then the switch is rebuilt with the
+    // rest of the head, which may hold more modes. A final `_` arm runs if
+    // no arm did, tracked by a flag that the other arms set.
+    // ============================================================
+    (@all_head $modes:tt [$($head:tt)+] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@all_arms [$modes [$($head)+]] [] [] $($arms)*)
+    };
+
+    (@all_head $modes:tt [$($head:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@all_head $modes [$($head)* $next] $($rest)+)
+    };
+
+    (@all_head $modes:tt [$($head:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `all ", stringify!($($head)* $($rest)?), "`"))
+    };
+
+    (@all_arms [[$($mode:tt)*] [$($head:tt)+]] [$([$($pat:tt)+] { $($body:tt)* })*] [] _ => $default:block $(,)? $(;)?) => {{
+        let mut matched = false;
+        $crate::__typeswitch!(@entry [$($mode)*] $($head)+ {
+            $($($pat)+ => { fallthrough; matched = true; $($body)* })*
+        });
+        if !matched $default
+    }};
+
+    (@all_arms $ctx:tt $done:tt [] , $($rest:tt)*) => {
+        $crate::__typeswitch!(@all_arms $ctx $done [] $($rest)*)
+    };
+
+    (@all_arms $ctx:tt $done:tt [] ; $($rest:tt)*) => {
+        $crate::__typeswitch!(@all_arms $ctx $done [] $($rest)*)
+    };
+
+    (@all_arms $ctx:tt $done:tt [] _ => $default:expr $(,)?) => {
+        $crate::__typeswitch!(@all_arms $ctx $done [] _ => { $default })
+    };
+
+    (@all_arms $ctx:tt [$($done:tt)*] [$($pat:tt)+] => { fallthrough; $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@all_arms $ctx [$($done)* [$($pat)+] { $($body)* }] [] $($rest)*)
+    };
+
+    (@all_arms $ctx:tt [$($done:tt)*] [$($pat:tt)+] => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@all_arms $ctx [$($done)* [$($pat)+] { $($body)* }] [] $($rest)*)
+    };
+
+    (@all_arms $ctx:tt $done:tt [$($pat:tt)+] => switch $($rest:tt)*) => {
+        compile_error!(concat!("typeswitch: `", stringify!($($pat)+), " => switch` can't be used in an `all` switch, use a nested `typeswitch!` instead"))
+    };
+
+    (@all_arms $ctx:tt $done:tt [$($pat:tt)+] => $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@all_arms $ctx $done [$($pat)+] => { $e } $($($rest)*)?)
+    };
+
+    (@all_arms $ctx:tt [$($done:tt)*] [$($pat:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@all_arms $ctx [$($done)*] [$($pat)* $next] $($rest)*)
+    };
+
+    (@all_arms [[$($mode:tt)*] [$($head:tt)+]] [$([$($pat:tt)+] { $($body:tt)* })*] []) => {
+        $crate::__typeswitch!(@entry [$($mode)*] $($head)+ {
+            $($($pat)+ => { fallthrough; $($body)* })*
+        })
+    };
+
+    (@all_arms $ctx:tt $done:tt [$($pat:tt)+]) => {
+        compile_error!(concat!("typeswitch: expected `=>` after `", stringify!($($pat)+), "`"))
+    };
+
+    // ============================================================
+    // CELL SUBJECTS (`cell x`)
+    // `@cell [modes] ...` takes a `v as` / `mut v as` pre-binding off the
+    // front and collects the `RefCell` expression up to the arms. The
+    // pre-binding and the arm patterns, the tokens before each `=>` and those
+    // of nested `switch` arms, are then searched for `mut` and `replace`:
+    // with one of those the cell is borrowed mutably, and otherwise shared.
+    // Arm bodies are skipped, so a `&mut` in one doesn't count. The guard
+    // is a temporary of the subject expression, so it lives until the
+    // switch is done, like any other.
+    // ============================================================
+    (@cell $modes:tt $modifier:ident $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@cell_head $modes [$modifier $bind as] [] $($rest)+)
+    };
+
+    (@cell $modes:tt $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@cell_head $modes [$bind as] [] $($rest)+)
+    };
+
+    (@cell $modes:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@cell_head $modes [] [] $($rest)+)
+    };
+
+    (@cell_head $modes:tt [$($pre:tt)*] [$($cell:tt)+] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@cell_scan [$modes [$($pre)*] [$($cell)+] { $($arms)* }] $($pre)* $($arms)*)
+    };
+
+    (@cell_head $modes:tt $pre:tt [$($cell:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@cell_head $modes $pre [$($cell)* $next] $($rest)+)
+    };
+
+    (@cell_head $modes:tt $pre:tt [$($cell:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `cell ", stringify!($($cell)* $($rest)?), "`"))
+    };
+
+    (@cell_scan [[$($mode:tt)*] [$($pre:tt)*] [$($cell:tt)+] $arms:tt] mut $($rest:tt)*) => {
+        $crate::__typeswitch!(@entry [$($mode)*] $($pre)* &mut *::core::cell::RefCell::borrow_mut(&($($cell)+)) $arms)
+    };
+
+    (@cell_scan [[$($mode:tt)*] [$($pre:tt)*] [$($cell:tt)+] $arms:tt] replace $($rest:tt)*) => {
+        $crate::__typeswitch!(@entry [$($mode)*] $($pre)* &mut *::core::cell::RefCell::borrow_mut(&($($cell)+)) $arms)
+    };
+
+    // A nested switch's arms are scanned in place of its body.
+    (@cell_scan $ctx:tt => switch { $($inner:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@cell_scan $ctx $($inner)* , $($rest)*)
+    };
+
+    (@cell_scan $ctx:tt => { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@cell_scan $ctx $($rest)*)
+    };
+
+    (@cell_scan $ctx:tt => $($rest:tt)*) => {
+        $crate::__typeswitch!(@cell_scan_expr $ctx $($rest)*)
+    };
+
+    (@cell_scan $ctx:tt $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@cell_scan $ctx $($rest)*)
+    };
+
+    (@cell_scan [[$($mode:tt)*] [$($pre:tt)*] [$($cell:tt)+] $arms:tt]) => {
+        $crate::__typeswitch!(@entry [$($mode)*] $($pre)* &*::core::cell::RefCell::borrow(&($($cell)+)) $arms)
+    };
+
+    // An expression body runs up to the `,` that ends its arm.
+    (@cell_scan_expr $ctx:tt , $($rest:tt)*) => {
+        $crate::__typeswitch!(@cell_scan $ctx $($rest)*)
+    };
+
+    (@cell_scan_expr $ctx:tt $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@cell_scan_expr $ctx $($rest)*)
+    };
+
+    (@cell_scan_expr $ctx:tt) => {
+        $crate::__typeswitch!(@cell_scan $ctx)
+    };
+
+    // ============================================================
+    // FLOW SWITCHES (`flow x`)
+    // The arms evaluate to `ControlFlow`, and a switch without a default
+    // gets `_ => Continue(())`. Whether it has one is up to `@types`, which
+    // hands the `[flow [modes] [head] [arms]]` context back to `@types_out`.
+    // ============================================================
+    (@flow $modes:tt [$($head:tt)+] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@types [flow $modes [$($head)+] [$($arms)*]] [] $($arms)*)
+    };
+
+    (@flow $modes:tt [$($head:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@flow $modes [$($head)* $next] $($rest)+)
+    };
+
+    (@flow $modes:tt [$($head:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `flow ", stringify!($($head)* $($rest)?), "`"))
+    };
+
+    // ============================================================
+    // ARM TYPE LISTS (`@types NAME`, `typeswitch_set!`, `flow`)
+    // `@types $out [names] arms` walks the arms once more and keeps the
+    // types of each pattern: whatever follows the binding's `:`, up to `=>`,
+    // an `if` or a `where`, split at `|`. A guarded type is kept as
+    // `[if Type]`, and a default as `[_]`. Attributes are skipped, and
+    // `not (...)`, `_ as dyn Trait` and `none` arms name no type.
+    // `switch { ... }` bodies are walked too, since their types are
+    // handled by the same switch. `$out` says what the names are for:
+    // `[list]` for the `&[&str]` of `@types`, `[set ..]` for a set check.
+    // ============================================================
+    (@types $out:tt $names:tt) => {
+        $crate::__typeswitch!(@types_out $out [] $names)
+    };
+
+    (@types $out:tt $names:tt #[$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__typeswitch!(@types $out $names $($rest)*)
+    };
+
+    (@types $out:tt $names:tt , $($rest:tt)*) => {
+        $crate::__typeswitch!(@types $out $names $($rest)*)
+    };
+
+    (@types $out:tt $names:tt ; $($rest:tt)*) => {
+        $crate::__typeswitch!(@types $out $names $($rest)*)
+    };
+
+    (@types $out:tt $names:tt _ $(@ $name:ident)? as dyn $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out $names $($rest)*)
+    };
+
+    (@types $out:tt [$($names:tt)*] _ $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [_]] $($rest)*)
+    };
+
+    (@types $out:tt [$($names:tt)*] $kind:ident _ as $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [_]] $($rest)*)
+    };
+
+    (@types $out:tt [$($names:tt)*] $kind:ident default as $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [_]] $($rest)*)
+    };
+
+    (@types $out:tt [$($names:tt)*] default @ $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [_]] $($rest)*)
+    };
+
+    (@types $out:tt [$($names:tt)*] default => $body:block $(,)? $(;)?) => {
+        $crate::__typeswitch!(@types $out [$($names)* [_]])
+    };
+
+    (@types $out:tt [$($names:tt)*] default => $e:expr $(,)?) => {
+        $crate::__typeswitch!(@types $out [$($names)* [_]])
+    };
+
+    (@types $out:tt $names:tt not ($($ty:tt)*) $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out $names $($rest)*)
+    };
+
+    (@types $out:tt $names:tt none => $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_body $out $names $($rest)*)
+    };
+
+    (@types $out:tt $names:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@types_pat $out $names [] $($rest)+)
+    };
+
+    // The pattern, one token at a time: `[names] [current alternative]`.
+    // A trailing `| _` makes the arm a default.
+    (@types_pat $out:tt [$($names:tt)*] [_] => $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_body $out [$($names)* [_]] $($rest)*)
+    };
+
+    (@types_pat $out:tt [$($names:tt)*] [] (impl $($bound:tt)+) for [$($ty:ty),+ $(,)?] => $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_body $out [$($names)* $([$ty])+] $($rest)*)
+    };
+
+    (@types_pat $out:tt [$($names:tt)*] [] (impl $($bound:tt)+) for [$($ty:ty),+ $(,)?] $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* $([if $ty])+] $($rest)*)
+    };
+
+    (@types_pat $out:tt [$($names:tt)*] $ty:tt => $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_body $out [$($names)* $ty] $($rest)*)
+    };
+
+    (@types_pat $out:tt [$($names:tt)*] [$($ty:tt)*] if $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [if $($ty)*]] $($rest)*)
+    };
+
+    (@types_pat $out:tt [$($names:tt)*] [$($ty:tt)*] where $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [if $($ty)*]] $($rest)*)
+    };
+
+    // An extractor may turn any value down, so it counts like a guard.
+    (@types_pat $out:tt [$($names:tt)*] [$($ty:tt)*] via $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out [$($names)* [if $($ty)*]] $($rest)*)
+    };
+
+    // Everything before the `:` is the binding.
+    (@types_pat $out:tt $names:tt $ty:tt : $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_pat $out $names [] $($rest)*)
+    };
+
+    // The alternatives before the last share its guard, if it has one; the
+    // set check only needs the unguarded types to be exact.
+    (@types_pat $out:tt [$($names:tt)*] $ty:tt | $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_pat $out [$($names)* $ty] [] $($rest)*)
+    };
+
+    (@types_pat $out:tt $names:tt [$($ty:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_pat $out $names [$($ty)* $next] $($rest)*)
+    };
+
+    (@types_pat $out:tt $names:tt $ty:tt) => {
+        compile_error!("typeswitch: expected `=>` after the last arm's pattern")
+    };
+
+    // A guard or default pattern, up to its `=>`.
+    (@types_skip $out:tt $names:tt => $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_body $out $names $($rest)*)
+    };
+
+    (@types_skip $out:tt $names:tt $next:tt $($rest:tt)*) => {
+        $crate::__typeswitch!(@types_skip $out $names $($rest)*)
+    };
+
+    (@types_skip $out:tt $names:tt) => {
+        compile_error!("typeswitch: expected `=>` after the last arm's pattern")
+    };
+
+    // The body: a nested `switch`, a block, or an expression and its comma.
+    // A default inside a `switch` only covers its own arm, which is all
+    // `flow` wants to know, so it skips those arms.
+    (@types_body [flow $($ctx:tt)*] $names:tt switch { $($arms:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@types [flow $($ctx)*] $names $($rest)*)
+    };
+
+    (@types_body $out:tt $names:tt switch { $($arms:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@types $out $names $($arms)* , $($rest)*)
+    };
+
+    (@types_body $out:tt $names:tt { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__typeswitch!(@types $out $names $($rest)*)
+    };
+
+    (@types_body $out:tt $names:tt $e:expr $(, $($rest:tt)*)?) => {
+        $crate::__typeswitch!(@types $out $names $($($rest)*)?)
+    };
+
+    // `[list]`: every named type, guarded or not, as a `&[&str]`.
+    (@types_out [list] [$($kept:tt)*] [[_] $($names:tt)*]) => {
+        $crate::__typeswitch!(@types_out [list] [$($kept)*] [$($names)*])
+    };
+
+    (@types_out [list] [$($kept:tt)*] [[if $($ty:tt)*] $($names:tt)*]) => {
+        $crate::__typeswitch!(@types_out [list] [$($kept)* [$($ty)*]] [$($names)*])
+    };
+
+    (@types_out [list] [$($kept:tt)*] [$ty:tt $($names:tt)*]) => {
+        $crate::__typeswitch!(@types_out [list] [$($kept)* $ty] [$($names)*])
+    };
+
+    (@types_out [list] [$([$ty:ty])*] []) => {
+        &[$(::core::stringify!($ty)),*]
+    };
+
+    // `[set [members] [switch]]`: with a default nothing can be missed.
+    // Otherwise every member needs an unguarded arm of its own type.
+    (@types_out [set $members:tt [$($switch:tt)*]] $kept:tt [[_] $($names:tt)*]) => {
+        $crate::typeswitch!($($switch)*)
+    };
+
+    (@types_out [set $members:tt $switch:tt] $kept:tt [[if $($ty:tt)*] $($names:tt)*]) => {
+        $crate::__typeswitch!(@types_out [set $members $switch] $kept [$($names)*])
+    };
+
+    (@types_out [set $members:tt $switch:tt] [$($kept:tt)*] [$ty:tt $($names:tt)*]) => {
+        $crate::__typeswitch!(@types_out [set $members $switch] [$($kept)* $ty] [$($names)*])
+    };
+
+    (@types_out [set [$($member:ty),*] [$($switch:tt)*]] $kept:tt []) => {{
+        // Only type-checked: each member's `Found` must come out `Yes`.
+        let _ = || {
+            use $crate::__private::{DifferentType as _, Or as _, SameType as _};
+            $($crate::__typeswitch!(@set_member $member $kept);)*
+        };
+        $crate::__typeswitch!(@set_exhaustive $($switch)*)
+    }};
+
+    // `[flow [modes] [head] [arms]]`: the arms as written if one is a
+    // default, or with `Continue(())` as the default otherwise.
+    (@types_out [flow [$($mode:tt)*] [$($head:tt)+] [$($arms:tt)*]] $kept:tt [[_] $($names:tt)*]) => {
+        $crate::__typeswitch!(@entry [$($mode)*] $($head)+ { $($arms)* })
+    };
+
+    (@types_out [flow $($ctx:tt)*] $kept:tt [$ty:tt $($names:tt)*]) => {
+        $crate::__typeswitch!(@types_out [flow $($ctx)*] $kept [$($names)*])
+    };
+
+    (@types_out [flow [$($mode:tt)*] [$($head:tt)+] [$($arms:tt)*]] $kept:tt []) => {
+        $crate::__typeswitch!(@entry [$($mode)*] $($head)+ {
+            $($arms)* , _ => ::core::ops::ControlFlow::Continue(())
+        })
+    };
+
+    // Like a `match`, the switch has a value however it ends: a subject
+    // outside the set panics, or is handed back by `try`.
+    (@set_exhaustive try $($switch:tt)+) => {
+        $crate::typeswitch!(try $($switch)+)
+    };
+
+    (@set_exhaustive mut $var:ident { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@fixed [mut] [exhaustive] $var { $($arms)* })
+    };
+
+    (@set_exhaustive $($switch:tt)+) => {
+        $crate::typeswitch!(exhaustive $($switch)+)
+    };
+
+    // `typeswitch_set!`: the head, up to the arms.
+    (@set $members:tt [$($head:tt)*] { $($arms:tt)* }) => {
+        $crate::__typeswitch!(@types [set $members [$($head)* { $($arms)* }]] [] $($arms)*)
+    };
+
+    (@set $members:tt [$($head:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@set $members [$($head)* $next] $($rest)+)
+    };
+
+    (@set_member $member:ty [$([$($ty:tt)+])*]) => {
+        $crate::__private::require_in_set::<$member, _>(
+            $crate::__private::No $(.or((&$crate::__private::Pair::<$member, $($ty)+>(::core::marker::PhantomData)).same()))*
+        )
+    };
+
+    // ============================================================
+    // ITERATION (`typeswitch_each!`)
+    // `@each [head] [iterable] ...` takes an optional `v as` / `mut v as`
+    // pre-binding off the front, then collects the iterable up to the arms.
+    // Each item becomes the subject of a `typeswitch!` in a plain `for`
+    // loop, so `break` and `continue` in an arm act on that loop.
+    // ============================================================
+    (@each [] [] $modifier:ident $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@each [$modifier $bind as] [] $($rest)+)
+    };
+
+    (@each [] [] $bind:ident as $($rest:tt)+) => {
+        $crate::__typeswitch!(@each [$bind as] [] $($rest)+)
+    };
+
+    (@each [$($head:tt)*] [$($iter:tt)+] { $($arms:tt)* }) => {
+        for item in $($iter)+ {
+            $crate::typeswitch!($($head)* item { $($arms)* })
+        }
+    };
+
+    (@each $head:tt [$($iter:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::__typeswitch!(@each $head [$($iter)* $next] $($rest)+)
+    };
+
+    (@each $head:tt [$($iter:tt)*] $($rest:tt)?) => {
+        compile_error!(concat!("typeswitch: expected `{ ... }` arms after `", stringify!($($iter)* $($rest)?), "`"))
+    };
+
+    // ============================================================
+    // EXPRESSION SUBJECTS (Catch-all)
+    // Must stay last: anything no rule above claimed is an expression.
+    // The subject is evaluated once, before any arm is tried.
+    // ============================================================
+
+    // Internal rules that did not match must never be mistaken for a subject.
+    (@ $($rest:tt)*) => {
+        compile_error!(concat!("typeswitch: malformed input near `@", stringify!($($rest)*), "`"))
+    };
+
+    ($($rest:tt)+) => {
+        $crate::__typeswitch!(@entry [] $($rest)+)
+    };
+}
+
+/// Passes owned arms (`box`, `replace`, `arc`, `rc`, `rc_mut`, `weak`) through when `alloc` is enabled.
+#[cfg(feature = "alloc")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __typeswitch_alloc {
+    ($($arm:tt)*) => {
+        $($arm)*
+    };
+}
+
+/// Logs one type test of a switch to stderr, with the type as written, whether
+/// it held, and the subject's static type and value's `TypeId`.
+#[cfg(feature = "debug")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __typeswitch_trace {
+    ($var:expr, $id:expr, [$($ty:tt)+] $test:expr) => {
+        $crate::__private::trace(::core::stringify!($($ty)+), ::core::any::type_name_of_val(&$var), $id, $test)
+    };
+}
+
+/// Without the `debug` feature a type test is just the test.
+#[cfg(not(feature = "debug"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __typeswitch_trace {
+    ($var:expr, $id:expr, [$($ty:tt)+] $test:expr) => {
+        $test
+    };
+}
+
+/// Rejects owned arms: without `alloc` there is no `Box`, `Arc` or `Rc` to take apart.
+#[cfg(not(feature = "alloc"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __typeswitch_alloc {
+    ($($arm:tt)*) => {
+        compile_error!("typeswitch: `box`, `replace`, `arc`, `rc`, `rc_mut` and `weak` arms need the `alloc` feature")
+    };
+}
+
+/// Lets a trait object be seen as `dyn Any`, for switching on it with
+/// `typeswitch!(via_any x { ... })`.
+///
+/// Make it a supertrait of your own trait and every implementor gets it for
+/// free from the blanket impl:
+///
+/// ```rust
+/// use typeswitch::{typeswitch, AsAny};
+///
+/// trait Plugin: AsAny {}
+/// impl Plugin for i32 {}
+/// impl Plugin for String {}
+///
+/// let p: Box<dyn Plugin> = Box::new(String::from("hi"));
+/// let len = typeswitch!(via_any p {
+///     s: String => s.len(),
+///     _ => 0,
+/// });
+/// assert_eq!(len, 2);
+/// ```
+///
+/// Call these on the trait object itself (`(*boxed).as_any()`), not on a
+/// `Box` or `&` around it: the wrapper is `Any` too, and would be what you get.
+pub trait AsAny: Any {
+    /// `self` as `&dyn Any`.
+    fn as_any(&self) -> &dyn Any;
+    /// `self` as `&mut dyn Any`.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// The box as `Box<dyn Any>`, for `box` arms.
+    #[cfg(feature = "alloc")]
+    fn into_any(self: alloc::boxed::Box<Self>) -> alloc::boxed::Box<dyn Any>;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    #[cfg(feature = "alloc")]
+    fn into_any(self: alloc::boxed::Box<Self>) -> alloc::boxed::Box<dyn Any> {
+        self
+    }
+}
+
+/// The types that a `binding: dyn Trait` arm accepts, implemented for
+/// `dyn Trait` by [`register_dyn!`].
+///
+/// `Any` only knows a value's concrete type, so a trait object can only be
+/// recovered by trying each type that is known to implement the trait.
+pub trait DynRegistry {
+    /// The value as `&Self`, if its type was registered.
+    fn cast_ref(value: &dyn Any) -> Option<&Self>;
+    /// The value as `&mut Self`, if its type was registered.
+    fn cast_mut(value: &mut dyn Any) -> Option<&mut Self>;
+}
+
+/// Registers the types that `binding: dyn Trait` and `_ as dyn Trait` arms
+/// should recognize as implementing `Trait`.
+///
+/// Types are tried in the order they are listed, and a type that is not
+/// listed never matches, whatever it implements.
+///
+/// ```rust
+/// use typeswitch::{register_dyn, typeswitch};
+/// use std::any::Any;
+///
+/// trait Shape {
+///     fn area(&self) -> f64;
+/// }
+/// struct Square(f64);
+/// struct Circle(f64);
+/// impl Shape for Square {
+///     fn area(&self) -> f64 { self.0 * self.0 }
+/// }
+/// impl Shape for Circle {
+///     fn area(&self) -> f64 { 3.0 * self.0 * self.0 }
+/// }
+///
+/// register_dyn!(dyn Shape: Square, Circle);
+///
+/// let x: &dyn Any = &Circle(1.0);
+/// let area = typeswitch!(v as x {
+///     Square => 0.0,
+///     _ as dyn Shape => v.area(),
+///     _ => -1.0,
+/// });
+/// assert_eq!(area, 3.0);
+/// ```
+///
+/// # Type families
+///
+/// `trait Name: types` declares `Name` as a marker trait, implements it for
+/// every listed type and registers them. A `dyn Name` arm after the explicit ones
+/// then catches the rest of the family, and each family can have its own fallback:
+///
+/// ```rust
+/// use typeswitch::{register_dyn, typeswitch};
+/// use std::any::Any;
+///
+/// register_dyn!(trait Integer: i8, i16, i32, i64, u8, u16, u32, u64);
+/// register_dyn!(pub trait Float: f32, f64);
+///
+/// let kind = |x: &dyn Any| typeswitch!(x {
+///     i32 | i64 => "int",
+///     dyn Integer => "other int",
+///     dyn Float => "float",
+///     _ => "unknown",
+/// });
+/// assert_eq!(kind(&1i64), "int");
+/// assert_eq!(kind(&1u8), "other int");
+/// assert_eq!(kind(&1.0f32), "float");
+/// assert_eq!(kind(&"one"), "unknown");
+/// ```
+#[macro_export]
+macro_rules! register_dyn {
+    ($vis:vis trait $tr:ident : $($ty:ty),+ $(,)?) => {
+        $vis trait $tr {}
+        $(impl $tr for $ty {})+
+        $crate::register_dyn!(dyn $tr: $($ty),+);
+    };
+    (dyn $tr:path : $($ty:ty),+ $(,)?) => {
+        impl $crate::DynRegistry for dyn $tr {
+            fn cast_ref(value: &dyn ::core::any::Any) -> ::core::option::Option<&Self> {
+                $(
+                    if let ::core::option::Option::Some(value) = value.downcast_ref::<$ty>() {
+                        return ::core::option::Option::Some(value);
+                    }
+                )+
+                ::core::option::Option::None
+            }
+
+            fn cast_mut(value: &mut dyn ::core::any::Any) -> ::core::option::Option<&mut Self> {
+                $(
+                    if value.is::<$ty>() {
+                        return value.downcast_mut::<$ty>().map(|value| value as &mut Self);
+                    }
+                )+
+                ::core::option::Option::None
+            }
+        }
+    };
+}
+
+/// Handles a value of any of the types listed in [`typeswitch_visit!`] by shared
+/// reference, with one generic method instead of one arm per type.
+pub trait Visitor {
+    /// Called with the downcast value.
+    fn visit<T: Any>(&mut self, value: &T);
+}
+
+/// Like [`Visitor`], by mutable reference: `typeswitch_visit!(mut x, ..)`.
+pub trait VisitorMut {
+    /// Called with the downcast value.
+    fn visit_mut<T: Any>(&mut self, value: &mut T);
+}
+
+/// Like [`Visitor`], by value out of a box: `typeswitch_visit!(box x, ..)`.
+pub trait VisitorOwned {
+    /// Called with the value moved out of the box.
+    fn visit_owned<T: Any>(&mut self, value: T);
+}
+
+impl<V: Visitor + ?Sized> Visitor for &mut V {
+    fn visit<T: Any>(&mut self, value: &T) {
+        (**self).visit(value)
+    }
+}
+
+impl<V: VisitorMut + ?Sized> VisitorMut for &mut V {
+    fn visit_mut<T: Any>(&mut self, value: &mut T) {
+        (**self).visit_mut(value)
+    }
+}
+
+impl<V: VisitorOwned + ?Sized> VisitorOwned for &mut V {
+    fn visit_owned<T: Any>(&mut self, value: T) {
+        (**self).visit_owned(value)
+    }
+}
+
+/// Hands the subject to a generic [`Visitor`] if it holds one of the listed types.
+///
+/// `typeswitch_visit!(x, visitor, [A, B])` calls `visitor.visit::<A>(..)` or
+/// `visitor.visit::<B>(..)` and evaluates to `true`, or to `false` if the value
+/// is neither. Types are tried in order. The visitor is borrowed mutably, so pass
+/// a `mut` variable or a `&mut` reference.
+///
+/// - `mut x` uses a [`VisitorMut`] and also evaluates to a `bool`.
+/// - `box x` uses a [`VisitorOwned`], moving the value out of the box. It
+///   evaluates to `Ok(())`, or gives the box back as `Err` if no type matched.
+///
+/// ```rust
+/// # #[cfg(feature = "alloc")] {
+/// # use typeswitch::{typeswitch_visit, Visitor, VisitorOwned};
+/// # use std::any::Any;
+/// struct Sizes(Vec<usize>);
+///
+/// impl Visitor for Sizes {
+///     fn visit<T: Any>(&mut self, _: &T) {
+///         self.0.push(size_of::<T>());
+///     }
+/// }
+///
+/// let mut sizes = Sizes(Vec::new());
+/// for x in [&1u8 as &dyn Any, &1u64, &"str"] {
+///     typeswitch_visit!(x, sizes, [u8, u16, u32, u64]);
+/// }
+/// assert_eq!(sizes.0, [1, 8]);
+///
+/// struct Collect(Vec<String>);
+///
+/// impl VisitorOwned for Collect {
+///     fn visit_owned<T: Any>(&mut self, value: T) {
+///         self.0.push(std::any::type_name_of_val(&value).into());
+///     }
+/// }
+///
+/// let x: Box<dyn Any> = Box::new(String::from("moved"));
+/// assert!(typeswitch_visit!(box x, &mut Collect(Vec::new()), [i32, String]).is_ok());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! typeswitch_visit {
+    // A plain variable stays a variable, so the switch borrows it instead of
+    // moving it like an expression subject.
+    (mut $subject:ident, $($rest:tt)+) => {
+        $crate::typeswitch_visit!(@visit [$subject] mut, $($rest)+)
+    };
+
+    (mut $subject:expr, $($rest:tt)+) => {
+        $crate::typeswitch_visit!(@visit [$subject] mut, $($rest)+)
+    };
+
+    (box $subject:expr, $($rest:tt)+) => {
+        $crate::typeswitch_visit!(@visit [$subject] box, $($rest)+)
+    };
+
+    (@visit [$($subject:tt)+] mut, $visitor:expr, [$($ty:ty),+ $(,)?]) => {
+        match &mut $visitor {
+            visitor => $crate::typeswitch!($($subject)+ {
+                $(mut value: $ty => { $crate::VisitorMut::visit_mut(visitor, value); true })+
+                _ => false,
+            }),
+        }
+    };
+
+    (@visit [$($subject:tt)+] box, $visitor:expr, [$($ty:ty),+ $(,)?]) => {
+        match &mut $visitor {
+            visitor => $crate::typeswitch!(try $($subject)+ {
+                $(box value: $ty => $crate::VisitorOwned::visit_owned(visitor, value),)+
+            }),
+        }
+    };
+
+    (@visit [$($subject:tt)+] ref, $visitor:expr, [$($ty:ty),+ $(,)?]) => {
+        match &mut $visitor {
+            visitor => $crate::typeswitch!($($subject)+ {
+                $(value: $ty => { $crate::Visitor::visit(visitor, value); true })+
+                _ => false,
+            }),
+        }
+    };
+
+    ($subject:ident, $($rest:tt)+) => {
+        $crate::typeswitch_visit!(@visit [$subject] ref, $($rest)+)
+    };
+
+    ($subject:expr, $($rest:tt)+) => {
+        $crate::typeswitch_visit!(@visit [$subject] ref, $($rest)+)
+    };
+}
+
+/// The value as a `&T`, if it is one.
+///
+/// This is the check behind every borrowing arm, as a plain function for
+/// code that builds its own dispatch or generates calls to it.
+///
+/// ```rust
+/// # use std::any::Any;
+/// let x: &dyn Any = &5i32;
+/// assert_eq!(typeswitch::downcast_ref_any::<i32>(x), Some(&5));
+/// assert_eq!(typeswitch::downcast_ref_any::<u8>(x), None);
+/// ```
+#[inline]
+pub fn downcast_ref_any<T: Any>(value: &dyn Any) -> Option<&T> {
+    value.downcast_ref::<T>()
+}
+
+/// The value as a `&mut T`, if it is one. The check behind `mut` arms.
+///
+/// ```rust
+/// # use std::any::Any;
+/// let mut n = 5i32;
+/// if let Some(n) = typeswitch::downcast_mut_any::<i32>(&mut n) {
+///     *n += 1;
+/// }
+/// assert_eq!(n, 6);
+/// ```
+#[inline]
+pub fn downcast_mut_any<T: Any>(value: &mut dyn Any) -> Option<&mut T> {
+    value.downcast_mut::<T>()
+}
+
+/// The value moved out of its box, or the box back unchanged if it holds
+/// something else. The move behind `box` arms.
+///
+/// A `Box<dyn Any + Send>` coerces to the `Box<dyn Any>` this takes.
+///
+/// ```rust
+/// # use std::any::Any;
+/// let x: Box<dyn Any> = Box::new(String::from("hi"));
+/// let x = typeswitch::downcast_owned::<i32>(x).unwrap_err();
+/// assert_eq!(typeswitch::downcast_owned::<String>(x).unwrap(), "hi");
+/// ```
+#[cfg(feature = "alloc")]
+#[inline]
+pub fn downcast_owned<T: Any>(value: Box<dyn Any>) -> Result<T, Box<dyn Any>> {
+    value.downcast::<T>().map(|value| *value)
+}
+
+/// The error of [`downcast_or_bail!`] and its `_mut` and `_owned` forms: the
+/// subject did not hold the expected type.
+///
+/// A `dyn Any` only knows the `TypeId` of what it holds, not the name of its
+/// type, so that is what `found` has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeMismatch {
+    /// The name of the expected type, from [`type_name`](core::any::type_name).
+    pub expected: &'static str,
+    /// The type of the value that was found instead.
+    pub found: TypeId,
+}
+
+impl TypeMismatch {
+    fn new<T: Any>(found: &dyn Any) -> Self {
+        TypeMismatch { expected: core::any::type_name::<T>(), found: found.type_id() }
+    }
+}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected a value of type `{}`, found one of {:?}", self.expected, self.found)
+    }
+}
+
+impl core::error::Error for TypeMismatch {}
+
+/// What a `route` switch evaluates to: the value of the arm that matched, or
+/// the subject that no arm took, still boxed so it can be passed along.
+///
+/// It is the `Result` of a `try` switch with names that read better in a
+/// routing pipeline. `U` is the subject's type, a `Box<dyn Any>` unless the
+/// switch was on something else, such as a `Box<dyn Any + Send>`.
+///
+/// ```rust
+/// # use typeswitch::{typeswitch, Routed};
+/// # use std::any::Any;
+/// fn route(x: Box<dyn Any>) -> Routed<String> {
+///     typeswitch!(route x {
+///         box s: String => s,
+///         box n: i32 => n.to_string(),
+///     })
+/// }
+///
+/// assert_eq!(route(Box::new(5i32)).matched().as_deref(), Some("5"));
+/// let rest = route(Box::new(1.5f64)).unmatched().unwrap();
+/// assert_eq!(rest.downcast_ref::<f64>(), Some(&1.5));
+/// ```
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub enum Routed<R, U = Box<dyn Any>> {
+    /// An arm matched and evaluated to this.
+    Matched(R),
+    /// No arm matched; this is the subject, unchanged.
+    Unmatched(U),
+}
+
+#[cfg(feature = "alloc")]
+impl<R, U> Routed<R, U> {
+    /// The value of the arm that matched, if one did.
+    pub fn matched(self) -> Option<R> {
+        match self {
+            Routed::Matched(value) => Some(value),
+            Routed::Unmatched(_) => None,
+        }
+    }
+
+    /// The subject, if no arm matched it.
+    pub fn unmatched(self) -> Option<U> {
+        match self {
+            Routed::Matched(_) => None,
+            Routed::Unmatched(subject) => Some(subject),
+        }
+    }
+
+    /// Whether an arm matched.
+    pub fn is_matched(&self) -> bool {
+        matches!(self, Routed::Matched(_))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<R, U> From<Result<R, U>> for Routed<R, U> {
+    fn from(result: Result<R, U>) -> Self {
+        match result {
+            Ok(value) => Routed::Matched(value),
+            Err(subject) => Routed::Unmatched(subject),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<R, U> From<Routed<R, U>> for Result<R, U> {
+    fn from(routed: Routed<R, U>) -> Self {
+        match routed {
+            Routed::Matched(value) => Ok(value),
+            Routed::Unmatched(subject) => Err(subject),
+        }
+    }
+}
+
+/// Paths used by the expansion, so that it does not depend on what the caller has in scope.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "alloc")]
+    pub use alloc::rc::Rc;
+
+    use core::any::Any;
+    use core::error::Error;
+    use core::marker::PhantomData;
+    use core::ops::{Deref, DerefMut};
+
+    use crate::{AsAny, TypeMismatch};
+    #[cfg(feature = "alloc")]
+    pub use alloc::boxed::Box;
+
+    pub fn downcast_ref_or_mismatch<T: Any>(value: &dyn Any) -> Result<&T, TypeMismatch> {
+        crate::downcast_ref_any::<T>(value).ok_or_else(|| TypeMismatch::new::<T>(value))
+    }
+
+    pub fn downcast_mut_or_mismatch<T: Any>(value: &mut dyn Any) -> Result<&mut T, TypeMismatch> {
+        // `downcast_mut` would keep `value` borrowed in the `Err` branch too.
+        if value.is::<T>() {
+            // SAFETY: the value was just checked to be a `T`.
+            Ok(unsafe { unchecked_downcast_mut::<T>(value) })
+        } else {
+            Err(TypeMismatch::new::<T>(value))
+        }
+    }
+
+    /// The `T` behind a `&mut dyn Any` whose type has already been checked,
+    /// checked once more only in debug builds like `unchecked_downcast`.
+    ///
+    /// # Safety
+    ///
+    /// `value` must be a `T`.
+    #[track_caller]
+    pub unsafe fn unchecked_downcast_mut<T: Any>(value: &mut dyn Any) -> &mut T {
+        debug_assert!((*value).is::<T>(), "typeswitch: type check passed but downcast failed");
+        // SAFETY: the caller guarantees that the value is a `T`.
+        unsafe { &mut *(value as *mut dyn Any).cast::<T>() }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn downcast_owned_or_mismatch<T: Any>(value: Box<dyn Any>) -> Result<T, TypeMismatch> {
+        crate::downcast_owned::<T>(value).map_err(|value| TypeMismatch::new::<T>(&*value))
+    }
+
+    /// `Pair<A, B>::same()` is `Yes` when `A` and `B` are the same type and
+    /// `No` otherwise, chosen by autoref: `SameType` applies to the pair
+    /// itself, `DifferentType` only to a reference to it.
+    pub struct Pair<A: ?Sized, B: ?Sized>(pub PhantomData<fn(&A, &B)>);
+
+    pub struct Yes;
+    pub struct No;
+
+    pub trait SameType {
+        fn same(&self) -> Yes;
+    }
+
+    impl<T: ?Sized> SameType for Pair<T, T> {
+        fn same(&self) -> Yes {
+            Yes
+        }
+    }
+
+    pub trait DifferentType {
+        fn same(&self) -> No;
+    }
+
+    impl<A: ?Sized, B: ?Sized> DifferentType for &Pair<A, B> {
+        fn same(&self) -> No {
+            No
+        }
+    }
+
+    /// `Yes` as soon as one side is.
+    pub trait Or<B> {
+        type Output;
+        fn or(self, other: B) -> Self::Output;
+    }
+
+    impl<B> Or<B> for Yes {
+        type Output = Yes;
+        fn or(self, _: B) -> Yes {
+            Yes
+        }
+    }
+
+    impl Or<Yes> for No {
+        type Output = Yes;
+        fn or(self, _: Yes) -> Yes {
+            Yes
+        }
+    }
+
+    impl Or<No> for No {
+        type Output = No;
+        fn or(self, _: No) -> No {
+            No
+        }
+    }
+
+    #[diagnostic::on_unimplemented(
+        message = "`{M}` is in the set, but no arm handles it",
+        label = "missing an arm for `{M}`",
+        note = "add an arm for `{M}` without a guard, or a `_` default"
+    )]
+    pub trait InSet<M: ?Sized> {}
+
+    impl<M: ?Sized> InSet<M> for Yes {}
+
+    pub fn require_in_set<M: ?Sized, F: InSet<M>>(_: F) {}
+
+    /// The subject of a `via_any` switch: a pointer to something `AsAny`,
+    /// such as `&dyn Trait` or `Box<dyn Trait>`, that derefs to `dyn Any`.
+    pub struct ViaAny<P>(pub P);
+
+    impl<P> ViaAny<P> {
+        pub fn into_inner(self) -> P {
+            self.0
+        }
+    }
+
+    impl<P: Deref<Target: AsAny>> Deref for ViaAny<P> {
+        type Target = dyn Any;
+
+        fn deref(&self) -> &dyn Any {
+            AsAny::as_any(&*self.0)
+        }
+    }
+
+    impl<P: DerefMut<Target: AsAny>> DerefMut for ViaAny<P> {
+        fn deref_mut(&mut self) -> &mut dyn Any {
+            AsAny::as_any_mut(&mut *self.0)
+        }
+    }
+
+    /// The `dyn Any` types a subject can deref to.
+    pub trait DynAny {
+        fn as_dyn(&self) -> &dyn Any;
+        fn as_dyn_mut(&mut self) -> &mut dyn Any;
+    }
+
+    macro_rules! dyn_any {
+        ($($object:ty),+) => {$(
+            impl DynAny for $object {
+                fn as_dyn(&self) -> &dyn Any {
+                    self
+                }
+
+                fn as_dyn_mut(&mut self) -> &mut dyn Any {
+                    self
+                }
+            }
+        )+};
+    }
+
+    dyn_any!(dyn Any, dyn Any + Send, dyn Any + Sync, dyn Any + Send + Sync);
+
+    /// Picks how to see `*subject` as `dyn Any`, by autoref specialization:
+    /// `(&&&Probe(&*subject)).kind()` resolves to the first of these that applies.
+    ///
+    /// 1. [`Pointer`]: a pointer to `dyn Any`, for `&Box<dyn Any>` or `&mut Rc<dyn Any>` subjects.
+    /// 2. [`Direct`]: `dyn Any` itself, for `&dyn Any` or `Box<dyn Any>` subjects.
+    /// 3. [`Coerce`]: any other `'static` type, for a `&T` subject.
+    pub struct Probe<'a, S: ?Sized>(pub &'a S);
+
+    pub struct Pointer;
+    pub struct Direct;
+    pub struct Coerce;
+
+    pub trait PointerKind {
+        fn kind(&self) -> Pointer {
+            Pointer
+        }
+    }
+
+    impl<S: ?Sized + Deref<Target: DynAny>> PointerKind for &&Probe<'_, S> {}
+
+    pub trait DirectKind {
+        fn kind(&self) -> Direct {
+            Direct
+        }
+    }
+
+    impl<S: ?Sized + DynAny> DirectKind for &Probe<'_, S> {}
+
+    pub trait CoerceKind {
+        fn kind(&self) -> Coerce {
+            Coerce
+        }
+    }
+
+    impl<S: Any> CoerceKind for Probe<'_, S> {}
+
+    impl Pointer {
+        pub fn any_ref<S: ?Sized + Deref<Target: DynAny>>(self, subject: &S) -> &dyn Any {
+            (**subject).as_dyn()
+        }
+
+        pub fn any_mut<S: ?Sized + DerefMut<Target: DynAny>>(self, subject: &mut S) -> &mut dyn Any {
+            (**subject).as_dyn_mut()
+        }
+    }
+
+    impl Pointer {
+        #[cfg(feature = "alloc")]
+        pub fn into_box<S: IntoAnyBox>(self, subject: S) -> Box<dyn Any> {
+            subject.into_any_box()
+        }
+    }
+
+    /// The boxes that `into_any!` passes on instead of boxing again.
+    #[cfg(feature = "alloc")]
+    #[diagnostic::on_unimplemented(
+        message = "`into_any!` would box `{Self}`, which already points to a `dyn Any`",
+        note = "pass the value it points to, or a `Box<dyn Any>`"
+    )]
+    pub trait IntoAnyBox {
+        fn into_any_box(self) -> Box<dyn Any>;
+    }
+
+    #[cfg(feature = "alloc")]
+    impl IntoAnyBox for Box<dyn Any> {
+        fn into_any_box(self) -> Box<dyn Any> {
+            self
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl IntoAnyBox for Box<dyn Any + Send> {
+        fn into_any_box(self) -> Box<dyn Any> {
+            self
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl IntoAnyBox for Box<dyn Any + Sync> {
+        fn into_any_box(self) -> Box<dyn Any> {
+            self
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl IntoAnyBox for Box<dyn Any + Send + Sync> {
+        fn into_any_box(self) -> Box<dyn Any> {
+            self
+        }
+    }
+
+    impl Direct {
+        pub fn any_ref<S: ?Sized + DynAny>(self, subject: &S) -> &dyn Any {
+            subject.as_dyn()
+        }
+
+        pub fn any_mut<S: ?Sized + DynAny>(self, subject: &mut S) -> &mut dyn Any {
+            subject.as_dyn_mut()
+        }
+    }
+
+    impl Coerce {
+        pub fn any_ref<S: Any>(self, subject: &S) -> &dyn Any {
+            subject
+        }
+
+        pub fn any_mut<S: Any>(self, subject: &mut S) -> &mut dyn Any {
+            subject
+        }
+
+        #[cfg(feature = "alloc")]
+        pub fn into_box<S: Any>(self, subject: S) -> Box<dyn Any> {
+            Box::new(subject)
+        }
+    }
+
+    /// Moves the `T` out of a box whose type the arm has already checked.
+    ///
+    /// Debug builds check the type once more and panic at the arm if it is
+    /// wrong. Release builds trust the arm's check instead of repeating it.
+    ///
+    /// # Safety
+    ///
+    /// `value` must hold a `T`.
+    #[cfg(feature = "alloc")]
+    #[track_caller]
+    pub unsafe fn unchecked_downcast<T: Any>(value: Box<dyn Any>) -> T {
+        debug_assert!((*value).is::<T>(), "typeswitch: type check passed but downcast failed");
+        // SAFETY: the caller guarantees that the box holds a `T`, so the
+        // allocation has `T`'s layout.
+        unsafe { *Box::from_raw(Box::into_raw(value).cast::<T>()) }
+    }
+
+    /// A subject that `box` arms can move the value out of.
+    #[cfg(feature = "alloc")]
+    #[diagnostic::on_unimplemented(
+        message = "`box` arms need a subject that owns its value, but this one is `{Self}`",
+        label = "not a `Box<dyn Any>`",
+        note = "switch on the `Box<dyn Any>` itself, or use `binding: Type` / `mut binding: Type` arms to borrow"
+    )]
+    pub trait BoxSubject {
+        /// The box a `box _ as rest` default hands back.
+        type Boxed;
+
+        /// The value as a `T`. The arm has already checked the type.
+        fn take<T: Any>(self) -> T;
+
+        /// Like [`take`](Self::take), without checking the type again in
+        /// release builds.
+        ///
+        /// # Safety
+        ///
+        /// The value must be a `T`.
+        unsafe fn take_unchecked<T: Any>(self) -> T;
+
+        /// The box itself, which no arm has taken apart.
+        fn into_box(self) -> Self::Boxed;
+    }
+
+    #[cfg(feature = "alloc")]
+    macro_rules! box_subject {
+        ($($object:ty),+) => {$(
+            impl BoxSubject for Box<$object> {
+                type Boxed = Self;
+
+                #[track_caller]
+                fn take<T: Any>(self) -> T {
+                    crate::downcast_owned::<T>(self).expect("typeswitch: type check passed but downcast failed")
+                }
+
+                #[track_caller]
+                unsafe fn take_unchecked<T: Any>(self) -> T {
+                    // SAFETY: forwarded from the caller.
+                    unsafe { unchecked_downcast(self) }
+                }
+
+                fn into_box(self) -> Self {
+                    self
+                }
+            }
+        )+};
+    }
+
+    #[cfg(feature = "alloc")]
+    box_subject!(dyn Any, dyn Any + Send, dyn Any + Sync, dyn Any + Send + Sync);
+
+    #[cfg(feature = "alloc")]
+    impl<U: ?Sized + AsAny> BoxSubject for ViaAny<Box<U>> {
+        type Boxed = Box<U>;
+
+        #[track_caller]
+        fn take<T: Any>(self) -> T {
+            BoxSubject::take(AsAny::into_any(self.0))
+        }
+
+        /// The arm checked `as_any`, but the value comes from `into_any`, and
+        /// a safe `AsAny` impl may make the two disagree. So this checks again.
+        #[track_caller]
+        unsafe fn take_unchecked<T: Any>(self) -> T {
+            BoxSubject::take(AsAny::into_any(self.0))
+        }
+
+        fn into_box(self) -> Box<U> {
+            self.0
+        }
+    }
+
+    /// A shared pointer that `arc` and `rc` arms turn into a typed one.
+    #[cfg(feature = "alloc")]
+    #[diagnostic::on_unimplemented(
+        message = "`arc` and `rc` arms need an `Arc<dyn Any + Send + Sync>` or `Rc<dyn Any>` subject, but this one is `{Self}`",
+        label = "not a shared `dyn Any` pointer"
+    )]
+    pub trait SharedSubject {
+        /// The pointer to a `T`.
+        type Typed<T: Any>;
+
+        /// The pointer as a pointer to `T`, without checking the type again
+        /// in release builds.
+        ///
+        /// # Safety
+        ///
+        /// The value must be a `T`.
+        unsafe fn cast_unchecked<T: Any>(self) -> Self::Typed<T>;
+
+        /// The pointer itself, which no arm has cast.
+        fn into_shared(self) -> Self;
+    }
+
+    #[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+    impl SharedSubject for alloc::sync::Arc<dyn Any + Send + Sync> {
+        type Typed<T: Any> = alloc::sync::Arc<T>;
+
+        #[track_caller]
+        unsafe fn cast_unchecked<T: Any>(self) -> alloc::sync::Arc<T> {
+            debug_assert!((*self).is::<T>(), "typeswitch: type check passed but downcast failed");
+            // SAFETY: the caller guarantees that the value is a `T`, so the
+            // pointer is one that an `Arc<T>` would have handed out.
+            unsafe { alloc::sync::Arc::from_raw(alloc::sync::Arc::into_raw(self).cast::<T>()) }
+        }
+
+        fn into_shared(self) -> Self {
+            self
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl SharedSubject for Rc<dyn Any> {
+        type Typed<T: Any> = Rc<T>;
+
+        #[track_caller]
+        unsafe fn cast_unchecked<T: Any>(self) -> Rc<T> {
+            debug_assert!((*self).is::<T>(), "typeswitch: type check passed but downcast failed");
+            // SAFETY: as for `Arc` above.
+            unsafe { Rc::from_raw(Rc::into_raw(self).cast::<T>()) }
+        }
+
+        fn into_shared(self) -> Self {
+            self
+        }
+    }
+
+    /// A `Weak` subject as `weak` arms see it: the `Weak` itself, and what
+    /// upgrading it gave. It is upgraded once, by the first `weak` arm, and
+    /// handed on to the next arm until one of them takes the pointer.
+    #[cfg(feature = "alloc")]
+    pub struct WeakState<'a, W: ?Sized, S> {
+        /// The subject.
+        pub weak: &'a W,
+        /// The upgraded pointer, or `None` if the value was already dropped.
+        pub strong: Option<S>,
+    }
+
+    /// The arms after the `weak` ones still see the subject.
+    #[cfg(feature = "alloc")]
+    impl<W: ?Sized, S> Deref for WeakState<'_, W, S> {
+        type Target = W;
+
+        fn deref(&self) -> &W {
+            self.weak
+        }
+    }
+
+    /// A `Weak` that `weak` arms upgrade, or the state an earlier one left.
+    ///
+    /// Called as a method, so that a `Weak` subject is only borrowed while
+    /// a `WeakState` is moved on to the next arm.
+    #[cfg(feature = "alloc")]
+    pub trait WeakSubject<'a> {
+        /// The `Weak` type.
+        type Weak: ?Sized + 'a;
+
+        /// What it upgrades to.
+        type Strong;
+
+        /// The subject upgraded, unless an earlier arm did that already.
+        fn weak_state(self) -> WeakState<'a, Self::Weak, Self::Strong>;
+    }
+
+    /// A `Weak` subject, or the state of one, for `weak_dead` arms.
+    #[cfg(feature = "alloc")]
+    pub trait WeakDead {
+        /// Whether the value is gone.
+        fn weak_is_dead(&self) -> bool;
+    }
+
+    #[cfg(feature = "alloc")]
+    impl<'a, W: ?Sized, S> WeakSubject<'a> for WeakState<'a, W, S> {
+        type Weak = W;
+        type Strong = S;
+
+        fn weak_state(self) -> Self {
+            self
+        }
+    }
+
+    /// No arm took the pointer, so it is only missing if the upgrade failed.
+    #[cfg(feature = "alloc")]
+    impl<W: ?Sized, S> WeakDead for WeakState<'_, W, S> {
+        fn weak_is_dead(&self) -> bool {
+            self.strong.is_none()
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    macro_rules! weak_subject {
+        ($(#[$cfg:meta])* $weak:ty => $strong:ty, $object:ty) => {
+            $(#[$cfg])*
+            impl<'a> WeakSubject<'a> for &'a $weak {
+                type Weak = $weak;
+                type Strong = $strong;
+
+                fn weak_state(self) -> WeakState<'a, $weak, $strong> {
+                    WeakState { weak: self, strong: self.upgrade() }
+                }
+            }
+
+            $(#[$cfg])*
+            impl WeakDead for $weak {
+                fn weak_is_dead(&self) -> bool {
+                    self.strong_count() == 0
+                }
+            }
+
+            $(#[$cfg])*
+            impl<W: ?Sized> WeakState<'_, W, $strong> {
+                /// Whether the value is alive and a `T`.
+                pub fn is<T: Any>(&self) -> bool {
+                    self.strong.as_deref().is_some_and(<$object>::is::<T>)
+                }
+
+                /// The value as a `&T`, for a guard to look at.
+                pub fn peek<T: Any>(&self) -> Option<&T> {
+                    self.strong.as_deref().and_then(<$object>::downcast_ref::<T>)
+                }
+
+                /// The upgraded pointer as a pointer to `T`, without checking
+                /// the type again in release builds.
+                ///
+                /// # Safety
+                ///
+                /// The value must be alive and a `T`. Only the arm can reach
+                /// the state, so nothing changes it after its check.
+                #[track_caller]
+                pub unsafe fn take_unchecked<T: Any>(&mut self) -> <$strong as SharedSubject>::Typed<T> {
+                    // SAFETY: forwarded from the caller.
+                    unsafe { SharedSubject::cast_unchecked::<T>(self.strong.take().unwrap_unchecked()) }
+                }
+            }
+        };
+    }
+
+    #[cfg(feature = "alloc")]
+    weak_subject!(
+        #[cfg(target_has_atomic = "ptr")]
+        alloc::sync::Weak<dyn Any + Send + Sync> => alloc::sync::Arc<dyn Any + Send + Sync>, dyn Any + Send + Sync
+    );
+
+    #[cfg(feature = "alloc")]
+    weak_subject!(alloc::rc::Weak<dyn Any> => Rc<dyn Any>, dyn Any);
+
+    /// A box that a `replace` arm can refill: a `Box<dyn Any>` subject, or
+    /// through a `&mut Box<dyn Any>` one by autoderef.
+    #[cfg(feature = "alloc")]
+    pub trait BoxSlot {
+        /// Moves the value out as a `T`, leaving a `()` in its place.
+        /// The arm has already checked the type.
+        fn replace_take<T: Any>(&mut self) -> T;
+
+        /// Like [`replace_take`](Self::replace_take), without checking the
+        /// type again in release builds.
+        ///
+        /// # Safety
+        ///
+        /// The value must be a `T`.
+        unsafe fn replace_take_unchecked<T: Any>(&mut self) -> T;
+
+        /// The box, for the arm to put the new value in.
+        fn replace_slot(&mut self) -> &mut Self;
+    }
+
+    #[cfg(feature = "alloc")]
+    macro_rules! box_slot {
+        ($($object:ty),+) => {$(
+            impl BoxSlot for Box<$object> {
+                fn replace_take<T: Any>(&mut self) -> T {
+                    let old = core::mem::replace(self, Box::new(()));
+                    crate::downcast_owned::<T>(old).expect("typeswitch: type check passed but downcast failed")
+                }
+
+                #[track_caller]
+                unsafe fn replace_take_unchecked<T: Any>(&mut self) -> T {
+                    let old = core::mem::replace(self, Box::new(()));
+                    // SAFETY: forwarded from the caller.
+                    unsafe { unchecked_downcast(old) }
+                }
+
+                fn replace_slot(&mut self) -> &mut Self {
+                    self
+                }
+            }
+        )+};
+    }
+
+    #[cfg(feature = "alloc")]
+    box_slot!(dyn Any, dyn Any + Send, dyn Any + Sync, dyn Any + Send + Sync);
+
+    /// The outcome of a type test, for [`trace`].
+    #[cfg(feature = "debug")]
+    pub trait Matched {
+        fn matched(&self) -> bool;
+    }
+
+    #[cfg(feature = "debug")]
+    impl Matched for bool {
+        fn matched(&self) -> bool {
+            *self
+        }
+    }
+
+    #[cfg(feature = "debug")]
+    impl<T> Matched for Option<T> {
+        fn matched(&self) -> bool {
+            self.is_some()
+        }
+    }
+
+    /// Prints a type test of the `debug` feature, and hands its outcome back.
+    #[cfg(feature = "debug")]
+    pub fn trace<R: Matched>(ty: &str, subject: &str, id: Option<core::any::TypeId>, outcome: R) -> R {
+        let verdict = if outcome.matched() { "matched" } else { "no match" };
+        match id {
+            Some(id) => std::eprintln!("[typeswitch] `{ty}`: {verdict} (subject `{subject}`, value {id:?})"),
+            None => std::eprintln!("[typeswitch] `{ty}`: {verdict} (subject `{subject}`)"),
+        }
+        outcome
+    }
+
+    /// Calls the extractor of a `via` arm. Naming `T` checks that it
+    /// returns the arm's type.
+    pub fn extract_ref<'a, T: ?Sized>(f: impl FnOnce(&'a dyn Any) -> Option<&'a T>, value: &'a dyn Any) -> Option<&'a T> {
+        f(value)
+    }
+
+    /// The `mut` counterpart of [`extract_ref`].
+    pub fn extract_mut<'a, T: ?Sized>(
+        f: impl FnOnce(&'a mut dyn Any) -> Option<&'a mut T>,
+        value: &'a mut dyn Any,
+    ) -> Option<&'a mut T> {
+        f(value)
+    }
+
+    /// The first error in `err`'s `source()` chain, starting with `err` itself,
+    /// that is a `T`.
+    pub fn find_source<'a, T: Error + 'static>(err: &'a (dyn Error + 'static)) -> Option<&'a T> {
+        let mut err = err;
+        loop {
+            if let Some(found) = err.downcast_ref::<T>() {
+                return Some(found);
+            }
+            err = err.source()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+
+    #[cfg(feature = "proc-macro")]
+    use crate::typeswitch;
+
+    #[test]
+    fn test_standard_immutable() {
+        let x: &dyn Any = &42i32;
+        let result = typeswitch! { x {
+                val: i32 => { format!("int {}", val) }
+                val: String => { format!("string {}", val) }
+                _ => { "unknown".to_string() }
+            }
+        };
+        assert_eq!(result, "int 42");
+    }
+
+    #[test]
+    fn test_standard_mutable() {
+        let mut val = 10i32;
+        let x: &mut dyn Any = &mut val;
+
+        typeswitch! { x {
+                // #[cfg(feature = "d")]
+                mut v: i32 => { *v += 10; }
+                _ => {}
+            }
+        }
+
+        assert_eq!(val, 20);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_owned_box() {
+        let x: Box<dyn Any> = Box::new(String::from("Hello"));
+
+        let res = typeswitch! { x {
+                // Checks types but consumes box only if match
+                box s: String => { s }
+                _ => { String::new() }
+            }
+        };
+
+        assert_eq!(res, "Hello");
+    }
+
+    #[test]
+    fn test_pre_binding_syntax() {
+        let x: &dyn Any = &100i32;
+
+        let res = typeswitch! {
+            // Automatically binds 'v' to the downcasted type
+            v as x {
+                String => { v.len() }
+                i32 => { *v as usize }
+                _ => { 0 }
+            }
+        };
+
+        assert_eq!(res, 100);
+    }
+
+    #[test]
+    fn test_pre_binding_mutable_() {
+        let mut x: Box<dyn Any> = Box::new(String::from("Hello"));
+
+        let result = typeswitch! { mut v as x {
+                i32 => { format!("int {}", v) }
+                String => { format!("string {}", v) }
+                _ => { "unknown".to_string() }
+            }
+        };
+        assert_eq!(result, "string Hello");
+    }    
+
+    #[test]
+    fn test_expression_return() {
+        let x: &dyn Any = &1.5f64;
+        let val = typeswitch! { x {
+                f64 => { 1 }
+                _ => { 0 }
+            }
+        };
+        assert_eq!(val, 1);
+    }
+
+    #[test]
+    fn test_or_pattern() {
+        let x: Box<dyn Any> = Box::new(10i64);
+        
+        let res = typeswitch! { x {
+                f32 | f64 => { "float" }
+                i32 | i64 => { "int" }
+                _ => { "other" }
+            }
+        };
+        
+        assert_eq!(res, "int");
+    }
+
+    #[test]
+    fn test_or_pattern_head_type() {
+        // The first alternative must be checked against the boxed value, not the box.
+        let b: Box<dyn Any> = Box::new(10i32);
+        let res = typeswitch! { b {
+                i32 | i64 => { "int" }
+                _ => { "other" }
+            }
+        };
+        assert_eq!(res, "int");
+
+        let mut val = 1.5f32;
+        let m: &mut dyn Any = &mut val;
+        let res = typeswitch! { m {
+                f32 | f64 => { "float" }
+                _ => { "other" }
+            }
+        };
+        assert_eq!(res, "float");
+    }
+
+    #[test]
+    fn test_or_pattern_binding() {
+        let x: &dyn Any = &7i64;
+        let res = typeswitch! { x {
+                num: i32 | i64 => { num.downcast_ref::<i64>().copied() }
+                _ => { None }
+            }
+        };
+        assert_eq!(res, Some(7));
+
+        // The default still runs after an or-binding arm.
+        let s: &dyn Any = &"str";
+        let res = typeswitch!(v as s {
+            i32 | i64 => { v.is::<i32>() }
+            _ => { false }
+        });
+        assert!(!res);
+
+        let mut val = 1u16;
+        let m: &mut dyn Any = &mut val;
+        typeswitch! { m {
+                mut num: u8 | u16 => {
+                    if let Some(n) = num.downcast_mut::<u16>() { *n += 1; }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(val, 2);
+    }
+
+    #[test]
+    fn test_mut_or_pattern_fallthrough() {
+        // None of the alternatives match, so the later `mut` arm must be able to
+        // borrow the subject mutably again.
+        let mut x: Box<dyn Any> = Box::new(String::from("a"));
+        typeswitch! { x {
+                mut n: i32 | i64 => {
+                    if let Some(n) = n.downcast_mut::<i32>() { *n += 1; }
+                }
+                mut s: String => { s.push('b'); }
+                _ => {}
+            }
+        }
+        assert_eq!(x.downcast_ref::<String>().unwrap(), "ab");
+
+        let res = typeswitch!(mut v as x {
+            u8 | u16 => { v.is::<u8>() }
+            String => { v.push('c'); true }
+            _ => { false }
+        });
+        assert!(res);
+        assert_eq!(x.downcast_ref::<String>().unwrap(), "abc");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_box_or_pattern() {
+        let b: Box<dyn Any> = Box::new("borrowed");
+        let res = typeswitch! { b {
+                box s: String | &'static str => { s.to_string() }
+                _ => { String::new() }
+            }
+        };
+        assert_eq!(res, "borrowed");
+
+        // Nothing matched, so the box is still intact in the default arm.
+        let b: Box<dyn Any> = Box::new(5u8);
+        let res = typeswitch! { b {
+                box s: String | &'static str => { s.len() }
+                _ => { usize::from(*b.downcast::<u8>().unwrap()) }
+            }
+        };
+        assert_eq!(res, 5);
+    }
+
+    #[test]
+    fn test_guard_falls_through() {
+        let x: &dyn Any = &-5i32;
+        let res = typeswitch! { x {
+                n: i32 if *n > 0 => { "positive" }
+                n: i32 if *n < 0 => { "negative" }
+                i32 => { "zero" }
+                _ => { "other" }
+            }
+        };
+        assert_eq!(res, "negative");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_guard_mut_and_box() {
+        let mut val = 3i32;
+        let x: &mut dyn Any = &mut val;
+        typeswitch! { x {
+                mut n: i32 if *n > 10 => { *n = 0; }
+                mut n: i32 if *n > 1 => { *n *= 2; }
+                _ => {}
+            }
+        }
+        assert_eq!(val, 6);
+
+        // A failed guard must not consume the box.
+        let b: Box<dyn Any> = Box::new(String::from("hi"));
+        let res = typeswitch! { b {
+                box s: String if s.len() > 5 => { s }
+                box s: String => { s + "!" }
+                _ => { String::new() }
+            }
+        };
+        assert_eq!(res, "hi!");
+    }
+
+    #[test]
+    fn test_guard_pre_binding() {
+        let x: &dyn Any = &7u8;
+        let res = typeswitch!(v as x {
+            u8 if *v % 2 == 0 => { "even" }
+            u8 if *v > 0 => { "odd" }
+            _ => { "other" }
+        });
+        assert_eq!(res, "odd");
+
+        let flag = true;
+        let res = typeswitch! { x {
+                u8 if flag => { 1 }
+                _ => { 0 }
+            }
+        };
+        assert_eq!(res, 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_cfg_arms() {
+        let x: &dyn Any = &1i32;
+        let res = typeswitch! { x {
+                // The type doesn't exist, so this only compiles if the arm is really stripped.
+                #[cfg(any())]
+                v: DoesNotExist => { v.frobnicate() }
+                #[cfg(any())]
+                i32 => { "disabled" }
+                #[cfg(all())]
+                n: i32 | i64 => { if n.is::<i32>() { "enabled" } else { "i64" } }
+                _ => { "other" }
+            }
+        };
+        assert_eq!(res, "enabled");
+
+        let mut val = 1u8;
+        let m: &mut dyn Any = &mut val;
+        typeswitch! { m {
+                #[cfg(any())]
+                mut n: u8 => { *n = 10; }
+                #[cfg(all())]
+                #[allow(clippy::assign_op_pattern)]
+                mut n: u8 => { *n = *n + 1; }
+                _ => {}
+            }
+        }
+        assert_eq!(val, 2);
+
+        let b: Box<dyn Any> = Box::new(String::from("owned"));
+        let res = typeswitch!(v as b {
+            #[cfg(any())]
+            _ => { String::from("disabled default") }
+            #[cfg(any())]
+            u8 => { v.to_string() }
+            #[cfg(all())]
+            String if v.len() > 1 => { v.clone() }
+            _ => { String::new() }
+        });
+        assert_eq!(res, "owned");
+
+        let res = typeswitch! { b {
+                #[cfg(any())]
+                box s: String => { s }
+                _ => { String::from("kept") }
+            }
+        };
+        assert_eq!(res, "kept");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_try_returns_unmatched_box() {
+        let x: Box<dyn Any> = Box::new(3.5f64);
+        let res = typeswitch!(try x {
+            box s: String => { s }
+            box n: i32 => { n.to_string() }
+        });
+
+        // Nothing matched, so the box comes back and can be switched on again.
+        let x = res.unwrap_err();
+        let res = typeswitch!(try x {
+            box f: f64 => { f.to_string() }
+        });
+        assert_eq!(res.ok().as_deref(), Some("3.5"));
+
+        let x: &dyn Any = &1u8;
+        let res: Result<u8, _> = typeswitch!(try v as x {
+            u8 => { *v }
+        });
+        assert_eq!(res.ok(), Some(1));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_expression_subject() {
+        use std::cell::Cell;
+
+        let calls = Cell::new(0);
+        let make = || -> Box<dyn Any> {
+            calls.set(calls.get() + 1);
+            Box::new(String::from("made"))
+        };
+
+        let res = typeswitch! { make() {
+                i32 => { String::new() }
+                u8 | u16 => { String::new() }
+                box s: String => { s }
+                _ => { String::new() }
+            }
+        };
+        assert_eq!(res, "made");
+        assert_eq!(calls.get(), 1);
+
+        struct Holder {
+            field: Box<dyn Any>,
+        }
+        let mut holder = Holder { field: Box::new(2i32) };
+        typeswitch!(mut v as holder.field.as_mut() {
+            i32 => { *v += 1; }
+            _ => {}
+        });
+        let res = typeswitch!(v as &*holder.field {
+            i32 => { *v }
+            _ => { 0 }
+        });
+        assert_eq!(res, 3);
+
+        let res = typeswitch!(try make() {
+            box n: i32 => { n }
+        });
+        assert!(res.is_err());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_arc_subject() {
+        use std::sync::Arc;
+
+        #[derive(Debug, PartialEq)]
+        struct Config(u8);
+
+        let x: Arc<dyn Any + Send + Sync> = Arc::new(Config(7));
+        let other = Arc::clone(&x);
+
+        // Reference arms work through the Arc's deref.
+        let res = typeswitch! { x {
+                c: Config => { c.0 }
+                _ => { 0 }
+            }
+        };
+        assert_eq!(res, 7);
+
+        // A failed `arc` arm neither clones nor drops the Arc.
+        let res = typeswitch! { x {
+                arc s: String => { s.len() }
+                arc c: Config if c.0 > 100 => { c.0.into() }
+                _ => { Arc::strong_count(&x) }
+            }
+        };
+        assert_eq!(res, 2);
+
+        let res: Arc<Config> = typeswitch!(arc v as other {
+            Config => { v }
+            _ => { Arc::new(Config(0)) }
+        });
+        assert_eq!(*res, Config(7));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_rc_subject() {
+        use std::rc::Rc;
+
+        let x: Rc<dyn Any> = Rc::new(String::from("shared"));
+
+        // Reference arms only need a shared borrow of the Rc.
+        let len = typeswitch! { x {
+                s: String => { s.len() }
+                _ => { 0 }
+            }
+        };
+        assert_eq!(len, 6);
+
+        let res: Rc<String> = typeswitch! { x {
+                rc n: i32 => { Rc::new(n.to_string()) }
+                rc s: String => { s }
+                _ => { Rc::new(String::new()) }
+            }
+        };
+        assert_eq!(*res, "shared");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_rc_mut_needs_unique() {
+        use std::rc::Rc;
+
+        let mut x: Rc<dyn Any> = Rc::new(1i32);
+
+        typeswitch! { x {
+                rc_mut n: i32 => { *n += 1; }
+                _ => { panic!("unique Rc should be mutable") }
+            }
+        };
+
+        // With a second owner `get_mut` fails, so the arm falls through.
+        let other = Rc::clone(&x);
+        let res = typeswitch! { x {
+                rc_mut n: i32 => { *n += 1; "mutated" }
+                n: i32 if *n == 2 => { "shared" }
+                _ => { "unknown" }
+            }
+        };
+        assert_eq!(res, "shared");
+        drop(other);
+    }
+
+    #[test]
+    fn test_named_default() {
+        use std::any::TypeId;
+
+        let x: Box<dyn Any> = Box::new(1.5f64);
+
+        let id = typeswitch! { x {
+                i32 => { None }
+                _ @ other => { Some(other.type_id()) }
+            }
+        };
+        assert_eq!(id, Some(TypeId::of::<f64>()));
+
+        // Also with auto-binding: `other` can be switched on again.
+        let mut y: Box<dyn Any> = Box::new(7u8);
+        let res = typeswitch!(mut v as y {
+            i32 => { *v += 1; 0 }
+            _ @ other => {
+                typeswitch! { other {
+                    n: u8 => { u32::from(*n) }
+                    _ => { 0 }
+                }}
+            }
+        });
+        assert_eq!(res, 7);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_match_mode() {
+        let mut x: Box<dyn Any> = Box::new(5u16);
+
+        let res = typeswitch!(match x {
+            i32 | i64 => { 0 }
+            n: u16 if *n > 9 => { 1 }
+            n: u16 => { *n }
+            _ => { 2 }
+        });
+        assert_eq!(res, 5);
+
+        typeswitch!(match mut v as x {
+            u8 => { *v = 0; }
+            u16 => { *v += 1; }
+            _ => {}
+        });
+        assert_eq!(x.downcast_ref::<u16>(), Some(&6));
+
+        let res = typeswitch!(try match x {
+            box s: String => { s }
+        });
+        let x = res.unwrap_err();
+        let res = typeswitch!(try match x {
+            box n: u16 => { n }
+        });
+        assert_eq!(res.ok(), Some(6));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_expression_arms() {
+        let x: &dyn Any = &3i32;
+
+        // Mixed with block arms, with and without a binding or a guard.
+        let res = typeswitch!(v as x {
+            u8 | u16 => format!("small {}", v.is::<u8>()),
+            i32 if *v > 5 => format!("big {}", v),
+            String => { v.clone() }
+            i32 => format!("int {}", v),
+            _ => String::new()
+        });
+        assert_eq!(res, "int 3");
+
+        let res = typeswitch! { x {
+                n: u8 => u32::from(*n),
+                i64 | u64 => 1,
+                _ @ other => u32::from(other.is::<i32>()),
+            }
+        };
+        assert_eq!(res, 1);
+
+        let mut y: Box<dyn Any> = Box::new(String::from("a"));
+        typeswitch! { y {
+                mut s: String => s.push('b'),
+                _ => (),
+            }
+        };
+        let len = typeswitch! { y {
+                box n: i32 if *n > 0 => n as usize,
+                box s: String => s.len(),
+                _ => 0
+            }
+        };
+        assert_eq!(len, 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_control_flow_from_arms() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Tracked(Rc<Cell<u32>>);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        // `return` from a `box` arm drops the moved-out value exactly once.
+        fn early(x: Box<dyn Any>) -> u32 {
+            typeswitch! { x {
+                    box t: Tracked => { let _t = t; return 1; }
+                    _ => {}
+                }
+            };
+            0
+        }
+        let drops = Rc::new(Cell::new(0));
+        assert_eq!(early(Box::new(Tracked(drops.clone()))), 1);
+        assert_eq!(drops.get(), 1);
+
+        // A labeled `break` and `continue` reach the enclosing loops.
+        let items: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new("skip"), Box::new(2i32), Box::new(0u8), Box::new(3i32)];
+        let mut sum = 0;
+        'outer: for _ in 0..3 {
+            for item in &items {
+                typeswitch! { &**item {
+                        &'static str => { continue; }
+                        u8 => { break 'outer; }
+                        n: i32 => sum += n,
+                    }
+                };
+            }
+        }
+        assert_eq!(sum, 3);
+
+        // `?` propagates out of the enclosing fn.
+        fn parse(x: &dyn Any) -> Result<i32, std::num::ParseIntError> {
+            let n = typeswitch! { x {
+                    s: String => s.parse::<i32>()?,
+                    n: i32 => *n,
+                    _ => 0,
+                }
+            };
+            Ok(n * 2)
+        }
+        assert_eq!(parse(&String::from("21")), Ok(42));
+        assert!(parse(&String::from("x")).is_err());
+        assert_eq!(parse(&4i32), Ok(8));
+    }
+
+    #[test]
+    fn test_exhaustive_value() {
+        let x: &dyn Any = &2u8;
+        let n: u32 = typeswitch!(exhaustive x {
+            n: u8 => u32::from(*n),
+            n: u16 => u32::from(*n),
+        });
+        assert_eq!(n, 2);
+
+        let n: u32 = typeswitch!(exhaustive match v as x {
+            u8 => u32::from(*v),
+            u32 => *v,
+        });
+        assert_eq!(n, 2);
+
+        // A variable may still be called `exhaustive`.
+        let exhaustive = x;
+        let hit = typeswitch! { exhaustive {
+                u8 => true,
+                _ => false,
+            }
+        };
+        assert!(hit);
+    }
+
+    #[test]
+    #[should_panic(expected = "no arm matched")]
+    fn test_exhaustive_panics() {
+        let x: &dyn Any = &2i64;
+        let _: u32 = typeswitch!(exhaustive x {
+            n: u8 => u32::from(*n),
+        });
+    }
+
+    #[test]
+    fn test_err_source() {
+        use std::error::Error;
+        use std::fmt;
+
+        #[derive(Debug)]
+        struct Timeout(u32);
+        #[derive(Debug)]
+        struct Wrapped(Timeout);
+
+        impl fmt::Display for Timeout {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "timed out after {}s", self.0)
+            }
+        }
+        impl Error for Timeout {}
+        impl fmt::Display for Wrapped {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("request failed")
+            }
+        }
+        impl Error for Wrapped {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let err: Box<dyn Error + Send + Sync> = Box::new(Wrapped(Timeout(5)));
+
+        // The first arm whose type appears anywhere in the chain wins.
+        let res = typeswitch!(err_source err {
+            std::io::Error => 0,
+            t: Timeout if t.0 > 10 => 1,
+            t: Timeout => t.0,
+            _ @ other => other.to_string().len() as u32,
+        });
+        assert_eq!(res, 5);
+
+        let res = typeswitch!(err_source v as err {
+            Wrapped => v.to_string(),
+            _ => String::new(),
+        });
+        assert_eq!(res, "request failed");
+
+        let io: &(dyn Error + 'static) = &std::io::Error::other("boom");
+        let res = typeswitch!(try err_source io {
+            Timeout => {}
+        });
+        assert_eq!(res.unwrap_err().to_string(), "boom");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_opt_subject() {
+        let describe = |x: Option<Box<dyn Any>>| {
+            typeswitch!(opt x {
+                none => String::from("nothing"),
+                box s: String => s,
+                n: i32 => n.to_string(),
+                _ => String::from("other"),
+            })
+        };
+        assert_eq!(describe(None), "nothing");
+        assert_eq!(describe(Some(Box::new(String::from("text")))), "text");
+        assert_eq!(describe(Some(Box::new(4i32))), "4");
+        assert_eq!(describe(Some(Box::new(1.5f64))), "other");
+
+        // Without a `none` arm, `None` is just another miss.
+        let mut n = 1u8;
+        let x: Option<&mut dyn Any> = Some(&mut n);
+        typeswitch!(opt mut v as x {
+            u8 => *v += 1,
+        });
+        assert_eq!(n, 2);
+
+        let x: Option<&mut dyn Any> = None;
+        typeswitch!(opt mut v as x {
+            u8 => *v += 1,
+        });
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_try_opt_returns_unmatched_box() {
+        let x: Option<Box<dyn Any>> = Some(Box::new(2.5f64));
+        let res = typeswitch!(try opt x {
+            none => 0,
+            box n: i32 => n,
+        });
+        let back = res.unwrap_err().unwrap();
+        assert_eq!(back.downcast_ref::<f64>(), Some(&2.5));
+
+        let x: Option<Box<dyn Any>> = None;
+        let res = typeswitch!(try opt x {
+            box n: i32 => n,
+        });
+        assert!(matches!(res, Err(None)));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_box_auto_binding() {
+        let describe = |x: Box<dyn Any>| {
+            typeswitch!(box v as x {
+                String => v,
+                i32 | u8 => v.to_string(),
+                Vec<char> if v.is_empty() => String::from("empty"),
+                Vec<char> => v.into_iter().collect(),
+                _ => String::from("other"),
+            })
+        };
+        assert_eq!(describe(Box::new(String::from("owned"))), "owned");
+        assert_eq!(describe(Box::new(7u8)), "7");
+        assert_eq!(describe(Box::new(vec!['x'; 0])), "empty");
+        assert_eq!(describe(Box::new(vec!['o', 'k'])), "ok");
+        assert_eq!(describe(Box::new(1.5f64)), "other");
+
+        // With an expression subject the box is still moved out, not borrowed.
+        let make = || -> Box<dyn Any> { Box::new(String::from("made")) };
+        let s: String = typeswitch!(box v as make() {
+            String => v,
+            _ => String::new(),
+        });
+        assert_eq!(s, "made");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_auto_binding_default_value() {
+        let x: &dyn Any = &1.5f64;
+        assert_eq!(typeswitch!(v as x { i32 => *v, _ => -1 }), -1);
+        assert_eq!(typeswitch!(v as x { i32 => { *v } _ => { -2 } }), -2);
+        assert_eq!(typeswitch!(v as x { i32 => *v, _ @ o => i32::from(o.is::<f64>()) }), 1);
+        assert_eq!(typeswitch!(v as x { i32 => *v, #[cfg(all())] _ => -3 }), -3);
+        assert_eq!(typeswitch!(v as x { i32 => *v, #[cfg(any())] _ => -3, #[cfg(not(any()))] _ => -6 }), -6);
+
+        let mut y: Box<dyn Any> = Box::new(1.5f64);
+        assert_eq!(typeswitch!(mut v as y { i32 => { *v += 1; *v } _ => -4 }), -4);
+        assert_eq!(typeswitch!(box v as y { i32 => v, _ => -5 }), -5);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_str_and_string_are_distinct() {
+        let lit: Box<dyn Any> = Box::new("abc");
+        let owned: Box<dyn Any> = Box::new(String::from("abc"));
+        let kind = |x: &dyn Any| typeswitch!(x { String => "String", &str => "&str", _ => "other" });
+        assert_eq!(kind(&*lit), "&str");
+        assert_eq!(kind(&*owned), "String");
+
+        // A `str` slice can't be boxed as `dyn Any`, but a `&'static str` can be moved out.
+        let s = typeswitch!(box v as lit { String => v, &str => v.to_uppercase(), _ => String::new() });
+        assert_eq!(s, "ABC");
+    }
+
+    #[test]
+    fn test_fallthrough() {
+        let mut x: Box<dyn Any> = Box::new(3u8);
+        let mut log = Vec::new();
+        let res = typeswitch!(try x {
+            u8 | u16 => { fallthrough; log.push("small"); }
+            mut n: u8 => { fallthrough; *n += 1; }
+            #[cfg(any())]
+            u8 => { fallthrough; log.push("configured out"); }
+            n: u8 if *n > 10 => { log.push("big"); *n }
+            n: u8 => { log.push("u8"); *n }
+        });
+        assert_eq!(res.ok(), Some(4));
+        assert_eq!(log, ["small", "u8"]);
+
+        // The value flows on to the default when nothing below matches.
+        let y: &dyn Any = &"hi";
+        let n = typeswitch!(y { &str => { fallthrough; log.push("str"); } String => 1, _ => 2 });
+        assert_eq!(n, 2);
+        assert_eq!(log.last(), Some(&"str"));
+    }
+
+    #[test]
+    fn test_tuple_subjects() {
+        let mut a: Box<dyn Any> = Box::new(1i32);
+        let b: &dyn Any = &String::from("b");
+        let mut order = Vec::new();
+
+        let res = typeswitch!((a, b) {
+            (u8, _) => 0,
+            (x: i32, String) if { order.push("guard"); *x > 5 } => 1,
+            #[cfg(any())]
+            (_, _) => 2,
+            (mut x: i32, s: String) => { *x += s.len() as i32; 3 }
+            _ => 4,
+        });
+        assert_eq!(res, 3);
+        assert_eq!(order, ["guard"]);
+        assert_eq!(a.downcast_ref::<i32>(), Some(&2));
+
+        // Expression subjects are evaluated once, and `try` returns all of them.
+        let res = typeswitch!(try (Box::new(1u8) as Box<dyn Any>, b) { (u8, u8) => () });
+        let (first, second) = res.unwrap_err();
+        assert!(first.is::<u8>() && second.is::<String>());
+
+        // A single parenthesized subject is an ordinary switch.
+        assert!(typeswitch!((b) { String => true, _ => false }));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_fixed_access_macros() {
+        let mut x: Box<dyn Any> = Box::new(5i32);
+        typeswitch_mut!(x {
+            n: i32 if *n > 3 => *n *= 2,
+            #[cfg(all())]
+            s: String => { s.clear() }
+            b: u8 | u16 => { let _ = b; }
+            Vec<u8> => {}
+        });
+        typeswitch_mut!(v as x { i32 => *v += 1 });
+        assert_eq!(typeswitch_ref!(match x { n: i32 => *n, _ => 0 }), 11);
+        assert_eq!(typeswitch_ref!(v as x { i32 => *v, _ => 0 }), 11);
+
+        let owned = typeswitch_owned!(try x { s: String => s, n: i32 => n.to_string() });
+        assert_eq!(owned.ok().as_deref(), Some("11"));
+        let y: Box<dyn Any> = Box::new(2u8);
+        assert_eq!(typeswitch_owned!(v as y { u8 => u32::from(v), _ => 0 }), 2);
+    }
+
+    trait Named {
+        fn name(&self) -> String;
+        fn rename(&mut self, to: &str);
+    }
+
+    struct Dog(String);
+    struct Cat(String);
+
+    impl Named for Dog {
+        fn name(&self) -> String {
+            format!("dog {}", self.0)
+        }
+        fn rename(&mut self, to: &str) {
+            self.0 = to.into();
+        }
+    }
+
+    impl Named for Cat {
+        fn name(&self) -> String {
+            format!("cat {}", self.0)
+        }
+        fn rename(&mut self, to: &str) {
+            self.0 = to.into();
+        }
+    }
+
+    crate::register_dyn!(dyn Named: Dog, Cat);
+
+    #[test]
+    fn test_dyn_trait_arms() {
+        let mut x: Box<dyn Any> = Box::new(Cat("tom".into()));
+        typeswitch!(x { mut n: dyn Named if n.name().len() > 3 => n.rename("felix") });
+        assert_eq!(typeswitch!(x { Dog => String::new(), n: dyn Named => n.name(), _ => String::new() }), "cat felix");
+
+        let name = |x: &dyn Any| typeswitch!(v as x { _ as dyn Named => v.name(), i32 => format!("int {v}"), _ => "?".into() });
+        assert_eq!(name(&Dog("rex".into())), "dog rex");
+        assert_eq!(name(&1i32), "int 1");
+        // Implementing the trait is not enough, the type has to be registered.
+        assert_eq!(name(&1u8), "?");
+
+        let mut y: Box<dyn Any> = Box::new(Dog("rex".into()));
+        typeswitch!(mut v as y { _ as dyn Named => v.rename("max") });
+        assert!(typeswitch!(y { _ @ n as dyn Named => n.name() == "dog max", _ => false }));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_via_any() {
+        trait Plugin: crate::AsAny {}
+        impl Plugin for i32 {}
+        impl Plugin for String {}
+
+        let mut p: Box<dyn Plugin> = Box::new(5i32);
+        typeswitch!(via_any &mut *p { mut n: i32 => *n += 1 });
+        assert_eq!(typeswitch!(match via_any &*p { n: i32 => *n, _ => 0 }), 6);
+
+        // An unmatched subject comes back as it was given.
+        let p: Box<dyn Plugin> = typeswitch!(try via_any p { box s: String => s }).unwrap_err();
+        assert_eq!(typeswitch!(via_any box v as p { i32 => v, _ => 0 }), 6);
+
+        let o: Option<&dyn Plugin> = None;
+        assert_eq!(typeswitch!(opt via_any o { none => -1, n: i32 => *n, _ => 0 }), -1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    #[should_panic(expected = "type check passed but downcast failed")]
+    fn test_via_any_box_arm_with_lying_as_any() {
+        trait Liar: 'static {}
+        impl Liar for u8 {}
+
+        // `as_any` and `into_any` disagree on the type, which safe code may do.
+        impl crate::AsAny for dyn Liar {
+            fn as_any(&self) -> &dyn Any {
+                &[0u64; 4]
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn Any {
+                unreachable!()
+            }
+
+            fn into_any(self: Box<Self>) -> Box<dyn Any> {
+                Box::new(7u8)
+            }
+        }
+
+        let p: Box<dyn Liar> = Box::new(7u8);
+        typeswitch!(via_any p { box v: [u64; 4] => { panic!("read {v:?} from a u8") } _ => {} });
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_box_arms_on_send_sync_boxes() {
+        let x: Box<dyn Any + Send> = Box::new(String::from("send"));
+        assert_eq!(typeswitch!(x { box s: String => s, _ => String::new() }), "send");
+        let y: Box<dyn Any + Send + Sync> = Box::new(3u8);
+        assert_eq!(typeswitch!(box v as y { u8 => v, _ => 0 }), 3);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_destructuring_bindings() {
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        struct Meters(f64);
+
+        let v: &dyn Any = &Point { x: 3, y: 1 };
+        let res = typeswitch!(v {
+            Point { x, y }: Point if x < y => *y - *x,
+            Point { x: px, .. }: Point => *px,
+            Meters(m): Meters => *m as i32,
+            _ => 0,
+        });
+        assert_eq!(res, 3);
+
+        let mut m: Box<dyn Any> = Box::new([1u8, 2]);
+        typeswitch!(m {
+            mut [first, _]: [u8; 2] => { *first = 10; }
+            _ => {}
+        });
+        let sum = typeswitch!(m {
+            box [a, b]: [u8; 2] => a + b,
+            _ => 0,
+        });
+        assert_eq!(sum, 12);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_indexed() {
+        fn index_of(x: &dyn Any) -> (&'static str, usize) {
+            typeswitch!(indexed x {
+                i32 => "int",
+                #[allow(unused_variables)]
+                s: String => { "string" }
+                u8 | u16 => "small",
+                _ => "other",
+            })
+        }
+        assert_eq!(index_of(&1i32), ("int", 0));
+        assert_eq!(index_of(&String::new()), ("string", 1));
+        assert_eq!(index_of(&1u16), ("small", 2));
+        assert_eq!(index_of(&1.5f64), ("other", 3));
+
+        let v: Box<dyn Any> = Box::new(1.5f64);
+        let (res, index) = typeswitch!(indexed try v { box n: u8 => n });
+        assert!(res.is_err());
+        assert_eq!(index, 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_async_arms() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Waker};
+
+        fn block_on<F: Future>(fut: F) -> F::Output {
+            let mut fut = pin!(fut);
+            let mut cx = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                    return out;
+                }
+            }
+        }
+        fn assert_send<F: Future + Send>(fut: F) -> F {
+            fut
+        }
+
+        async fn double(n: usize) -> usize {
+            n * 2
+        }
+        async fn push(v: &mut Vec<usize>) {
+            v.push(1);
+        }
+
+        async fn handle(mut x: Box<dyn Any + Send>) -> usize {
+            // The `&mut Vec` binding is held across the `.await`.
+            let pushed = typeswitch!(match x {
+                mut v: Vec<usize> => { push(v).await; v.len() }
+                n: usize => double(*n).await,
+                _ => 0,
+            });
+            pushed + typeswitch!(x {
+                box s: String => double(s.len()).await,
+                _ => 0,
+            })
+        }
+
+        assert_eq!(block_on(assert_send(handle(Box::new(vec![1usize])))), 2);
+        assert_eq!(block_on(handle(Box::new(3usize))), 6);
+        assert_eq!(block_on(handle(Box::new(String::from("abc")))), 6);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_box_default_keeps_the_box() {
+        fn redispatch(x: Box<dyn Any + Send>) -> Result<i64, Box<dyn Any + Send>> {
+            typeswitch!(x {
+                box n: i32 => Ok(i64::from(n)),
+                box n: i64 => Ok(n),
+                box _ as rest => Err(rest),
+            })
+        }
+        assert_eq!(redispatch(Box::new(2i32)).ok(), Some(2));
+        let rest = redispatch(Box::new(String::from("left"))).unwrap_err();
+        assert_eq!(*rest.downcast::<String>().unwrap(), "left");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_complex_arm_types() {
+        use std::borrow::Cow;
+        use std::collections::HashMap;
+        use std::marker::PhantomData;
+
+        struct Buf<const N: usize>([u8; N]);
+
+        let x: &dyn Any = &[7u8; 32];
+        assert!(typeswitch!(x { [u8; 32] => true, _ => false }));
+        assert_eq!(typeswitch!(x { a: [u8; 32] if a[0] == 7 => a.len(), _ => 0 }), 32);
+        assert!(typeswitch!(x { Cow<'static, str> | [u8; 32] => true, _ => false }));
+        assert!(typeswitch!(match x { PhantomData<fn(u8) -> u16> => false, [u8; 32] => true, _ => false }));
+        assert_eq!(typeswitch!(v as x { HashMap<String, Vec<(u8, u16)>> => v.len(), [u8; 32] => v.len(), _ => 0 }), 32);
+
+        let b: &dyn Any = &Buf::<32>([0; 32]);
+        assert_eq!(typeswitch!(b { Result<u8, u8> | Buf<16> => 1, Buf<{ 4 * 8 }> => 2, _ => 0 }), 2);
+
+        let mut c: Box<dyn Any> = Box::new(Cow::<'static, str>::Borrowed("a"));
+        typeswitch!(c {
+            mut s: Cow<'static, str> if s.len() == 1 => { s.to_mut().push('b'); }
+            mut p: PhantomData<u8> | [u8; 32] => { let _ = p; }
+            _ => {}
+        });
+        typeswitch!(mut v as c {
+            Cow<'static, str> => { v.to_mut().push('c'); }
+            _ => {}
+        });
+        let s = typeswitch!(c {
+            box a: [u8; 32] | [u8; 16] => a.len().to_string(),
+            box s: Cow<'static, str> => s.into_owned(),
+            _ => String::new(),
+        });
+        assert_eq!(s, "abc");
+
+        let p: Box<dyn Any> = Box::new(PhantomData::<u8>);
+        assert!(typeswitch!(box v as p {
+            PhantomData<u16> => { let _: PhantomData<u16> = v; false }
+            PhantomData<u8> => { let _: PhantomData<u8> = v; true }
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_matches_type() {
+        let x: &dyn Any = &1i64;
+        assert!(matches_type!(x, i32 | i64 | f64));
+        assert!(!matches_type!(x, String));
+
+        let mut m: Box<dyn Any> = Box::new(String::from("s"));
+        assert!(matches_type!(m, String,));
+        let r: &mut dyn Any = &mut *m;
+        assert!(matches_type!(r, Vec<u8> | String));
+        assert!(matches_type!(m, String));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_auto_binding_hygiene() {
+        let v = "outer";
+        let x: &dyn Any = &5i32;
+        let y: &dyn Any = &1.5f64;
+
+        // Arms and guards see the downcast value, the default and the code
+        // after the switch see the outer `v`.
+        let describe = |subject: &dyn Any| typeswitch!(v as subject {
+            i32 => v.to_string(),
+            u8 if *v > 0 => v.to_string(),
+            _ => v.to_string(),
+        });
+        assert_eq!(describe(x), "5");
+        assert_eq!(describe(y), "outer");
+        assert_eq!(v, "outer");
+
+        // Variables named like the macro's own bindings are left alone.
+        let (subject, value, index, inner) = (1u8, 2u8, 3u8, 4u8);
+        let (sum, arm) = typeswitch!(indexed match (Box::new(3u8) as Box<dyn Any>) {
+            box n: u8 => n + subject + value + index + inner,
+            _ => 0,
+        });
+        assert_eq!((sum, arm), (13, 0));
+        let sum = typeswitch!(opt Some(x) {
+            none => 0,
+            n: i32 => n + i32::from(subject),
+            _ => 0,
+        });
+        assert_eq!(sum, 6);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_mut_box_subjects() {
+        struct Holder {
+            field: Box<dyn Any>,
+        }
+
+        fn update(slot: &mut Box<dyn Any>) -> &'static str {
+            typeswitch!(slot {
+                mut n: i32 => { *n += 1; "bumped" }
+                s: String if s.is_empty() => "empty",
+                _ => "other",
+            })
+        }
+        fn shrink(slot: &mut Box<dyn Any>) {
+            typeswitch!(slot {
+                replace s: String => s.len(),
+                replace n: i32 => i64::from(n),
+            });
+        }
+
+        let mut h = Holder { field: Box::new(1i32) };
+        assert_eq!(update(&mut h.field), "bumped");
+        assert_eq!(h.field.downcast_ref::<i32>(), Some(&2));
+        shrink(&mut h.field);
+        assert_eq!(h.field.downcast_ref::<i64>(), Some(&2));
+
+        h.field = Box::new(String::from("abc"));
+        shrink(&mut h.field);
+        assert_eq!(h.field.downcast_ref::<usize>(), Some(&3));
+        assert_eq!(update(&mut h.field), "other");
+
+        // A shared reference to a box sees the value too, not the box.
+        let boxes: Vec<Box<dyn Any>> = vec![Box::new(1u8), Box::new(String::new())];
+        let names: Vec<&str> = boxes.iter().map(|b| typeswitch!(b { u8 => "u8", String => "String", _ => "?" })).collect();
+        assert_eq!(names, ["u8", "String"]);
+        assert!(crate::matches_type!(&boxes[0], u8));
+
+        let mut b: Box<dyn Any + Send> = Box::new(5u8);
+        typeswitch!(b { replace n: u8 => u16::from(n) * 2 });
+        assert_eq!(b.downcast_ref::<u16>(), Some(&10));
+    }
+
+    #[cfg(all(feature = "proc-macro", feature = "alloc"))]
+    #[test]
+    fn test_cases_attribute() {
+        use crate::cases;
+
+        #[cases]
+        fn label(value: &dyn Any) -> &'static str {
+            match value {
+                i32(n) if *n > 10 => "big",
+                i32(_) => "int",
+                u8(_) | u16(_) => "unsigned",
+                String => "string",
+                #[cfg(any())]
+                f32 => "never",
+                _ => "other",
+            }
+        }
+
+        #[cases]
+        fn bump(value: &mut dyn Any) {
+            match value {
+                i32(n) => {
+                    *n += 1;
+                }
+                String(s) => s.push('!'),
+                _ => {}
+            }
+        }
+
+        #[cases]
+        fn take(value: Box<dyn Any>) -> Option<String> {
+            match value {
+                String(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        assert_eq!(label(&11i32), "big");
+        assert_eq!(label(&1i32), "int");
+        assert_eq!(label(&1u16), "unsigned");
+        assert_eq!(label(&String::new()), "string");
+        assert_eq!(label(&1.0f32), "other");
+
+        let mut n = 1i32;
+        bump(&mut n);
+        let mut s = String::from("hi");
+        bump(&mut s);
+        assert_eq!((n, s.as_str()), (2, "hi!"));
+
+        assert_eq!(take(Box::new(String::from("owned"))).as_deref(), Some("owned"));
+        assert_eq!(take(Box::new(1u8)), None);
+    }
+
+    #[cfg(feature = "proc-macro")]
+    #[test]
+    fn test_derive_type_dispatch() {
+        use crate::TypeDispatch;
+        use std::sync::Arc;
+
+        #[derive(TypeDispatch)]
+        #[allow(dead_code)]
+        enum Event {
+            Boxed(Box<dyn Any>),
+            Shared(u8, Arc<dyn Any + Send + Sync>),
+            Named {
+                id: u32,
+                /// Docs and visibility on the field are skipped.
+                payload: std::boxed::Box<dyn core::any::Any + Send>,
+            },
+            Plain(u32),
+            Empty,
+        }
+
+        let name = |event: &Event| {
+            event.dispatch(|v| {
+                typeswitch! { v {
+                    i32 => "i32",
+                    String => "String",
+                    () => "unit",
+                    _ => "other",
+                }}
+            })
+        };
+        assert_eq!(name(&Event::Boxed(Box::new(1i32))), "i32");
+        assert_eq!(name(&Event::Shared(0, Arc::new(String::new()))), "String");
+        assert_eq!(name(&Event::Named { id: 0, payload: Box::new(1.5f64) }), "other");
+        assert_eq!(name(&Event::Plain(3)), "unit");
+        assert_eq!(name(&Event::Empty), "unit");
+
+        #[derive(TypeDispatch)]
+        enum Only {
+            One(Box<dyn Any>),
+        }
+        assert!(Only::One(Box::new(2u8)).dispatch(|v| v.is::<u8>()));
+    }
+
+    #[test]
+    fn test_dyn_families() {
+        crate::register_dyn!(trait Int: i8, i16, i32, i64);
+        crate::register_dyn!(pub trait Float: f32, f64);
+
+        let kind = |x: &dyn Any| {
+            typeswitch!(x {
+                i32 => "i32",
+                dyn Int => "int",
+                dyn Float => "float",
+                _ => "other",
+            })
+        };
+        assert_eq!(kind(&1i32), "i32");
+        assert_eq!(kind(&1i8), "int");
+        assert_eq!(kind(&1i64), "int");
+        assert_eq!(kind(&1.0f64), "float");
+        assert_eq!(kind(&1u8), "other");
+
+        // With a pre-binding, the bare arm binds `&dyn Int` as usual.
+        let x: &dyn Any = &1i16;
+        assert!(typeswitch!(v as x { dyn Int => { let _: &dyn Int = v; true } _ => false }));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_each() {
+        let mut items: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new(2u8), Box::new(String::from("a")), Box::new(3i32)];
+
+        let mut ints = 0;
+        crate::typeswitch_each!(&items {
+            n: i32 => ints += n,
+            _ => {}
+        });
+        assert_eq!(ints, 4);
+
+        crate::typeswitch_each!(mut v as &mut items {
+            i32 => *v += 1,
+            String => v.push('!'),
+            _ => {}
+        });
+
+        // `continue` skips to the next item and `break` leaves the loop.
+        let mut seen = Vec::new();
+        crate::typeswitch_each!(&items {
+            u8 => continue,
+            s: String => { seen.push(s.clone()); break }
+            n: i32 => seen.push(n.to_string()),
+        });
+        assert_eq!(seen, ["2", "a!"]);
+
+        let mut owned = Vec::new();
+        crate::typeswitch_each!(items {
+            box s: String => owned.push(s),
+            box n: i32 => owned.push(n.to_string()),
+            _ => {}
+        });
+        assert_eq!(owned, ["2", "a!", "4"]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_with_id() {
+        use std::any::TypeId;
+
+        let x: &dyn Any = &1.5f64;
+        let (value, id) = typeswitch!(with_id x { i32 => 1, _ => 0 });
+        assert_eq!((value, id), (0, TypeId::of::<f64>()));
+
+        let mut y: Box<dyn Any> = Box::new(1i32);
+        let ((), id) = typeswitch!(with_id match mut v as y { i32 => *v += 1, _ => {} });
+        assert_eq!(id, TypeId::of::<i32>());
+        assert_eq!(y.downcast_ref::<i32>(), Some(&2));
+
+        // An expression subject is evaluated once, and the id is the boxed value's,
+        // not the box's.
+        let mut calls = 0;
+        let mut make = || {
+            calls += 1;
+            Box::new(String::from("a")) as Box<dyn Any>
+        };
+        let (s, id) = typeswitch!(with_id make() { box s: String => s, _ => String::new() });
+        assert_eq!((s.as_str(), id, calls), ("a", TypeId::of::<String>(), 1));
+
+        let z: Box<dyn Any> = Box::new(1u8);
+        let (result, id) = typeswitch!(with_id try z { box n: i32 => n });
+        assert!(result.is_err());
+        assert_eq!(id, TypeId::of::<u8>());
+    }
+
+    #[test]
+    fn test_not_arms() {
+        let kind = |x: &dyn Any| {
+            typeswitch!(x {
+                not (String | &'static str) if matches_type!(x, f32 | f64) => "float",
+                not (String | &'static str) => "not text",
+                String => "string",
+                _ => "str",
+            })
+        };
+        assert_eq!(kind(&1.5f64), "float");
+        assert_eq!(kind(&1i32), "not text");
+        assert_eq!(kind(&String::new()), "string");
+        assert_eq!(kind(&"a"), "str");
+
+        // A `not` arm ahead of a concrete arm takes that type first.
+        let mut x: Box<dyn Any> = Box::new(1i32);
+        let arm = typeswitch!(match x {
+            not (String) => 1,
+            mut n: i32 => { *n += 1; 2 }
+            _ => 3,
+        });
+        assert_eq!(arm, 1);
+        assert_eq!(x.downcast_ref::<i32>(), Some(&1));
+    }
+
+    #[test]
+    fn test_consecutive_mut_arms() {
+        // Each `downcast_mut` borrow ends before the next arm is tried, with or
+        // without a guard in between, and in both codegen modes.
+        fn bump(x: &mut dyn Any) -> &'static str {
+            typeswitch!(x {
+                mut n: i32 if *n < 0 => { *n = 0; "negative i32" }
+                mut n: i32 => { *n += 1; "i32" }
+                mut s: String => { s.push('!'); "String" }
+                mut v: Vec<u8> => { v.push(0); "Vec" }
+                _ => "other",
+            })
+        }
+        let (mut a, mut b, mut c, mut d) = (-1i32, 1i32, String::new(), Vec::<u8>::new());
+        assert_eq!(bump(&mut a), "negative i32");
+        assert_eq!(bump(&mut b), "i32");
+        assert_eq!(bump(&mut c), "String");
+        assert_eq!(bump(&mut d), "Vec");
+        assert_eq!((a, b, c.as_str(), d.len()), (0, 2, "!", 1));
+
+        let mut x: Box<dyn Any> = Box::new(1u8);
+        typeswitch!(match x {
+            mut n: i32 => *n += 1,
+            mut s: String => s.clear(),
+            mut n: u8 => *n += 1,
+        });
+        assert_eq!(x.downcast_ref::<u8>(), Some(&2));
+    }
+
+    #[test]
+    fn test_mut_subject() {
+        let mut x: Box<dyn Any> = Box::new(1i32);
+        typeswitch!(mut x {
+            n: i32 => *n += 1,
+            s: String => s.push('!'),
+        });
+        assert_eq!(x.downcast_ref::<i32>(), Some(&2));
+
+        // A redundant `mut` on an arm is accepted.
+        let y: &mut dyn Any = &mut *x;
+        let doubled = typeswitch!(mut y {
+            mut n: i32 => { *n *= 2; *n }
+            _ => 0,
+        });
+        assert_eq!(doubled, 4);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_generic_container_arms() {
+        use std::collections::HashMap;
+
+        let describe = |x: &dyn Any| {
+            typeswitch!(x {
+                v: Vec<i32> => format!("ints {v:?}"),
+                Vec<String> | Vec<&'static str> => String::from("strings"),
+                b: Box<[u8]> => format!("bytes {}", b.len()),
+                m: HashMap<String, i32> if m.contains_key("a") => String::from("map with a"),
+                HashMap<String, i32> | HashMap<i32, String> => String::from("map"),
+                _ => String::from("other"),
+            })
+        };
+        assert_eq!(describe(&vec![1, 2]), "ints [1, 2]");
+        assert_eq!(describe(&vec![String::new()]), "strings");
+        assert_eq!(describe(&vec!["a"]), "strings");
+        assert_eq!(describe(&Box::<[u8]>::from(&[1u8, 2, 3][..])), "bytes 3");
+        assert_eq!(describe(&HashMap::from([(String::from("a"), 1)])), "map with a");
+        assert_eq!(describe(&HashMap::<i32, String>::new()), "map");
+        assert_eq!(describe(&vec![1u8]), "other");
+
+        let take = |x: Box<dyn Any>| {
+            typeswitch!(box v as x {
+                Vec<i32> | Vec<u8> => v.len(),
+                Box<[u8]> => v.len() * 10,
+                HashMap<String, i32> => v.into_values().sum::<i32>() as usize,
+                _ => 0,
+            })
+        };
+        assert_eq!(take(Box::new(vec![1u8, 2])), 2);
+        assert_eq!(take(Box::new(Box::<[u8]>::from(&[0u8][..]))), 10);
+        assert_eq!(take(Box::new(HashMap::from([(String::from("a"), 4), (String::from("b"), 5)]))), 9);
+
+        let x: Box<dyn Any> = Box::new(vec![String::from("a")]);
+        assert_eq!(typeswitch!(x { box v: Vec<String> | Vec<Box<[u8]>> => v.len(), _ => 0 }), 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_visitors() {
+        use crate::{Visitor, VisitorMut, VisitorOwned};
+
+        #[derive(Default)]
+        struct Names(Vec<&'static str>);
+
+        impl Visitor for Names {
+            fn visit<T: Any>(&mut self, _: &T) {
+                self.0.push(std::any::type_name::<T>());
+            }
+        }
+
+        impl VisitorOwned for Names {
+            fn visit_owned<T: Any>(&mut self, value: T) {
+                self.visit(&value);
+            }
+        }
+
+        struct Reset;
+
+        impl VisitorMut for Reset {
+            fn visit_mut<T: Any>(&mut self, value: &mut T) {
+                if let Some(n) = <dyn Any>::downcast_mut::<i32>(value) {
+                    *n = 0;
+                }
+            }
+        }
+
+        let mut names = Names::default();
+        let x: &dyn Any = &1i32;
+        assert!(crate::typeswitch_visit!(x, names, [String, i32]));
+        assert!(!crate::typeswitch_visit!(x, &mut names, [String, u8,]));
+        assert_eq!(names.0, ["i32"]);
+
+        let mut y: Box<dyn Any> = Box::new(5i32);
+        assert!(crate::typeswitch_visit!(mut y, Reset, [u8, i32]));
+        assert_eq!(y.downcast_ref::<i32>(), Some(&0));
+
+        assert!(crate::typeswitch_visit!(box y, names, [i32]).is_ok());
+        let rest = crate::typeswitch_visit!(box Box::new(1u8) as Box<dyn Any>, names, [i32, String]);
+        assert!(rest.is_err_and(|b| b.is::<u8>()));
+        assert_eq!(names.0, ["i32", "i32"]);
+    }
+
+    #[test]
+    fn test_default_only_switch() {
+        // Empty braces are a compile error, but a lone default is a switch.
+        let x: &dyn Any = &1i32;
+        assert_eq!(typeswitch!(x { _ => 1 }), 1);
+        assert_eq!(typeswitch!(v as x { _ => 2 }), 2);
+        assert_eq!(typeswitch!((x, x) { _ => 3 }), 3);
+        assert!(x.is::<i32>());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_from_any() {
+        use std::sync::Arc;
+
+        let mut x: Box<dyn Any> = Box::new(1i32);
+        assert_eq!(crate::from_any!(x, i32), Some(&1));
+        assert_eq!(crate::from_any!(&*x, u8), None);
+        *crate::from_any_mut!(x, i32).unwrap() += 1;
+        assert!(crate::from_any_mut!(&mut *x, String).is_none());
+        assert_eq!(crate::from_any_owned!(x, i32), Some(2));
+
+        let shared: Arc<dyn Any + Send + Sync> = Arc::new(String::from("a"));
+        assert_eq!(crate::from_any!(shared, String).map(String::as_str), Some("a"));
+        let sent: Box<dyn Any + Send> = Box::new(1.5f64);
+        assert_eq!(crate::from_any_owned!(sent, f32), None);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_switch_bodies() {
+        crate::register_dyn!(trait Number: i32, u8, f64);
+
+        let kind = |x: &dyn Any| {
+            typeswitch!(x {
+                dyn Number => switch {
+                    i32 | u8 => "int",
+                    _ => "float",
+                }
+                not (String) => switch {
+                    &'static str => "str",
+                    _ => "other",
+                }
+                _ => "String",
+            })
+        };
+        assert_eq!(kind(&1u8), "int");
+        assert_eq!(kind(&1.5f64), "float");
+        assert_eq!(kind(&"a"), "str");
+        assert_eq!(kind(&()), "other");
+        assert_eq!(kind(&String::new()), "String");
+
+        // The inner arms keep the pre-binding and borrow the subject mutably again.
+        let mut x: Box<dyn Any> = Box::new(5i32);
+        typeswitch!(mut v as x {
+            i32 | u8 => switch {
+                i32 if *v > 3 => *v = 0,
+                i32 => *v += 1,
+                u8 => *v += 1,
+            }
+            _ => {}
+        });
+        assert_eq!(x.downcast_ref::<i32>(), Some(&0));
+
+        // With `try`, unmatched inner arms give the subject back like any miss.
+        let y: Box<dyn Any> = Box::new(1i32);
+        let result = typeswitch!(try y {
+            n: i32 | i64 => switch { box n: i64 => n }
+            box s: String => s.len() as i64,
+        });
+        assert!(result.is_err_and(|b| b.is::<i32>()));
+
+        let z: &dyn Any = &1u8;
+        assert!(typeswitch!(match v as z { _ as dyn Number => switch { u8 => *v == 1, _ => false } _ => false }));
+    }
+
+    #[test]
+    fn test_or_pattern_default() {
+        fn kind(x: &dyn Any) -> &'static str {
+            typeswitch! { x {
+                String => "text",
+                i32 | i64 => "int",
+                f32 | f64 | _ => "other",
+            }}
+        }
+        assert_eq!(kind(&String::new()), "text");
+        assert_eq!(kind(&1i64), "int");
+        assert_eq!(kind(&1.5f64), "other");
+        assert_eq!(kind(&'c'), "other");
+
+        // With a pre-binding the named alternatives still bind the subject,
+        // while the catch-all binds nothing, like `_`.
+        let x: &dyn Any = &7u8;
+        let seen = typeswitch! { v as x {
+            u8 | u16 => v.is::<u8>(),
+            bool | _ => { false }
+        }};
+        assert!(seen);
+
+        let mut boxed: Box<dyn Any> = Box::new(3u8);
+        let out = typeswitch! { mut v as boxed {
+            u8 => { *v += 1; *v }
+            u16 | _ => 0,
+        }};
+        assert_eq!(out, 4);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_explicit_borrow_forms() {
+        let mut boxed: Box<dyn Any> = Box::new(String::from("ab"));
+
+        let len = typeswitch! { boxed {
+            ref s: String => s.len(),
+            _ => 0,
+        }};
+        assert_eq!(len, 2);
+
+        typeswitch! { boxed {
+            ref mut s: String => s.push('c'),
+            _ => {}
+        }}
+
+        let owned = typeswitch! { boxed {
+            move s: String => s,
+            _ => String::new(),
+        }};
+        assert_eq!(owned, "abc");
+
+        // The fixed-access macros accept the form they already use.
+        let mut n = 1i32;
+        typeswitch_mut! { (&mut n as &mut dyn Any) {
+            ref mut n: i32 => *n += 1,
+            _ => {}
+        }}
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn test_strict_value() {
+        let x: &dyn Any = &7u8;
+        let n: u32 = typeswitch!(strict x {
+            n: u8 => u32::from(*n),
+            n: u16 => u32::from(*n),
+        });
+        assert_eq!(n, 7);
+
+        // A variable may still be called `strict`.
+        let strict = x;
+        assert!(typeswitch! { strict { u8 => true, _ => false } });
+    }
+
+    #[test]
+    fn test_strict_panics_with_type() {
+        fn wide<T: Any>(x: &T) -> u32 {
+            typeswitch!(strict x {
+                n: u8 => u32::from(*n),
+            })
+        }
+        let err = std::panic::catch_unwind(|| wide(&2i64)).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        // The message doesn't name `exhaustive`, which `strict` stands for.
+        assert!(msg.starts_with("typeswitch: no arm matched the subject's type: TypeId("), "{msg}");
+        assert!(msg.ends_with(" behind `&i64`"), "{msg}");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_send_sync_subjects() {
+        fn bump(mut x: Box<dyn Any + Send>) -> Result<u8, Box<dyn Any + Send>> {
+            let seen = typeswitch! { x { n: u8 => *n, _ => 0 } };
+            typeswitch! { x { mut n: u8 => *n += 1 } }
+            let matched = typeswitch! { match x { u8 => true, _ => false } };
+            assert_eq!(matched, seen == 5);
+            typeswitch! { try x { box n: u8 => n } }
+        }
+        assert_eq!(bump(Box::new(5u8)).ok(), Some(6));
+        assert!(bump(Box::new(1i32)).is_err());
+
+        let mut y: Box<dyn Any + Send + Sync> = Box::new(String::from("a"));
+        assert_eq!(typeswitch! { y { s: String => s.len(), _ => 0 } }, 1);
+        typeswitch!(mut v as y { String => v.push('b'), _ => {} });
+        assert_eq!(typeswitch! { y { box s: String => s, _ => String::new() } }, "ab");
+
+        // Borrowed trait objects with auto traits work the same way.
+        let mut n = 1i32;
+        let r: &(dyn Any + Send + Sync) = &n;
+        assert!(typeswitch! { r { i32 => true, _ => false } });
+        let m: &mut (dyn Any + Send) = &mut n;
+        typeswitch! { m { mut n: i32 => *n *= 3, _ => {} } }
+        assert_eq!(n, 3);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_box_arms_move_once() {
+        use std::rc::Rc;
+
+        // The value is moved out exactly once, whichever way it is checked.
+        let counter = Rc::new(());
+        let take = |x: Box<dyn Any>| typeswitch! { x { box r: Rc<()> => r, _ => Rc::new(()) } };
+        let back = take(Box::new(Rc::clone(&counter)));
+        assert_eq!(Rc::strong_count(&counter), 2);
+        drop(back);
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        let x: Box<dyn Any + Send> = Box::new(());
+        assert_eq!(typeswitch! { match x { box unit: () => unit, _ => panic!() } }, ());
+        let y: Box<dyn Any> = Box::new(vec![1u8, 2]);
+        assert_eq!(typeswitch! { match y { u8 => 0, box v: Vec<u8> => v.len(), _ => 9 } }, 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_shared_arms_keep_counts() {
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        let shared: Arc<dyn Any + Send + Sync> = Arc::new(String::from("a"));
+        let other = Arc::clone(&shared);
+        let typed = typeswitch! { shared { u8 => None, arc s: String => Some(s), _ => None } }.unwrap();
+        assert_eq!(Arc::strong_count(&typed), 2);
+        drop(other);
+        assert_eq!(Arc::strong_count(&typed), 1);
+
+        let local: Rc<dyn Any> = Rc::new(5u8);
+        let typed = typeswitch! { local { rc n: u8 => n, _ => Rc::new(0) } };
+        assert_eq!((*typed, Rc::strong_count(&typed)), (5, 1));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_default_keyword() {
+        let x: &dyn Any = &1.5f64;
+        assert_eq!(typeswitch! { x { i32 => 1, default => 0 } }, 0);
+        assert_eq!(typeswitch! { x { i32 => { 1 } default => { 0 } } }, 0);
+        let name = typeswitch! { x { i32 => None, default @ any => Some(any.is::<f64>()) } };
+        assert_eq!(name, Some(true));
+
+        let b: Box<dyn Any> = Box::new(2u8);
+        let back = typeswitch! { b { box s: String => s.len(), box default as rest => usize::from(*rest.downcast::<u8>().unwrap()) } };
+        assert_eq!(back, 2);
+
+        // Anywhere but the last arm, `default` is just a name.
+        #[allow(non_camel_case_types)]
+        type default = f64;
+        let hit = typeswitch! { x { default => "f64", _ => "other" } };
+        assert_eq!(hit, "f64");
+    }
+
+    #[test]
+    fn test_result_type() {
+        use std::fmt::Debug;
+
+        let x: &dyn Any = &1i32;
+        let s = typeswitch!(x -> String { i32 => { "int".into() } _ => String::new() });
+        assert_eq!(s, "int");
+
+        // Each arm is coerced to the declared type.
+        let shown = typeswitch!(v as x -> Box<dyn Debug> { i32 => Box::new(*v), _ => Box::new("other") });
+        assert_eq!(format!("{shown:?}"), "1");
+
+        let t = typeswitch!(try match x -> String { n: i32 => n.to_string() });
+        assert_eq!(t.ok().as_deref(), Some("1"));
+
+        let mut b: Box<dyn Any> = Box::new(2u8);
+        let n = typeswitch!(mut v as b -> u32 { u8 => { *v += 1; (*v).into() } _ => 0 });
+        assert_eq!(n, 3);
+        assert!(b.is::<u8>(), "a variable subject is still only borrowed");
+
+        let (id_value, id) = typeswitch!(with_id x -> &str { i32 => "int", _ => "other" });
+        assert_eq!((id_value, id), ("int", std::any::TypeId::of::<i32>()));
+        let (_, index) = typeswitch!(indexed x -> Option<&str> { u8 => None, i32 => Some("int"), _ => None });
+        assert_eq!(index, 1);
+    }
+
+    #[test]
+    fn test_typeswitch_id() {
+        use std::any::TypeId;
+
+        fn kind(id: TypeId) -> &'static str {
+            typeswitch_id!(id {
+                u8 | u16 => "small",
+                not (String | &str) => "not text",
+                String => { "owned" }
+                _ => "str",
+            })
+        }
+        assert_eq!(kind(TypeId::of::<u16>()), "small");
+        assert_eq!(kind(TypeId::of::<f64>()), "not text");
+        assert_eq!(kind(TypeId::of::<String>()), "owned");
+        assert_eq!(kind(TypeId::of::<&str>()), "str");
+
+        // The id may be any expression; it is evaluated once.
+        let values: Vec<Box<dyn Any>> = vec![Box::new(1i32), Box::new(2u8)];
+        let mut seen = Vec::new();
+        for v in &values {
+            typeswitch_id!((**v).type_id() {
+                i32 => { fallthrough; seen.push("int"); }
+                #[cfg(any())]
+                u8 => seen.push("never"),
+                i32 | u8 => seen.push("number"),
+            });
+        }
+        assert_eq!(seen, ["int", "number", "number"]);
+    }
+
+    #[test]
+    fn test_nested_auto_bindings() {
+        let x: &dyn Any = &1i32;
+        let y: &dyn Any = &String::from("ab");
+
+        let out = typeswitch! { v as x {
+            i32 => {
+                let outer = *v;
+                let inner = typeswitch! { v as y {
+                    String => v.len() as i32,
+                    _ => -1,
+                }};
+                // Back in the outer arm, `v` is the outer binding again.
+                outer * 10 + inner + *v
+            }
+            _ => 0,
+        }};
+        assert_eq!(out, 13);
+
+        // The inner default and base case don't see the outer `v` as theirs.
+        let z: &dyn Any = &1.5f64;
+        let out = typeswitch! { v as x {
+            i32 => typeswitch! { v as z { String => v.len() as i32, _ => -1 } } + *v,
+            _ => 0,
+        }};
+        assert_eq!(out, 0);
+
+        let mut a: Box<dyn Any> = Box::new(5u8);
+        let mut b: Box<dyn Any> = Box::new(7u16);
+        typeswitch! { mut v as a {
+            u8 => {
+                *v += 1;
+                typeswitch! { mut v as b { u16 => *v += 1, _ => {} } }
+            }
+            _ => {}
+        }}
+        assert_eq!(b.downcast_ref::<u16>(), Some(&8));
+        assert_eq!(a.downcast_ref::<u8>(), Some(&6));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_predicate_arms() {
+        fn is_small(n: &i32) -> bool {
+            *n < 10
+        }
+
+        let classify = |x: &dyn Any| {
+            typeswitch! { x {
+                i32 where is_small => "small",
+                n: i32 where |n: &i32| *n > 100 => { assert!(*n > 100); "big" }
+                String where |s: &String| s.is_empty() => "empty",
+                _ => "other",
+            }}
+        };
+        assert_eq!(classify(&5i32), "small");
+        assert_eq!(classify(&500i32), "big");
+        assert_eq!(classify(&50i32), "other");
+        assert_eq!(classify(&String::new()), "empty");
+
+        let mut n = 5i32;
+        let x: &mut dyn Any = &mut n;
+        typeswitch! { x {
+            mut v: i32 where is_small => { *v += 1 }
+            _ => {}
+        }}
+        assert_eq!(n, 6);
+
+        let boxed: Box<dyn Any> = Box::new(7i32);
+        let got = typeswitch! { boxed {
+            box v: i32 where is_small => v,
+            _ => 0,
+        }};
+        assert_eq!(got, 7);
 
-    use super::*;
+        let x: &dyn Any = &3i32;
+        let got = typeswitch! { v as x {
+            i32 where is_small => *v,
+            _ => 0,
+        }};
+        assert_eq!(got, 3);
+    }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_standard_immutable() {
-        let x: &dyn Any = &42i32;
-        let result = typeswitch! { x {
-                val: i32 => { format!("int {}", val) }
-                val: String => { format!("string {}", val) }
-                _ => { "unknown".to_string() }
+    fn test_shared_default_keeps_pointer() {
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        let x: Arc<dyn Any + Send + Sync> = Arc::new(1.5f64);
+        let keep = Arc::clone(&x);
+        let res = typeswitch! { x {
+            arc s: String => Ok(s),
+            arc _ as rest => Err(rest),
+        }};
+        let rest = res.unwrap_err();
+        assert!(Arc::ptr_eq(&rest, &keep));
+        assert_eq!(Arc::strong_count(&keep), 2);
+
+        let x: Rc<dyn Any> = Rc::new(7u8);
+        let keep = Rc::clone(&x);
+        let rest = typeswitch! { x {
+            rc n: i32 => { let _ = n; None }
+            rc default as rest => Some(rest),
+        }};
+        assert_eq!(rest.unwrap().downcast_ref::<u8>(), Some(&7));
+        assert_eq!(Rc::strong_count(&keep), 1);
+    }
+
+    #[test]
+    fn test_fn_pointer_arms() {
+        fn double(n: i32) -> i32 {
+            n * 2
+        }
+        extern "C" fn unit() {}
+        fn len(s: &str) -> usize {
+            s.len()
+        }
+
+        let subjects: [Box<dyn Any>; 4] = [
+            Box::new(double as fn(i32) -> i32),
+            Box::new(unit as extern "C" fn()),
+            Box::new(len as fn(&str) -> usize),
+            Box::new(Box::new(|n: i32| n + 1) as Box<dyn Fn(i32) -> i32>),
+        ];
+        let called = subjects.iter().map(|x| {
+            typeswitch! { x {
+                f: fn(i32) -> i32 => f(1),
+                // A return type is needed right before `=>`, see the docs.
+                f: extern "C" fn() -> () => { f(); 0 }
+                f: fn(&str) -> usize => f("abc") as i32,
+                f: Box<dyn Fn(i32) -> i32> => f(10),
+                _ => -1,
+            }}
+        });
+        assert!(called.eq([2, 0, 3, 11]));
+
+        let kinds = subjects.iter().map(|x| {
+            typeswitch! { x {
+                extern "C" fn() | fn(i32) -> i32 => "pointer",
+                f: fn(&str) -> usize if f("") == 0 => "guarded",
+                _ => "other",
+            }}
+        });
+        assert!(kinds.eq(["pointer", "pointer", "guarded", "other"]));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_pointer_arms() {
+        use core::ptr::NonNull;
+
+        let mut byte = 7u8;
+        let ptr: *mut u8 = &mut byte;
+        let subjects: [Box<dyn Any>; 3] = [Box::new(ptr.cast_const()), Box::new(ptr), Box::new(NonNull::new(ptr).unwrap())];
+        let read = subjects.iter().map(|x| {
+            typeswitch! { x {
+                // SAFETY: every pointer points to `byte`, which is still alive.
+                p: *const u8 => unsafe { **p },
+                p: *mut u8 | *const i8 => if p.is::<*mut u8>() { 1 } else { 2 },
+                p: NonNull<u8> if p.as_ptr() == ptr => unsafe { *p.as_ptr() + 1 },
+                _ => 0,
+            }}
+        });
+        assert!(read.eq([7, 1, 8]));
+
+        // `not`, `match` mode and the type list see the pointer types whole.
+        let kinds = subjects.iter().map(|x| {
+            typeswitch!(match x {
+                *mut u8 | *mut i8 => "mut",
+                not (*const u8 | NonNull<u8>) => "other",
+                NonNull<u8> => "non-null",
+                _ => "const",
+            })
+        });
+        assert!(kinds.eq(["const", "mut", "non-null"]));
+        typeswitch!(&*subjects[0] { *const u8 => {}, *mut u8 | NonNull<u8> => {} } @types POINTERS);
+        assert_eq!(POINTERS, ["*const u8", "*mut u8", "NonNull<u8>"]);
+        assert!(matches_type!(subjects[1], *const u8 | *mut u8));
+
+        let owned: Box<dyn Any> = Box::new(ptr.cast_const());
+        assert_eq!(typeswitch!(owned { box p: *const u8 => p, _ => core::ptr::null() }), ptr.cast_const());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_arm_type_list() {
+        let x: &dyn Any = &7u8;
+        let mut hit = "";
+        typeswitch!(x {
+            #[allow(unused)]
+            i32 => hit = "i32",
+            Vec<String> | (u8, u16) => hit = "pair",
+            u8 => switch {
+                u8 where |n: &u8| *n > 5 => hit = "big u8",
+                _ => hit = "u8",
             }
-        };
-        assert_eq!(result, "int 42");
+            s: (impl ToString) for [char, bool] => hit = if s.to_string().is_empty() { "" } else { "text" },
+            not (String) => {}
+            f32 | _ => {}
+        } @types HANDLED);
+        assert_eq!(hit, "big u8");
+        assert_eq!(HANDLED, ["i32", "Vec<String>", "(u8, u16)", "u8", "u8", "char", "bool", "f32"]);
+
+        let mut owned: Box<dyn Any> = Box::new(String::from("a"));
+        typeswitch!(owned {
+            box s: String => drop(s),
+            mut n: i32 => *n += 1,
+            default => {}
+        } @types OWNED);
+        assert_eq!(OWNED, ["String", "i32"]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_standard_mutable() {
-        let mut val = 10i32;
-        let x: &mut dyn Any = &mut val;
+    fn test_downcast_or_bail() {
+        use crate::TypeMismatch;
+        use std::any::TypeId;
 
-        typeswitch! { x {
-                // #[cfg(feature = "d")]
-                mut v: i32 => { *v += 10; }
-                _ => {}
+        fn sum(items: &[&dyn Any]) -> Result<i32, TypeMismatch> {
+            let mut total = 0;
+            for item in items {
+                let n: &i32 = downcast_or_bail!(*item)?;
+                total += n;
             }
+            Ok(total)
         }
+        assert_eq!(sum(&[&1i32, &2i32]), Ok(3));
+        let err = sum(&[&1i32, &"two"]).unwrap_err();
+        assert_eq!(err, TypeMismatch { expected: "i32", found: TypeId::of::<&str>() });
+        assert!(err.to_string().starts_with("expected a value of type `i32`"));
 
-        assert_eq!(val, 20);
+        let mut boxed: Box<dyn Any> = Box::new(1u8);
+        *downcast_or_bail_mut!(boxed, u8).unwrap() += 1;
+        assert_eq!(downcast_or_bail!(boxed, u8), Ok(&2));
+        assert_eq!(downcast_or_bail_mut!(boxed, i8).unwrap_err().found, TypeId::of::<u8>());
+
+        let sent: Box<dyn Any + Send> = Box::new(String::from("x"));
+        assert_eq!(downcast_or_bail_owned!(sent, String).as_deref(), Ok("x"));
+        assert_eq!(downcast_or_bail_owned!(boxed, String).unwrap_err().expected, std::any::type_name::<String>());
     }
 
     #[test]
-    fn test_owned_box() {
-        let x: Box<dyn Any> = Box::new(String::from("Hello"));
+    fn test_closed_sets() {
+        struct Push(i32);
+        struct Pop;
+        define_any_set!(Command => [Push, Pop, String]);
 
-        let res = typeswitch! { x {
-                // Checks types but consumes box only if match
-                box s: String => { s }
-                _ => { String::new() }
-            }
+        let run = |stack: &mut Vec<i32>, command: &dyn Any| {
+            typeswitch_set!(Command, command {
+                p: Push => stack.push(p.0),
+                Pop => {
+                    stack.pop();
+                }
+                s: String | &str => stack.push(i32::from(s.is::<String>())),
+            })
         };
+        let mut stack = Vec::new();
+        run(&mut stack, &Push(4));
+        run(&mut stack, &Push(5));
+        run(&mut stack, &Pop);
+        run(&mut stack, &String::new());
+        assert_eq!(stack, [4, 1]);
 
-        assert_eq!(res, "Hello");
+        // With a default, nothing is required, and the switch is a plain one.
+        let x: &dyn Any = &1.5f64;
+        let kind = typeswitch_set!(Command, x {
+            Pop => "pop",
+            _ => "other",
+        });
+        assert_eq!(kind, "other");
+
+        let outside = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            typeswitch_set!(Command, x { Push => 0, Pop => 1, String => 2 })
+        }));
+        assert!(outside.is_err());
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_pre_binding_syntax() {
-        let x: &dyn Any = &100i32;
-
-        let res = typeswitch! {
-            // Automatically binds 'v' to the downcasted type
-            v as x {
-                String => { v.len() }
-                i32 => { *v as usize }
-                _ => { 0 }
+    fn test_grouped_types() {
+        trait Wide {
+            fn wide(&self) -> i64;
+        }
+        impl Wide for i8 {
+            fn wide(&self) -> i64 {
+                i64::from(*self)
+            }
+        }
+        impl Wide for u32 {
+            fn wide(&self) -> i64 {
+                i64::from(*self)
             }
+        }
+
+        let widen = |x: &dyn Any| {
+            typeswitch! { x {
+                v: (impl Wide) for [i8, u32] => v.wide(),
+                _ => -1,
+            }}
         };
+        assert_eq!(widen(&-3i8), -3);
+        assert_eq!(widen(&7u32), 7);
+        assert_eq!(widen(&7u64), -1);
 
-        assert_eq!(res, 100);
+        // Guards and modifiers apply to every listed type.
+        let mut n = 1u32;
+        let x: &mut dyn Any = &mut n;
+        typeswitch! { x {
+            mut v: (impl Wide + Copy) for [i8, u32,] if v.wide() > 0 => { *v += 1 }
+            _ => {}
+        }}
+        assert_eq!(n, 2);
+
+        let boxed: Box<dyn Any> = Box::new(4i8);
+        let got = typeswitch! { box v as boxed {
+            (impl Wide) for [u32, i8] => v.wide() * 2,
+            _ => 0,
+        }};
+        assert_eq!(got, 8);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_pre_binding_mutable_() {
-        let mut x: Box<dyn Any> = Box::new(String::from("Hello"));
+    fn test_cell_subjects() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
 
-        let result = typeswitch! { mut v as x {
-                i32 => { format!("int {}", v) }
-                String => { format!("string {}", v) }
-                _ => { "unknown".to_string() }
+        struct Holder {
+            slot: RefCell<Box<dyn Any>>,
+        }
+
+        let cell: RefCell<Box<dyn Any>> = RefCell::new(Box::new(5i32));
+        assert_eq!(typeswitch!(cell cell { n: i32 => *n, _ => 0 }), 5);
+        // A `mut` arm takes the borrow mutably.
+        typeswitch!(cell cell { mut n: i32 => *n += 1, _ => {} });
+        assert_eq!(cell.borrow().downcast_ref::<i32>(), Some(&6));
+
+        let h = Holder { slot: RefCell::new(Box::new(String::from("a"))) };
+        typeswitch!(cell mut v as h.slot { String => v.push('b'), _ => {} });
+        assert_eq!(typeswitch!(cell v as h.slot { String => v.len(), _ => 0 }), 2);
+
+        // Through an `Rc`, with `replace` and another mode in front.
+        let shared = Rc::new(RefCell::new(Box::new(1u8) as Box<dyn Any>));
+        typeswitch!(cell shared { replace n: u8 => u16::from(n), _ => {} });
+        assert_eq!(typeswitch!(exhaustive cell shared { n: u16 => *n }), 1);
+
+        // The guard is released once the switch is done.
+        assert!(shared.try_borrow_mut().is_ok());
+
+        // Only arm patterns choose the borrow: a `&mut` in a body is not
+        // a `mut` arm, so a shared borrow held outside is fine.
+        let _held = cell.borrow();
+        let mut log = vec![0];
+        let n = typeswitch!(cell cell { n: i32 => std::mem::replace(&mut log, vec![*n]).len(), _ => 0 });
+        assert_eq!((n, log), (1, vec![6]));
+        drop(_held);
+
+        // A `mut` arm inside a nested switch borrows mutably.
+        typeswitch!(cell cell { i32 => switch { mut n: i32 => *n += 1, _ => {} } _ => {} });
+        assert_eq!(cell.borrow().downcast_ref::<i32>(), Some(&7));
+    }
+
+    #[test]
+    fn test_or_pattern_subject_forms() {
+        // Every alternative sees the value inside the box, not the box.
+        let mut boxed: Box<dyn Any> = Box::new(2u16);
+        assert!(typeswitch!(&boxed { u8 | u16 => true, _ => false }));
+        assert!(typeswitch!(&mut boxed { mut v: u8 | u16 => v.is::<u16>(), _ => false }));
+        assert!(typeswitch!(&boxed { not (u8 | Box<dyn Any>) => true, _ => false }));
+
+        let r: &dyn Any = &'c';
+        assert_eq!(typeswitch!(match r { i32 | char => 1, _ => 0 }), 1);
+    }
+
+    #[test]
+    fn test_via_extractors() {
+        struct Id(u64);
+
+        fn id(v: &dyn Any) -> Option<&u64> {
+            v.downcast_ref::<Id>().map(|id| &id.0).or_else(|| v.downcast_ref::<u64>())
+        }
+
+        fn id_mut(v: &mut dyn Any) -> Option<&mut u64> {
+            v.downcast_mut::<Id>().map(|id| &mut id.0)
+        }
+
+        let mut x: Box<dyn Any> = Box::new(Id(7));
+        assert_eq!(typeswitch!(&x { n: u64 via id => *n, _ => 0 }), 7);
+        assert_eq!(typeswitch!(&x { n: u64 via id if *n > 10 => *n, u64 via id => 1, _ => 0 }), 1);
+        typeswitch!(&mut x { mut n: u64 via id_mut => *n += 1, _ => {} });
+        // `match` mode doesn't get in the way of the extractor.
+        assert_eq!(typeswitch!(match v as &x { u64 via id => *v, _ => 0 }), 8);
+        assert_eq!(typeswitch!(&*Box::new(3u64) as &dyn Any { n: u64 via id => *n, _ => 0 }), 3);
+        assert_eq!(typeswitch!(&x { n: u64 via |_: &dyn Any| None::<&u64> => *n, _ => 0 }), 0);
+
+        typeswitch!(&x { u64 via id => {} _ => {} } @types VIA);
+        assert_eq!(VIA, ["u64"]);
+    }
+
+    #[test]
+    fn test_projection_subject() {
+        use std::cell::Cell;
+
+        enum Message {
+            Payload(Box<dyn Any>),
+            Empty,
+        }
+
+        struct Inbox {
+            message: Message,
+            reads: Cell<u32>,
+        }
+
+        impl Inbox {
+            fn payload(&self) -> &dyn Any {
+                self.reads.set(self.reads.get() + 1);
+                match &self.message {
+                    Message::Payload(inner) => &**inner,
+                    Message::Empty => &(),
+                }
             }
+        }
+
+        let inbox = Inbox { message: Message::Payload(Box::new(5i32)), reads: Cell::new(0) };
+        let n = typeswitch!(inbox.payload() {
+            n: i32 if *n > 10 => *n,
+            u8 | u16 => 1,
+            n: i32 => *n * 2,
+            _ => 0,
+        });
+        assert_eq!((n, inbox.reads.get()), (10, 1));
+
+        let v = typeswitch!(match v as inbox.payload() { i32 => *v, _ => 0 });
+        assert_eq!((v, inbox.reads.get()), (5, 2));
+
+        let empty = Inbox { message: Message::Empty, reads: Cell::new(0) };
+        assert!(typeswitch!(empty.payload() { () => true, _ => false }));
+        assert_eq!(empty.reads.get(), 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_explicit_binding_beats_pre_binding() {
+        let v = "outer";
+        let x: Box<dyn Any> = Box::new(String::from("ab"));
+        let len = typeswitch!(v as &x {
+            s: String => s.len() + v.len(),
+            i32 => *v as usize,
+            a: u8 | u16 => usize::from(a.is::<u8>()),
+            _ => 0,
+        });
+        // `v` in the `String` arm is still the outer one.
+        assert_eq!(len, 7);
+
+        let mut y: Box<dyn Any> = Box::new(1i32);
+        typeswitch!(v as &mut y { mut n: i32 if *n > 0 => *n += 10, _ => {} });
+        typeswitch!(mut v as &mut y { n: i32 => assert_eq!(*n, 11), String => v.push('x'), _ => {} });
+
+        let s = typeswitch!(box v as x { box s: String => s + "!", i32 => v.to_string(), _ => String::new() });
+        assert_eq!(s, "ab!");
+        assert_eq!(typeswitch!(v as y { i32 => *v, _ => 0 }), 11);
+    }
+
+    #[test]
+    fn test_reference_results() {
+        fn int(x: &dyn Any) -> &i32 {
+            typeswitch!(v as x { i32 => v, n: u8 if *n == 0 => &-1, _ => &0 })
+        }
+
+        fn int_mut(x: &mut dyn Any) -> Option<&mut i32> {
+            typeswitch!(x { mut n: i32 => Some(n), _ => None })
+        }
+
+        fn text<'a>(x: &'a Box<dyn Any>, fallback: &'a str) -> &'a str {
+            typeswitch!(match x { s: String => s.as_str(), s: &'static str => s, _ => fallback })
+        }
+
+        assert_eq!(*int(&7i32), 7);
+        assert_eq!(*int(&0u8), -1);
+        assert_eq!(*int(&"no"), 0);
+
+        let mut boxed: Box<dyn Any> = Box::new(1i32);
+        *int_mut(&mut *boxed).unwrap() += 1;
+        let n: &i32 = typeswitch!(boxed { n: i32 => { n } _ => { &0 } });
+        assert_eq!(*n, 2);
+
+        let fallback = String::from("none");
+        assert_eq!(text(&(Box::new(String::from("s")) as Box<dyn Any>), &fallback), "s");
+        assert_eq!(text(&boxed, &fallback), "none");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_any_helpers() {
+        struct Pair(u8, String);
+
+        let mut pair = Pair(1, String::from("a"));
+        assert!(as_any!(pair).is::<Pair>());
+        assert!(as_any!(pair.1).is::<String>());
+        typeswitch!(as_any_mut!(pair.0) { mut n: u8 => *n += 1, _ => {} });
+        assert_eq!(pair.0, 2);
+
+        // Evaluated once.
+        let mut calls = 0;
+        let mut next = || {
+            calls += 1;
+            7i32
         };
-        assert_eq!(result, "string Hello");
-    }    
+        assert_eq!(typeswitch!(as_any!(next()) { n: i32 => *n, _ => 0 }), 7);
+        assert_eq!(calls, 1);
+
+        // Boxes of `dyn Any` are seen through, not wrapped.
+        let boxed: Box<dyn Any> = into_any!(pair);
+        assert!(as_any!(boxed).is::<Pair>());
+        let sendable: Box<dyn Any + Send> = Box::new(3u16);
+        let boxed = into_any!(into_any!(sendable));
+        assert_eq!(boxed.downcast_ref::<u16>(), Some(&3));
+        let r: &dyn Any = &boxed;
+        assert!(as_any!(r).is::<Box<dyn Any>>());
+        assert!(as_any!(*r).is::<Box<dyn Any>>());
+    }
 
     #[test]
-    fn test_expression_return() {
-        let x: &dyn Any = &1.5f64;
-        let val = typeswitch! { x {
-                f64 => { 1 }
-                _ => { 0 }
-            }
+    fn test_monomorphizations() {
+        use std::collections::HashMap;
+
+        #[allow(dead_code)]
+        struct Wrapper<T, U = ()>(T, U);
+
+        fn which(x: &dyn Any) -> &'static str {
+            typeswitch!(x {
+                Wrapper<i32> => "i32",
+                w: Wrapper<u32> if w.0 > 10 => "big u32",
+                Wrapper<u32> => "u32",
+                Wrapper<i64, u8> | Wrapper<u64, u8> => "64 with u8",
+                Wrapper<Wrapper<Vec<i32>>> | Wrapper<HashMap<String, Vec<u8>>> => "nested",
+                Result<i32, String> | Option<Wrapper<i32>> => "std",
+                _ => "other",
+            })
+        }
+
+        assert_eq!(which(&Wrapper(1i32, ())), "i32");
+        assert_eq!(which(&Wrapper(11u32, ())), "big u32");
+        assert_eq!(which(&Wrapper(1u32, ())), "u32");
+        assert_eq!(which(&Wrapper(1u64, 0u8)), "64 with u8");
+        assert_eq!(which(&Wrapper(1i64, 0u16)), "other");
+        assert_eq!(which(&Wrapper(Wrapper(vec![1i32], ()), ())), "nested");
+        assert_eq!(which(&Wrapper(HashMap::<String, Vec<u8>>::new(), ())), "nested");
+        assert_eq!(which(&Some(Wrapper(1i32, ()))), "std");
+        assert_eq!(which(&Wrapper(1u8, ())), "other");
+
+        let x: Box<dyn Any> = Box::new(Wrapper(2u32, 3u8));
+        let n = typeswitch!(match v as x {
+            Wrapper<u32> => v.0,
+            Wrapper<u32, u8> | Wrapper<i32, u8> => u32::from(v.is::<Wrapper<u32, u8>>()) * 100,
+            _ => 0,
+        });
+        assert_eq!(n, 100);
+
+        typeswitch!(&*x { Wrapper<i32> => {} Wrapper<u32, u8> | Wrapper<Vec<Vec<u8>>> => {} _ => {} } @types KINDS);
+        assert_eq!(KINDS, ["Wrapper<i32>", "Wrapper<u32, u8>", "Wrapper<Vec<Vec<u8>>>"]);
+    }
+
+    #[test]
+    fn test_all_mode() {
+        fn tags(x: &dyn Any) -> Vec<&'static str> {
+            let mut tags = Vec::new();
+            typeswitch!(all x {
+                n: i32 if *n > 0 => tags.push("positive"),
+                i32 | i64 => { tags.push("integer") }
+                not (String) => tags.push("not a string"),
+                u8 => tags.push("byte"),
+                _ => tags.push("nothing"),
+            });
+            tags
+        }
+
+        assert_eq!(tags(&5i32), ["positive", "integer", "not a string"]);
+        assert_eq!(tags(&-5i32), ["integer", "not a string"]);
+        assert_eq!(tags(&String::new()), ["nothing"]);
+
+        // Repeated types, more modes after `all`, and arms that already fall through.
+        let mut b: Box<dyn Any> = Box::new(1u8);
+        typeswitch!(all mut v as &mut b { u8 => *v += 1, u8 => { fallthrough; *v *= 10 } });
+        let mut hits = 0;
+        typeswitch!(all match &*b { n: u8 => assert_eq!(*n, 20), u8 | u16 => hits += 1, i32 => hits += 10 });
+        assert_eq!(hits, 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_arm_separators() {
+        // Every arm kind with a block body, followed by `,`, `;`, or nothing.
+        macro_rules! check {
+            ($($sep:tt)?) => {{
+                let x: &dyn Any = &5i32;
+                assert_eq!(typeswitch!(x { n: i32 => { *n } $($sep)? _ => { 0 } $($sep)? }), 5);
+                assert_eq!(typeswitch!(x { u8 | i32 => { 1 } $($sep)? _ => { 0 } $($sep)? }), 1);
+                assert_eq!(typeswitch!(x { n: i32 if *n > 9 => { 1 } $($sep)? not (i32) => { 2 } $($sep)? _ @ v => { v.is::<i32>() as i32 } $($sep)? }), 1);
+                assert_eq!(typeswitch!(x { i32 => switch { u8 => { 1 } $($sep)? _ => { 2 } $($sep)? } $($sep)? _ => { 0 } $($sep)? }), 2);
+                assert_eq!(typeswitch!(x { #[cfg(any())] i32 => { 1 } $($sep)? i32 => { 2 } $($sep)? default => { 0 } $($sep)? }), 2);
+                assert_eq!(typeswitch!(match x { i32 => { 1 } $($sep)? _ => { 0 } $($sep)? }), 1);
+                assert_eq!(typeswitch!((x, x) { (i32, u8) => { 1 } $($sep)? (i32, _) => { 2 } $($sep)? _ => { 0 } $($sep)? }), 2);
+                assert_eq!(typeswitch!(indexed x { u8 => {} $($sep)? i32 => {} $($sep)? }), ((), 1));
+                assert_eq!(typeswitch_id!(core::any::TypeId::of::<i32>() { u8 => { 1 } $($sep)? i32 => { 2 } $($sep)? _ => { 0 } $($sep)? }), 2);
+
+                let mut b: Box<dyn Any> = Box::new(1u8);
+                typeswitch!(&mut b { mut v: u8 => { *v += 1 } $($sep)? replace v: String => { v } $($sep)? });
+                let mut hits = 0;
+                typeswitch!(all &*b { u8 => { hits += 1 } $($sep)? u8 | u16 => { hits += 1 } $($sep)? _ => {} $($sep)? });
+                assert_eq!(hits, 2);
+                assert_eq!(typeswitch_ref!(b { n: u8 => { *n } $($sep)? _ => { 0 } $($sep)? }), 2);
+                assert_eq!(typeswitch!(b { box n: u8 => { n } $($sep)? box _ as _b => { 0 } $($sep)? }), 2);
+            }};
+        }
+
+        check!(,);
+        check!(;);
+        check!();
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_downcast_functions() {
+        let mut x: Box<dyn Any> = Box::new(5i32);
+        assert_eq!(crate::downcast_ref_any::<i32>(&*x), Some(&5));
+        assert!(crate::downcast_ref_any::<i64>(&*x).is_none());
+        *crate::downcast_mut_any::<i32>(&mut *x).unwrap() += 1;
+        assert!(crate::downcast_mut_any::<u8>(&mut *x).is_none());
+
+        let x = crate::downcast_owned::<String>(x).unwrap_err();
+        assert_eq!(crate::downcast_owned::<i32>(x).ok(), Some(6));
+
+        let sendable: Box<dyn Any + Send> = Box::new(String::from("sent"));
+        assert_eq!(crate::downcast_owned::<String>(sendable).unwrap(), "sent");
+    }
+
+    #[test]
+    fn test_flow_mode() {
+        use core::ops::ControlFlow;
+
+        fn stage(x: &dyn Any, seen: &mut Vec<i32>) -> ControlFlow<&'static str> {
+            typeswitch!(flow x {
+                n: i32 if *n < 0 => ControlFlow::Break("negative"),
+                n: i32 => { seen.push(*n); ControlFlow::Continue(()) }
+                String => ControlFlow::Break("text"),
+            })
+        }
+
+        let items: [&dyn Any; 4] = [&1i32, &2u8, &-1i32, &5i32];
+        let mut seen = Vec::new();
+        assert_eq!(items.iter().try_for_each(|x| stage(*x, &mut seen)), ControlFlow::Break("negative"));
+        assert_eq!(seen, [1]);
+
+        // An own default, other modes, and a `_` that only covers a `switch` body.
+        let x: &dyn Any = &7u8;
+        let own: ControlFlow<(), u8> = typeswitch!(flow match x {
+            n: u8 => ControlFlow::Continue(*n),
+            _ => ControlFlow::Break(()),
+        });
+        assert_eq!(own, ControlFlow::Continue(7));
+        let bound: ControlFlow<u8> = typeswitch!(flow v as x { u8 => ControlFlow::Break(*v) });
+        assert_eq!(bound, ControlFlow::Break(7));
+        let nested: ControlFlow<u8> = typeswitch!(flow x { i32 => switch { _ => ControlFlow::Break(1) } });
+        assert_eq!(nested, ControlFlow::Continue(()));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_subject_access_modes() {
+        let mut x: Box<dyn Any> = Box::new(5i32);
+        typeswitch!(&mut x { n: i32 => *n += 1, s: String => s.push('!') });
+        assert_eq!(typeswitch!(&x { n: i32 => *n, _ => 0 }), 6);
+
+        // Pre-bindings, modes, repeated modifiers and `replace` under `&mut`.
+        typeswitch!(v as &mut x { i32 => *v *= 2 });
+        assert_eq!(typeswitch!(match v as &x { i32 => *v, _ => 0 }), 12);
+        typeswitch!(&mut x { replace s: String => s, mut n: i32 => *n += 1 });
+        let back = typeswitch!(try &mut x { s: String => s.len() }).unwrap_err();
+        assert_eq!(back.downcast_ref::<i32>(), Some(&13));
+
+        assert_eq!(typeswitch!(box x { n: i32 => n, _ => 0 }), 13);
+        let y: Box<dyn Any> = Box::new(String::from("owned"));
+        assert_eq!(typeswitch!(v as box y { String => v, _ => String::new() }), "owned");
+
+        // Any other `&` expression is still a plain subject.
+        let items: [Box<dyn Any>; 1] = [Box::new(1u8)];
+        assert!(typeswitch!(&items[0] { u8 => true, _ => false }));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_borrowing_arms_keep_box() {
+        let mut x: Box<dyn Any> = Box::new(5u8);
+        let seen = typeswitch!(x {
+            n: u8 => *n,
+            _ => 0,
+        });
+        typeswitch!(x {
+            mut n: u8 => *n += seen,
+            _ => {}
+        });
+        assert_eq!(x.downcast_ref::<u8>(), Some(&10));
+
+        let kept: Result<u8, Box<dyn Any>> = typeswitch!(x {
+            n: u8 if *n > 10 => Ok(*n),
+            box s: String => Ok(s.len() as u8),
+            box _ as rest => Err(rest),
+        });
+        assert_eq!(*kept.unwrap_err().downcast::<u8>().unwrap(), 10);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_arm_type() {
+        let x: &dyn Any = &vec![1u8];
+        assert_eq!(typeswitch!(x { String => arm_type!(), _v: Vec<u8> => arm_type!(), _ => "" }), "Vec<u8>");
+        assert_eq!(typeswitch!(x { v: Vec<u8> if v.is_empty() => "", Vec<u8> if true => arm_type!(), _ => "" }), "Vec<u8>");
+
+        let n: &dyn Any = &1.5f64;
+        assert_eq!(typeswitch!(n { _v: f32 | f64 => arm_type!(), _ => "" }), "f32 | f64");
+        let outer = typeswitch!(n {
+            f64 => switch {
+                f64 => { (arm_type!(), "nested") }
+                _ => ("", ""),
+            },
+            _ => ("", ""),
+        });
+        assert_eq!(outer, ("f64", "nested"));
+
+        let b: Box<dyn Any> = Box::new(7u16);
+        assert_eq!(typeswitch!(b { box n: u16 if n > &3 => (arm_type!(), n), _ => ("", 0) }), ("u16", 7));
+        let id = core::any::TypeId::of::<i8>();
+        assert_eq!(typeswitch_id!(id { i8 | i16 => arm_type!(), _ => "" }), "i8 | i16");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_route_mode() {
+        use crate::Routed;
+
+        let route = |x: Box<dyn Any>| -> Routed<usize> {
+            typeswitch!(route x {
+                box s: String => s.len(),
+                n: usize if *n > 0 => *n,
+            })
         };
-        assert_eq!(val, 1);
+        assert_eq!(route(Box::new(String::from("abc"))).matched(), Some(3));
+        assert!(route(Box::new(4usize)).is_matched());
+
+        let rest = route(Box::new(0usize)).unmatched().unwrap();
+        assert_eq!(rest.downcast_ref::<usize>(), Some(&0));
+        let result: Result<usize, Box<dyn Any>> = route(rest).into();
+        assert!(result.is_err());
+
+        let sendable: Box<dyn Any + Send> = Box::new(1u8);
+        let routed = typeswitch!(route match sendable { u16 => () });
+        assert!(matches!(routed, Routed::Unmatched(ref b) if b.is::<u8>()));
+        assert_eq!(Routed::<u8, ()>::from(Ok(1)).matched(), Some(1));
     }
 
     #[test]
-    fn test_or_pattern() {
-        let x: Box<dyn Any> = Box::new(10i64);
-        
-        let res = typeswitch! { x {
-                f32 | f64 => { "float" }
-                i32 | i64 => { "int" }
-                _ => { "other" }
-            }
+    fn test_unbound_arms_in_generic_fns() {
+        // Unbound arms test the subject through `@as_any`, which names
+        // `&dyn Any` itself, so nothing is left for inference to guess.
+        fn kind<T: 'static>(t: &mut T) -> &'static str {
+            typeswitch!(t {
+                i32 | i64 => "int",
+                String => "text",
+                _ => "other",
+            })
+        }
+        assert_eq!(kind(&mut 1i64), "int");
+        assert_eq!(kind(&mut String::new()), "text");
+
+        fn is_text<R: Default + From<bool>>(x: &mut dyn Any) -> R {
+            typeswitch!(match x { String => R::from(true), _ => R::default() })
+        }
+        assert!(is_text::<bool>(&mut String::new()));
+        assert_eq!(is_text::<u8>(&mut 1u8), 0);
+
+        fn boxed<T: 'static>(value: T) -> bool {
+            let b: Box<dyn Any> = Box::new(value);
+            typeswitch!(b { u8 if true => true, _ => false })
+        }
+        assert!(boxed(1u8));
+        assert!(!boxed(1u16));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_send_sync_box_subjects() {
+        fn take(x: Box<dyn Any + Send>) -> Result<String, Box<dyn Any + Send>> {
+            typeswitch!(try x {
+                box s: String if !s.is_empty() => s,
+                box n: u8 | u16 => n.to_string(),
+            })
+        }
+        assert_eq!(take(Box::new(String::from("a"))).unwrap(), "a");
+        assert_eq!(take(Box::new(2u16)).unwrap(), "2");
+        assert!(take(Box::new(String::new())).unwrap_err().is::<String>());
+
+        let shared: Box<dyn Any + Sync> = Box::new(vec![1u8, 2]);
+        let len = typeswitch!(shared {
+            v: Vec<u8> if v.len() > 5 => v.len(),
+            box v: Vec<u8> => v.len(),
+            _ => 0,
+        });
+        assert_eq!(len, 2);
+
+        let mut slot: Box<dyn Any + Sync> = Box::new(3u8);
+        typeswitch!(slot { replace n: u8 => n + 1 });
+        let rest = typeswitch!(slot { box _s: String => None, box _ as rest => Some(rest) });
+        assert_eq!(rest.and_then(|b| crate::downcast_owned::<u8>(b).ok()), Some(4));
+
+        let items: Vec<Box<dyn Any + Sync>> = vec![Box::new(1u8), Box::new("x")];
+        assert_eq!(crate::AnyIterExt::downcast_filter::<u8>(items.into_iter()).collect::<Vec<_>>(), [1]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_default_type_name() {
+        fn describe<T: 'static>(t: &T) -> String {
+            typeswitch!(v as t {
+                i32 => v.to_string(),
+                _ name => format!("a {name}"),
+            })
+        }
+        assert_eq!(describe(&1i32), "1");
+        assert_eq!(describe(&1.5f64), "a f64");
+
+        fn bump<T: 'static>(t: &mut T) -> &'static str {
+            typeswitch!(mut t { n: u8 => { *n += 1; "" } _ name => name })
+        }
+        assert_eq!(bump(&mut 0u8), "");
+        assert_eq!(bump(&mut String::new()), core::any::type_name::<String>());
+
+        let b: Box<dyn Any> = Box::new(1u16);
+        let name = typeswitch!(box b { _s: String => "", _ name => name });
+        assert_eq!(name, core::any::type_name::<dyn Any>());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_if_let_guards() {
+        struct Config {
+            database: Option<String>,
+            port: Option<u16>,
+        }
+
+        let describe = |x: &dyn Any| -> String {
+            typeswitch!(x {
+                c: Config if let Some(db) = &c.database => format!("db {db}"),
+                c: Config if let Some(port) = c.port && port > 1024 => format!("port {port}"),
+                Config => "bare".to_string(),
+                _ => "other".to_string(),
+            })
         };
-        
-        assert_eq!(res, "int");
+        assert_eq!(describe(&Config { database: Some("pg".into()), port: None }), "db pg");
+        assert_eq!(describe(&Config { database: None, port: Some(8080) }), "port 8080");
+        assert_eq!(describe(&Config { database: None, port: Some(80) }), "bare");
+        assert_eq!(describe(&1u8), "other");
+
+        fn port(x: &dyn Any) -> Option<&Option<u16>> {
+            x.downcast_ref::<Config>().map(|c| &c.port)
+        }
+        let c = Config { database: None, port: Some(7) };
+        assert_eq!(typeswitch!((&c) { p: Option<u16> via port if let Some(n) = *p => n, _ => 0 }), 7);
+
+        let mut x: Box<dyn Any> = Box::new(Config { database: None, port: Some(1) });
+        typeswitch!(mut c as x {
+            Config if let Some(port) = &mut c.port => { fallthrough; *port += 1; }
+            Config if let Some(2) = c.port => c.database = Some("set".into()),
+            _ => {}
+        });
+        let taken = typeswitch!(x {
+            box c: Config if let Some(port) = c.port => (port, c.database),
+            _ => (0, None),
+        });
+        assert_eq!(taken, (2, Some("set".to_string())));
+    }
+
+    #[test]
+    fn test_projection_arms() {
+        let x: &dyn Any = &String::from("abc");
+        let len = typeswitch!(x {
+            i32 => |&n| n as usize,
+            String => |s: &String| s.len(),
+            _ => 0,
+        });
+        assert_eq!(len, 3);
+
+        let mut items: Vec<u8> = vec![1];
+        let any: &mut dyn Any = &mut items;
+        typeswitch!(mut v as any {
+            Vec<u8> => |bytes| bytes.push(2),
+            _ => (),
+        });
+        assert_eq!(items, [1, 2]);
+
+        let f: fn(usize) -> usize = typeswitch!(x {
+            String => { |n| n + 1 }
+            _ => |n| n,
+        });
+        assert_eq!(f(1), 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_boxed_slices_and_guarded_or_patterns() {
+        fn take(x: Box<dyn Any>) -> Vec<i64> {
+            typeswitch!(x {
+                Box<[u16]> | Vec<u16> if false => Vec::new(),
+                s: Box<[i8]> | Box<[i16]> if s.is::<Box<[i8]>>() => vec![8],
+                box v: Box<[i32]> => v.into_vec().into_iter().map(i64::from).collect(),
+                box v: Box<[u8]> | Box<[u32]> if !v.is_empty() => vec![v.len() as i64],
+                _ => vec![-1],
+            })
+        }
+
+        assert_eq!(take(Box::new(Box::<[i32]>::from([1, 2]))), [1, 2]);
+        assert_eq!(take(Box::new(Box::<[u32]>::from([5, 6, 7]))), [3]);
+        assert_eq!(take(Box::new(Box::<[u8]>::from([]))), [-1]);
+        assert_eq!(take(Box::new(Box::<[i8]>::from([]))), [8]);
+        assert_eq!(take(Box::new(Box::<[i16]>::from([]))), [-1]);
+        assert_eq!(take(Box::new(vec![1u16])), [-1]);
+
+        let mut n = 1u32;
+        let x: &mut dyn Any = &mut n;
+        typeswitch!(mut v as x {
+            u16 | u32 if v.is::<u32>() => *v.downcast_mut::<u32>().unwrap() += 1,
+            _ => {}
+        });
+        assert_eq!(n, 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_weak_arms() {
+        use std::rc::{Rc, Weak};
+        use std::sync::Arc;
+
+        struct Logger(u32);
+        struct Metrics;
+
+        let logger: Rc<dyn Any> = Rc::new(Logger(3));
+        let metrics: Rc<dyn Any> = Rc::new(Metrics);
+        let gone: Rc<dyn Any> = Rc::new(Logger(0));
+        let observers: Vec<Weak<dyn Any>> = vec![Rc::downgrade(&logger), Rc::downgrade(&gone), Rc::downgrade(&metrics)];
+        drop(gone);
+
+        let seen: Vec<u32> = observers
+            .iter()
+            .map(|observer| {
+                typeswitch! { observer {
+                    weak l: Logger if l.0 > 10 => { 100 }
+                    // One upgrade for the whole switch: the subject's owner and this arm.
+                    weak l: Logger => { assert_eq!(Rc::strong_count(&l), 2); l.0 }
+                    weak m: Metrics | String => Rc::strong_count(&m) as u32,
+                    weak_dead => 0,
+                    _ => u32::MAX,
+                }}
+            })
+            .collect();
+        assert_eq!(seen, [3, 0, 2]);
+        assert_eq!(Rc::strong_count(&logger), 1);
+
+        // Without a `weak_dead` arm, a dead pointer reaches the default.
+        let res = typeswitch! { &observers[1] {
+            weak l: Logger => l.0,
+            _ => 7,
+        }};
+        assert_eq!(res, 7);
+
+        let shared: Arc<dyn Any + Send + Sync> = Arc::new(String::from("sync"));
+        let weak = Arc::downgrade(&shared);
+        let len = typeswitch!(weak s as weak {
+            u8 => usize::from(*s),
+            String if s.starts_with('s') => s.len(),
+            _ => usize::MAX,
+        });
+        assert_eq!(len, 4);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_single_check_takes() {
+        use crate::TypeMismatch;
+        use std::any::TypeId;
+
+        // `replace` moves the value out unchecked, except in `match` mode.
+        let mut b: Box<dyn Any> = Box::new(4i32);
+        typeswitch!(b { u8 => {}, replace n: i32 => n * 2, _ => {} });
+        typeswitch!(match b { replace n: i32 => n + 1, _ => {} });
+        assert_eq!(b.downcast_ref::<i32>(), Some(&9));
+
+        fn bump(value: &mut dyn Any) -> Result<(), TypeMismatch> {
+            *downcast_or_bail_mut!(value, u8)? += 1;
+            Ok(())
+        }
+        let mut n = 1u8;
+        assert_eq!(bump(&mut n), Ok(()));
+        assert_eq!(n, 2);
+        assert_eq!(bump(&mut 1i32).map_err(|e| e.found), Err(TypeId::of::<i32>()));
     }
 
-    // TODO:
     #[test]
     fn test_type_param() {
-        fn _func<T: 'static>(t: &T) {
+        // A `&T` or `&mut T` subject is coerced to `dyn Any`, which only needs `T: 'static`.
+        fn func<T: 'static>(t: &T) -> String {
             typeswitch! { t {
-                t: String => {println!("Amen: {t}");}
+                t: String => { format!("Amen: {t}") }
+                n: i32 => format!("int {n}"),
+                _ => String::new(),
             }}
         }
+        assert_eq!(func(&String::from("x")), "Amen: x");
+        assert_eq!(func(&1i32), "int 1");
+        assert_eq!(func(&1u8), "");
+
+        fn bump<T: 'static>(t: &mut T) {
+            typeswitch!(mut v as t { i32 => *v += 1, _ => {} });
+        }
+        let mut n = 1i32;
+        bump(&mut n);
+        bump(&mut String::new());
+        assert_eq!(n, 2);
     }
 }
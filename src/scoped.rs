@@ -0,0 +1,296 @@
+//! Type switching over values that borrow, behind the `unsafe-scoped` feature.
+//!
+//! `Any` needs `'static`, so a `Token<'arena>` can't be a `dyn Any`. Here a
+//! type instead names a `'static` stand-in for itself, usually the same type
+//! with its lifetime set to `'static`, and that stand-in's `TypeId` tells the
+//! types apart. Nothing checks the stand-in, which is why [`ScopedType`] is an
+//! `unsafe` trait.
+
+use core::any::TypeId;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+/// A type that [`ScopedAny`] can erase, with its `'static` stand-in.
+///
+/// # Safety
+///
+/// `Static` identifies `Self` among all the types that implement
+/// `ScopedType<'a>`, for every `'a`:
+///
+/// - `Self` has no lifetime other than `'a`, and no type parameter that could
+///   bring one in. Write `impl<'a> ScopedType<'a> for Token<'a>`, never an impl
+///   for `Token<'b>` or one generic over `T: 'a`.
+/// - No other type shares `Self`'s `Static`, bar `Self` at another `'a`. Using
+///   `Self` with `'a` replaced by `'static` (`Token<'static>` for `Token<'a>`)
+///   always meets this.
+///
+/// A downcast trusts `Static` alone, so breaking either rule lets it hand out
+/// a reference of the wrong type.
+///
+/// [`scoped_static!`](crate::scoped_static) implements the trait safely for
+/// your types without lifetimes. The crate implements it for the primitives,
+/// `&'a str`, `&'a [u8]` and, with `alloc`, `String`.
+///
+/// # Example
+///
+/// ```rust
+/// use typeswitch::{scoped_typeswitch, ScopedAny, ScopedType};
+///
+/// struct Token<'a>(&'a str);
+///
+/// // SAFETY: `Token<'a>` only has `'a`, and `Token<'static>` is its own stand-in.
+/// unsafe impl<'a> ScopedType<'a> for Token<'a> {
+///     type Static = Token<'static>;
+/// }
+///
+/// let text = String::from("word");
+/// let items: [&dyn ScopedAny<'_>; 2] = [&Token(&text), &7u32];
+/// let out: Vec<String> = items
+///     .iter()
+///     .map(|item| scoped_typeswitch!(*item {
+///         t: Token<'_> => t.0.to_uppercase(),
+///         n: u32 => n.to_string(),
+///         _ => String::new(),
+///     }))
+///     .collect();
+/// assert_eq!(out, ["WORD", "7"]);
+/// ```
+pub unsafe trait ScopedType<'a>: 'a {
+    /// The `'static` type whose `TypeId` stands for `Self`.
+    type Static: ?Sized + 'static;
+}
+
+mod sealed {
+    pub trait Sealed<'a> {}
+
+    impl<'a, T: super::ScopedType<'a>> Sealed<'a> for T {}
+}
+
+/// A `dyn Any` for types that may borrow for `'a`.
+///
+/// It is implemented for every [`ScopedType`], and only for those.
+pub trait ScopedAny<'a>: sealed::Sealed<'a> + 'a {
+    /// The `TypeId` of the value's [`ScopedType::Static`].
+    fn scoped_type_id(&self) -> TypeId;
+}
+
+impl<'a, T: ScopedType<'a>> ScopedAny<'a> for T {
+    fn scoped_type_id(&self) -> TypeId {
+        TypeId::of::<T::Static>()
+    }
+}
+
+impl<'a> dyn ScopedAny<'a> + 'a {
+    /// Whether the value is a `T`.
+    pub fn is<T: ScopedType<'a>>(&self) -> bool {
+        self.scoped_type_id() == TypeId::of::<T::Static>()
+    }
+
+    /// The value as a `&T`, if it is one.
+    pub fn downcast_ref<T: ScopedType<'a>>(&self) -> Option<&T> {
+        if self.is::<T>() {
+            // SAFETY: the value is a `T`, as `ScopedType` promises that no
+            // other type has `T::Static`.
+            Some(unsafe { &*(self as *const Self as *const T) })
+        } else {
+            None
+        }
+    }
+
+    /// The value as a `&mut T`, if it is one.
+    pub fn downcast_mut<T: ScopedType<'a>>(&mut self) -> Option<&mut T> {
+        if self.is::<T>() {
+            // SAFETY: as for `downcast_ref`.
+            Some(unsafe { &mut *(self as *mut Self as *mut T) })
+        } else {
+            None
+        }
+    }
+
+    /// The box as a `Box<T>`, or back unchanged if the value is not a `T`.
+    #[cfg(feature = "alloc")]
+    pub fn downcast<T: ScopedType<'a>>(self: Box<Self>) -> Result<Box<T>, Box<Self>> {
+        if self.is::<T>() {
+            // SAFETY: as for `downcast_ref`; the allocation was made for a `T`.
+            Ok(unsafe { Box::from_raw(Box::into_raw(self) as *mut T) })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Implements [`ScopedType`] for types without lifetimes, each its own stand-in.
+///
+/// ```rust
+/// struct Config { verbose: bool }
+///
+/// typeswitch::scoped_static!(Config);
+/// ```
+#[macro_export]
+macro_rules! scoped_static {
+    ($($ty:ty),+ $(,)?) => {$(
+        // SAFETY: a `'static` type names itself, which no other type can.
+        unsafe impl<'a> $crate::ScopedType<'a> for $ty
+        where
+            $ty: 'static,
+        {
+            type Static = $ty;
+        }
+    )+};
+}
+
+scoped_static!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, ());
+
+#[cfg(feature = "alloc")]
+scoped_static!(alloc::string::String);
+
+// SAFETY: `&'a str` only has `'a`, and `&'static str` is its own stand-in.
+unsafe impl<'a> ScopedType<'a> for &'a str {
+    type Static = &'static str;
+}
+
+// SAFETY: as for `&'a str`.
+unsafe impl<'a> ScopedType<'a> for &'a [u8] {
+    type Static = &'static [u8];
+}
+
+/// A [`typeswitch!`](crate::typeswitch!) for a `&dyn ScopedAny<'a>` or
+/// `&mut dyn ScopedAny<'a>` subject.
+///
+/// It takes a subset of the arms: `binding: Type`, `mut binding: Type` (for a
+/// `&mut` subject), a bare `Type` and a closing `_`, each with a block or an
+/// expression followed by a comma. A block may be followed by `,` or `;`. Arm
+/// types are [`ScopedType`]s, and their lifetimes can be left as `'_`. See
+/// [`ScopedType`] for an example.
+#[macro_export]
+macro_rules! scoped_typeswitch {
+    (@arms $var:ident;) => { () };
+
+    (@arms $var:ident; , $($rest:tt)*) => {
+        $crate::scoped_typeswitch!(@arms $var; $($rest)*)
+    };
+
+    (@arms $var:ident; ; $($rest:tt)*) => {
+        $crate::scoped_typeswitch!(@arms $var; $($rest)*)
+    };
+
+    (@arms $var:ident; _ => $body:block $(,)?) => { $body };
+    (@arms $var:ident; _ => $body:block ;) => { $body };
+    (@arms $var:ident; _ => $body:expr $(,)?) => { $body };
+
+    (@arms $var:ident; mut $bind:ident : $ty:ty => $body:block $($rest:tt)*) => {
+        if let ::core::option::Option::Some($bind) = $var.downcast_mut::<$ty>() {
+            $body
+        } else {
+            $crate::scoped_typeswitch!(@arms $var; $($rest)*)
+        }
+    };
+
+    (@arms $var:ident; $bind:ident : $ty:ty => $body:block $($rest:tt)*) => {
+        if let ::core::option::Option::Some($bind) = $var.downcast_ref::<$ty>() {
+            $body
+        } else {
+            $crate::scoped_typeswitch!(@arms $var; $($rest)*)
+        }
+    };
+
+    (@arms $var:ident; $ty:ty => $body:block $($rest:tt)*) => {
+        if $var.is::<$ty>() {
+            $body
+        } else {
+            $crate::scoped_typeswitch!(@arms $var; $($rest)*)
+        }
+    };
+
+    (@arms $var:ident; $($pat:ident)+ : $ty:ty => $body:expr $(, $($rest:tt)*)?) => {
+        $crate::scoped_typeswitch!(@arms $var; $($pat)+ : $ty => { $body } $($($rest)*)?)
+    };
+
+    (@arms $var:ident; $ty:ty => $body:expr $(, $($rest:tt)*)?) => {
+        $crate::scoped_typeswitch!(@arms $var; $ty => { $body } $($($rest)*)?)
+    };
+
+    (@arms $var:ident; $($rest:tt)+) => {
+        compile_error!(concat!("scoped_typeswitch: unsupported arm `", stringify!($($rest)+), "`"))
+    };
+
+    // The subject is everything up to the arms, and is evaluated once.
+    (@head [$($subject:tt)+] { $($arms:tt)* }) => {
+        match $($subject)+ {
+            #[allow(unused_mut)]
+            mut subject => $crate::scoped_typeswitch!(@arms subject; $($arms)*),
+        }
+    };
+
+    (@head [$($subject:tt)*] $next:tt $($rest:tt)+) => {
+        $crate::scoped_typeswitch!(@head [$($subject)* $next] $($rest)+)
+    };
+
+    ($($rest:tt)+) => {
+        $crate::scoped_typeswitch!(@head [] $($rest)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScopedAny, ScopedType};
+
+    struct Word<'a>(&'a str);
+
+    // SAFETY: `Word<'a>` only has `'a`, and `Word<'static>` is its own stand-in.
+    unsafe impl<'a> ScopedType<'a> for Word<'a> {
+        type Static = Word<'static>;
+    }
+
+    struct Count(usize);
+
+    crate::scoped_static!(Count);
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_scoped_downcasts() {
+        let text = String::from("scoped");
+        let word = Word(&text);
+        let any: &dyn ScopedAny<'_> = &word;
+        assert!(any.is::<Word<'_>>());
+        assert!(!any.is::<u8>());
+        assert_eq!(any.downcast_ref::<Word<'_>>().map(|w| w.0), Some("scoped"));
+
+        let mut count = Count(1);
+        let any: &mut dyn ScopedAny<'_> = &mut count;
+        any.downcast_mut::<Count>().unwrap().0 += 1;
+        assert!(any.downcast_mut::<Word<'_>>().is_none());
+        assert_eq!(count.0, 2);
+
+        let boxed: Box<dyn ScopedAny<'_>> = Box::new(Word(&text));
+        let boxed = boxed.downcast::<u8>().unwrap_err();
+        assert_eq!(boxed.downcast::<Word<'_>>().ok().map(|w| w.0), Some("scoped"));
+    }
+
+    #[test]
+    fn test_scoped_typeswitch() {
+        let text = String::from("abc");
+        let mut items: Vec<Box<dyn ScopedAny<'_>>> = vec![Box::new(Word(&text)), Box::new(Count(3)), Box::new(1u8)];
+
+        for item in &mut items {
+            scoped_typeswitch!(&mut **item {
+                mut c: Count => c.0 *= 2,
+                _ => {}
+            });
+        }
+
+        let lens: Vec<usize> = items
+            .iter()
+            .map(|item| {
+                scoped_typeswitch!(&**item {
+                    w: Word<'_> => { w.0.len() };
+                    c: Count => c.0,
+                    u8 => 1,
+                    _ => 0,
+                })
+            })
+            .collect();
+        assert_eq!(lens, [3, 6, 1]);
+    }
+}
@@ -0,0 +1,899 @@
+//! # TypeSwitch Macros
+//!
+//! A procedural front end for [`typeswitch!`](https://docs.rs/typeswitch). It parses the
+//! same syntax as the `macro_rules!` version and reports malformed arms at the offending
+//! tokens instead of deep inside the muncher. Valid input is handed, untouched, to
+//! `::typeswitch::__typeswitch!`, so the generated code is exactly the same.
+//!
+//! Enable it through the `proc-macro` feature of the `typeswitch` crate rather than
+//! depending on this crate directly.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Checks the arms of a type switch, then expands it with the `typeswitch` rules.
+///
+/// See the `typeswitch` crate for the full syntax.
+#[proc_macro]
+pub fn typeswitch(input: TokenStream) -> TokenStream {
+    match check(input.clone()) {
+        Ok(()) => forward(input),
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+/// Adds a `dispatch` method to an enum whose variants carry a `Box<dyn Any>` or
+/// `Arc<dyn Any>` payload.
+///
+/// `dispatch(f)` calls `f` with the payload of the current variant as `&dyn Any`.
+/// Variants without such a field are dispatched as `&()`. A variant may have other
+/// fields too, but at most one `Box<dyn Any>` or `Arc<dyn Any>`, with or without
+/// `+ Send` or `+ Send + Sync`. Generic enums are not supported.
+///
+/// See the `typeswitch` crate for an example.
+#[proc_macro_derive(TypeDispatch)]
+pub fn derive_type_dispatch(input: TokenStream) -> TokenStream {
+    match type_dispatch(input) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+/// Turns a function whose body is a `match` on one of its parameters into a
+/// type switch on that parameter.
+///
+/// Each arm names a type the way a tuple struct pattern would: `i32(n) => ...`
+/// binds the value, `String => ...` or `String(_) => ...` only tests for it, and
+/// `_ => ...` is the default. Or-patterns and guards carry over. A `&mut dyn Any`
+/// parameter is switched as `mut`, and a `Box<dyn Any>` as `box`; any other
+/// type is switched as a shared borrow.
+///
+/// See the `typeswitch` crate for an example.
+#[proc_macro_attribute]
+pub fn cases(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if let Some(token) = attr.into_iter().next() {
+        return Error::new(token.span(), "`#[cases]` takes no arguments").to_compile_error();
+    }
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        _ => return Error::new(Span::call_site(), "`#[cases]` can only be used on a function with a body").to_compile_error(),
+    };
+    // On a mistake the function keeps its signature, so callers don't pile up errors of their own.
+    let switch = cases_body(&tokens, &body).unwrap_or_else(|err| err.to_compile_error());
+    let mut block = Group::new(Delimiter::Brace, switch);
+    block.set_span(body.span());
+    tokens.push(TokenTree::Group(block));
+    tokens.into_iter().collect()
+}
+
+/// A diagnostic pointing at the user's tokens.
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Error { span, message: message.into() }
+    }
+
+    /// `::core::compile_error! { "..." }` with every token spanned at the mistake.
+    fn to_compile_error(&self) -> TokenStream {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let mut body = Group::new(Delimiter::Brace, TokenTree::Literal(message).into());
+        body.set_span(self.span);
+
+        let mut tokens = path(&["core", "compile_error"], self.span);
+        tokens.push(punct('!', Spacing::Alone, self.span));
+        tokens.push(TokenTree::Group(body));
+        tokens.into_iter().collect()
+    }
+}
+
+/// `::typeswitch::__typeswitch! { input }`
+fn forward(input: TokenStream) -> TokenStream {
+    let span = Span::call_site();
+    let mut tokens = path(&["typeswitch", "__typeswitch"], span);
+    tokens.push(punct('!', Spacing::Alone, span));
+    tokens.push(TokenTree::Group(Group::new(Delimiter::Brace, input)));
+    tokens.into_iter().collect()
+}
+
+// ============================================================
+// VALIDATION
+// ============================================================
+
+fn check(input: TokenStream) -> Result<(), Error> {
+    let mut tokens: Vec<TokenTree> = input.into_iter().collect();
+    // `{ ... } @types NAME` also lists the arms' types.
+    if let [.., at, types, TokenTree::Ident(_)] = &tokens[..]
+        && is_punct(at, '@')
+        && is_ident(types, "types")
+    {
+        tokens.truncate(tokens.len() - 3);
+    }
+    match tokens.split_last() {
+        Some((TokenTree::Group(arms), head)) if arms.delimiter() == Delimiter::Brace => {
+            let all = check_head(head, arms.span_open())?;
+            if arms.stream().is_empty() {
+                return Err(Error::new(arms.span(), "at least one arm is required"));
+            }
+            check_arms(arms.stream(), all)
+        }
+        Some((last, _)) => Err(Error::new(last.span(), "expected `{ ... }` arms after the subject")),
+        None => Err(Error::new(Span::call_site(), "expected a subject followed by `{ ... }` arms")),
+    }
+}
+
+/// Strips the modes (`with_id`, `indexed`, `route`, `try`, `exhaustive`, `strict`, `match`, `err_source`, `opt`, `via_any`, `cell`, `all`, `flow`) and a
+/// `v as` / `mut v as` pre-binding, then makes sure something is left to switch on.
+/// Returns whether the switch is in `all` mode.
+fn check_head(head: &[TokenTree], brace: Span) -> Result<bool, Error> {
+    let mut subject = head;
+    let mut all = false;
+    for mode in ["with_id", "indexed", "route", "try", "exhaustive", "strict", "match", "err_source", "opt", "via_any", "cell", "all", "flow"] {
+        // A lone `with_id`, `indexed`, `route`, `exhaustive`, `strict`, `err_source`, `opt`, `via_any`, `cell`, `all` or `flow` is the subject itself.
+        if let [first, rest @ ..] = subject
+            && is_ident(first, mode)
+            && !rest.is_empty()
+        {
+            subject = rest;
+            all |= mode == "all";
+        }
+    }
+    subject = match subject {
+        [TokenTree::Ident(_), as_, rest @ ..] | [TokenTree::Ident(_), TokenTree::Ident(_), as_, rest @ ..]
+            if is_ident(as_, "as") =>
+        {
+            rest
+        }
+        _ => subject,
+    };
+    if subject.is_empty() {
+        return Err(Error::new(brace, "expected a subject before the arms"));
+    }
+    Ok(all)
+}
+
+fn check_arms(stream: TokenStream, all: bool) -> Result<(), Error> {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    // Types already handled by an arm that always fires when the type matches.
+    let mut covered: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let attrs_start = i;
+        while i < tokens.len() && is_punct(&tokens[i], '#') {
+            match tokens.get(i + 1) {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => i += 2,
+                _ => return Err(Error::new(tokens[i].span(), "expected `[...]` after `#`")),
+            }
+        }
+        let has_attrs = i > attrs_start;
+
+        let start = i;
+        let mut in_guard = false;
+        while i < tokens.len() && !is_fat_arrow(&tokens, i) {
+            if !in_guard && is_body(&tokens, start, i) {
+                break;
+            }
+            in_guard |= is_guard(&tokens[i]);
+            i += 1;
+        }
+        let pattern = &tokens[start..i];
+        if i == tokens.len() || !is_fat_arrow(&tokens, i) {
+            return Err(match pattern.last() {
+                Some(last) => Error::new(last.span(), format!("expected `=>` after `{}`", text(pattern))),
+                None => Error::new(tokens[i - 1].span(), "expected an arm after this attribute"),
+            });
+        }
+        let arrow = tokens[i].span();
+        i += 2;
+
+        let pattern = check_pattern(pattern, arrow)?;
+
+        let mut falls_through = false;
+        match tokens.get(i) {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                falls_through = is_fallthrough(g);
+                i = skip_separator(&tokens, i + 1);
+            }
+            // `switch { ... }` holds arms of its own.
+            Some(kw) if is_ident(kw, "switch") && matches!(tokens.get(i + 1), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace) => {
+                if let Some(TokenTree::Group(g)) = tokens.get(i + 1) {
+                    check_arms(g.stream(), false)?;
+                }
+                i = skip_separator(&tokens, i + 2);
+            }
+            Some(comma) if is_punct(comma, ',') => {
+                return Err(Error::new(comma.span(), "expected an expression or `{ ... }` after `=>`"));
+            }
+            // Without a parser we cannot always tell where an expression ends;
+            // give up on the remaining arms rather than report a false error.
+            Some(_) => match expr_end(&tokens, i) {
+                Some(end) => i = (end + 1).min(tokens.len()),
+                None => return Ok(()),
+            },
+            None => return Err(Error::new(arrow, "expected an expression or `{ ... }` after `=>`")),
+        }
+
+        // An attribute may remove the arm, and a `fallthrough` arm goes on to the
+        // next ones, so neither makes later arms unreachable.
+        if has_attrs || falls_through {
+            continue;
+        }
+        match pattern {
+            Pattern::Default => {
+                // A trailing separator after the default is fine; another arm is not.
+                if let [next, ..] = &tokens[i..] {
+                    return Err(Error::new(
+                        next.span(),
+                        "unreachable arm: the `_` default above already matches everything",
+                    ));
+                }
+                break;
+            }
+            // In an `all` switch every matching arm runs, so a repeated type is fine.
+            Pattern::Types { .. } if all => {}
+            Pattern::Types { types, guarded } => {
+                for ty in types {
+                    let name = text(ty);
+                    if covered.contains(&name) {
+                        return Err(Error::new(ty[0].span(), format!("unreachable arm: `{name}` already matched")));
+                    }
+                    if !guarded {
+                        covered.push(name);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keywords that may precede `binding: Type` to pick the access mode.
+const MODIFIERS: &[&str] = &["box", "replace", "arc", "rc", "rc_mut", "weak", "mut", "ref", "move"];
+
+enum Pattern<'a> {
+    /// `_`, `_ @ name`, `_ name`, or `box _ as name` and its `arc` and `rc` forms
+    Default,
+    /// The `|`-separated alternatives, and whether the arm can still be skipped
+    /// when the type matches: an `if` guard, `rc_mut` on a shared `Rc`, or
+    /// `weak` on a dead `Weak`.
+    Types { types: Vec<&'a [TokenTree]>, guarded: bool },
+}
+
+/// `_ [[@] name]`, `(box|arc|rc) _ as name`, `not (Type [| Type]*)`, `weak_dead`, or `[modifier] [binding:] Type [| Type]* [if guard]`.
+fn check_pattern(pattern: &[TokenTree], arrow: Span) -> Result<Pattern<'_>, Error> {
+    let first = match pattern.first() {
+        Some(first) => first,
+        None => return Err(Error::new(arrow, "expected a type before `=>`")),
+    };
+    if let TokenTree::Punct(p) = first
+        && matches!(p.as_char(), ',' | ';')
+    {
+        return Err(Error::new(p.span(), format!("expected an arm, found `{}`", p.as_char())));
+    }
+    // Rust reads `fn() =>` as a misspelled `fn() -> ...`, even inside a macro.
+    if let [.., fn_, TokenTree::Group(args)] = pattern
+        && is_ident(fn_, "fn")
+        && args.delimiter() == Delimiter::Parenthesis
+    {
+        return Err(Error::new(arrow, "write `-> ()` after a function pointer type that ends the pattern"));
+    }
+    if is_ident(first, "_") {
+        // `_ [@ name] as dyn Trait` only takes registered types, so arms may follow.
+        let named = matches!(pattern, [_, at, TokenTree::Ident(_), ..] if is_punct(at, '@'));
+        if let [as_, dyn_, tr @ ..] = &pattern[if named { 3 } else { 1 }..]
+            && is_ident(as_, "as")
+            && is_ident(dyn_, "dyn")
+        {
+            if tr.is_empty() {
+                return Err(Error::new(dyn_.span(), "expected a trait after `dyn`"));
+            }
+            return Ok(Pattern::Types { types: Vec::new(), guarded: true });
+        }
+        return match pattern {
+            [_] => Ok(Pattern::Default),
+            [_, at, TokenTree::Ident(_)] if is_punct(at, '@') => Ok(Pattern::Default),
+            [_, at, ..] if is_punct(at, '@') => Err(Error::new(at.span(), "expected a name after `_ @`")),
+            [_, TokenTree::Ident(_)] => Ok(Pattern::Default),
+            [_, next, ..] => Err(Error::new(next.span(), "expected `=>`, `@ name` or a name after `_`")),
+            [] => unreachable!(),
+        };
+    }
+
+    // `not (A | B)` names the types it skips, so it never covers one.
+    if let [not, TokenTree::Group(types), rest @ ..] = pattern
+        && is_ident(not, "not")
+        && types.delimiter() == Delimiter::Parenthesis
+    {
+        if types.stream().is_empty() {
+            return Err(Error::new(types.span(), "expected the types to exclude"));
+        }
+        if let Some(next) = rest.first()
+            && !is_ident(next, "if")
+        {
+            return Err(Error::new(next.span(), "expected `=>` or `if` after `not (...)`"));
+        }
+        return Ok(Pattern::Types { types: Vec::new(), guarded: true });
+    }
+
+    if let [kind, under, as_, TokenTree::Ident(_)] = pattern
+        && ["box", "arc", "rc"].iter().any(|k| is_ident(kind, k))
+        && (is_ident(under, "_") || is_ident(under, "default"))
+        && is_ident(as_, "as")
+    {
+        return Ok(Pattern::Default);
+    }
+
+    // `weak_dead` is about the pointer, not the type behind it.
+    if let [dead] = pattern
+        && is_ident(dead, "weak_dead")
+    {
+        return Ok(Pattern::Types { types: Vec::new(), guarded: true });
+    }
+
+    let mut rest = pattern;
+    let fallible = is_ident(first, "rc_mut") || is_ident(first, "weak");
+    if MODIFIERS.iter().any(|m| is_ident(first, m)) {
+        // `ref mut` is the one modifier spelled with two words.
+        let words = if is_ident(first, "ref") && rest.get(1).is_some_and(|t| is_ident(t, "mut")) { 2 } else { 1 };
+        match binding_len(&rest[words..]) {
+            Some(len) => rest = &rest[words + len..],
+            None => return Err(Error::new(first.span(), format!("expected `binding: Type` after `{first}`"))),
+        }
+    } else if let Some(len) = binding_len(rest) {
+        rest = &rest[len..];
+    }
+
+    let (types, guard) = match rest.iter().position(is_guard) {
+        Some(pos) => (&rest[..pos], Some((&rest[pos], &rest[pos + 1..]))),
+        None => (rest, None),
+    };
+    if let Some((if_, condition)) = guard
+        && condition.is_empty()
+    {
+        return Err(Error::new(if_.span(), format!("expected a condition after `{if_}`")));
+    }
+
+    // `(impl Trait) for [A, B]` repeats the arm per type. Its types are not
+    // tracked, so it never makes a later arm unreachable.
+    if let [TokenTree::Group(bound), for_, TokenTree::Group(list)] = types
+        && bound.delimiter() == Delimiter::Parenthesis
+        && is_ident(for_, "for")
+        && list.delimiter() == Delimiter::Bracket
+    {
+        if list.stream().is_empty() {
+            return Err(Error::new(list.span(), "expected the types after `for`"));
+        }
+        return Ok(Pattern::Types { types: vec![types], guarded: true });
+    }
+
+    // Every `|`-separated alternative needs at least one token.
+    let mut alternatives = Vec::new();
+    let mut start = 0;
+    for (idx, token) in types.iter().enumerate() {
+        if is_punct(token, '|') {
+            if idx == start {
+                return Err(Error::new(token.span(), "expected a type before `|`"));
+            }
+            alternatives.push(&types[start..idx]);
+            start = idx + 1;
+        }
+    }
+    if start == types.len() {
+        let span = guard.map_or(arrow, |(if_, _)| if_.span());
+        return Err(Error::new(span, "expected a type"));
+    }
+    alternatives.push(&types[start..]);
+
+    // A trailing `| _` makes the arm a default that also names some types.
+    if let Some(pos) = alternatives.iter().position(|alt| matches!(alt, [t] if is_ident(t, "_"))) {
+        let under = alternatives[pos][0].span();
+        if pos + 1 != alternatives.len() || guard.is_some() {
+            return Err(Error::new(under, "`_` in an or-pattern must be the last alternative, without a guard"));
+        }
+        if rest.len() != pattern.len() {
+            return Err(Error::new(under, "an or-pattern ending in `_` cannot have a binding"));
+        }
+        return Ok(Pattern::Default);
+    }
+    for ty in &alternatives {
+        if let Some(lifetime) = borrowed_lifetime(ty) {
+            return Err(Error::new(
+                lifetime.span(),
+                format!("`{}` can never match: arm types must be `'static`, like every `dyn Any`", text(ty)),
+            ));
+        }
+    }
+    Ok(Pattern::Types { types: alternatives, guarded: fallible || guard.is_some() })
+}
+
+/// A named lifetime other than `'static` in a type. `'_` is inferred, as
+/// `'static` in an arm, and lifetimes bound by a `for<...>` are skipped.
+fn borrowed_lifetime(ty: &[TokenTree]) -> Option<Ident> {
+    if ty.iter().any(|t| is_ident(t, "for")) {
+        return None;
+    }
+    ty.iter().enumerate().find_map(|(i, token)| match (token, ty.get(i + 1)) {
+        (TokenTree::Punct(tick), Some(TokenTree::Ident(name)))
+            if tick.as_char() == '\'' && !matches!(name.to_string().as_str(), "static" | "_") =>
+        {
+            Some(name.clone())
+        }
+        (TokenTree::Group(g), _) => borrowed_lifetime(&g.stream().into_iter().collect::<Vec<_>>()),
+        _ => None,
+    })
+}
+
+/// `if guard`, or `where predicate`, which is a guard calling the predicate.
+fn is_guard(token: &TokenTree) -> bool {
+    is_ident(token, "if") || is_ident(token, "where")
+}
+
+/// The length of a leading `binding:` or destructuring `Pattern:`, colon
+/// included. Patterns are a struct, tuple struct, tuple or slice pattern.
+fn binding_len(tokens: &[TokenTree]) -> Option<usize> {
+    let end = tokens.iter().position(is_guard).unwrap_or(tokens.len());
+    let colon = tokens[..end].iter().position(is_colon)?;
+    let binding = match &tokens[..colon] {
+        [TokenTree::Ident(_)] => true,
+        [path @ .., TokenTree::Group(_)] => path.iter().all(|t| matches!(t, TokenTree::Ident(_)) || is_punct(t, ':')),
+        _ => false,
+    };
+    binding.then_some(colon + 1)
+}
+
+// ============================================================
+// CASES
+// ============================================================
+
+/// The type switch replacing the body of a `#[cases]` function.
+fn cases_body(signature: &[TokenTree], body: &Group) -> Result<TokenStream, Error> {
+    let keyword = signature
+        .iter()
+        .position(|t| is_ident(t, "fn"))
+        .ok_or_else(|| Error::new(Span::call_site(), "`#[cases]` can only be used on functions"))?;
+    let params = parameters(&signature[keyword + 1..])
+        .ok_or_else(|| Error::new(signature[keyword].span(), "expected the function's parameters"))?;
+
+    let tokens: Vec<TokenTree> = body.stream().into_iter().collect();
+    let (subject, arms) = match &tokens[..] {
+        [match_, TokenTree::Ident(subject), TokenTree::Group(arms)]
+            if is_ident(match_, "match") && arms.delimiter() == Delimiter::Brace =>
+        {
+            (subject, arms)
+        }
+        _ => {
+            return Err(Error::new(
+                body.span(),
+                "the body of a `#[cases]` function must be a single `match` on one of its parameters",
+            ));
+        }
+    };
+    let ty = split_commas(params.stream().into_iter().collect())
+        .into_iter()
+        .find_map(|param| {
+            let param = strip_attributes(&param);
+            let param = match param {
+                [mut_, rest @ ..] if is_ident(mut_, "mut") => rest,
+                _ => param,
+            };
+            match param {
+                [TokenTree::Ident(name), colon, ty @ ..] if name.to_string() == subject.to_string() && is_colon(colon) => {
+                    Some(ty.to_vec())
+                }
+                _ => None,
+            }
+        })
+        .ok_or_else(|| Error::new(subject.span(), format!("`{subject}` is not a parameter of this function")))?;
+
+    let mut head = Vec::new();
+    if let Some(mode) = subject_mode(&ty) {
+        head.push(TokenTree::Ident(Ident::new(mode, subject.span())));
+    }
+    head.push(TokenTree::Ident(subject.clone()));
+    let mut arms_group = Group::new(Delimiter::Brace, case_arms(arms)?);
+    arms_group.set_span(arms.span());
+    head.push(TokenTree::Group(arms_group));
+
+    let input: TokenStream = head.into_iter().collect();
+    check(input.clone())?;
+    Ok(forward(input))
+}
+
+/// The `(...)` parameter list following a function's name and generics.
+fn parameters(tokens: &[TokenTree]) -> Option<&Group> {
+    let mut depth = 0usize;
+    let mut arrow = false;
+    for token in tokens {
+        match token {
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis && depth == 0 => return Some(g),
+            t if is_punct(t, '<') => depth += 1,
+            t if is_punct(t, '>') && !arrow => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        arrow = matches!(token, TokenTree::Punct(p) if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+    }
+    None
+}
+
+/// `mut` for a `&mut` parameter and `box` for a `Box<...>` one.
+fn subject_mode(ty: &[TokenTree]) -> Option<&'static str> {
+    match ty {
+        [amp, rest @ ..] if is_punct(amp, '&') => {
+            // Skip a `'a` lifetime, a `'` joined to an identifier.
+            let rest = match rest {
+                [tick, TokenTree::Ident(_), rest @ ..] if is_punct(tick, '\'') => rest,
+                _ => rest,
+            };
+            rest.first().is_some_and(|t| is_ident(t, "mut")).then_some("mut")
+        }
+        _ => {
+            let open = ty.iter().position(|t| is_punct(t, '<'))?;
+            (open > 0 && is_ident(&ty[open - 1], "Box")).then_some("box")
+        }
+    }
+}
+
+/// Rewrites `match` arms into type switch arms. Only the patterns change: the
+/// guards and bodies are copied as they are, each arm ending in a `,`.
+fn case_arms(arms: &Group) -> Result<TokenStream, Error> {
+    let tokens: Vec<TokenTree> = arms.stream().into_iter().collect();
+    // Outside any group, `=>` only ever separates an arm's pattern from its body.
+    let arrows: Vec<usize> = (0..tokens.len().saturating_sub(1)).filter(|&i| is_fat_arrow(&tokens, i)).collect();
+    if arrows.is_empty() {
+        return match tokens.first() {
+            Some(first) => Err(Error::new(first.span(), "expected `=>` after the pattern")),
+            None => Err(Error::new(arms.span(), "at least one arm is required")),
+        };
+    }
+
+    // Each arm's pattern starts after the `,` ending the body before it, or
+    // after that body's block if it has no comma.
+    let mut starts = vec![0];
+    for pair in arrows.windows(2) {
+        let (from, to) = (pair[0] + 2, pair[1]);
+        let segment = &tokens[from..to];
+        let start = match last_comma(segment) {
+            Some(comma) => comma + 1,
+            None => segment
+                .iter()
+                .rposition(|t| matches!(t, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace))
+                .map(|block| block + 1)
+                .ok_or_else(|| Error::new(tokens[pair[0]].span(), "expected `,` after this arm's body"))?,
+        };
+        starts.push(from + start);
+    }
+
+    let mut out = Vec::new();
+    for (n, &arrow) in arrows.iter().enumerate() {
+        let end = starts.get(n + 1).copied().unwrap_or(tokens.len());
+        let mut body = &tokens[arrow + 2..end];
+        if let [rest @ .., comma] = body
+            && is_punct(comma, ',')
+        {
+            body = rest;
+        }
+        if body.is_empty() {
+            return Err(Error::new(tokens[arrow].span(), "expected an expression or `{ ... }` after `=>`"));
+        }
+        out.extend(case_pattern(&tokens[starts[n]..arrow], tokens[arrow].span())?);
+        out.extend(tokens[arrow..arrow + 2].iter().cloned());
+        out.extend(body.iter().cloned());
+        out.push(punct(',', Spacing::Alone, tokens[end - 1].span()));
+    }
+    Ok(out.into_iter().collect())
+}
+
+/// The index of the last `,` outside a `::<...>` generic argument list.
+fn last_comma(tokens: &[TokenTree]) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut last = None;
+    for (i, token) in tokens.iter().enumerate() {
+        let turbofish = i > 0 && is_punct(&tokens[i - 1], ':');
+        match token {
+            t if is_punct(t, ',') && depth == 0 => last = Some(i),
+            t if is_punct(t, '<') && (depth > 0 || turbofish) => depth += 1,
+            t if is_punct(t, '>') && depth > 0 => depth -= 1,
+            _ => {}
+        }
+    }
+    last
+}
+
+/// `Type(binding)` becomes `binding: Type`, and `Type(_)` becomes `Type`.
+/// Attributes, `_` and the guard are kept.
+fn case_pattern(pattern: &[TokenTree], arrow: Span) -> Result<Vec<TokenTree>, Error> {
+    let stripped = strip_attributes(pattern);
+    let attrs = &pattern[..pattern.len() - stripped.len()];
+    let (types, guard) = match stripped.iter().position(|t| is_ident(t, "if")) {
+        Some(pos) => stripped.split_at(pos),
+        None => (stripped, &[][..]),
+    };
+    let mut out = attrs.to_vec();
+    if let [under] = types
+        && is_ident(under, "_")
+    {
+        out.extend(stripped.iter().cloned());
+        return Ok(out);
+    }
+
+    let mut binding: Option<Vec<TokenTree>> = None;
+    let mut alternatives = Vec::new();
+    for (n, alternative) in types.split(|t| is_punct(t, '|')).enumerate() {
+        let (ty, bound) = match alternative {
+            [ty @ .., TokenTree::Group(g)] if !ty.is_empty() && g.delimiter() == Delimiter::Parenthesis => {
+                let inner: Vec<TokenTree> = g.stream().into_iter().collect();
+                match &inner[..] {
+                    [] => return Err(Error::new(g.span(), "expected a binding or `_` inside `(...)`")),
+                    [under] if is_ident(under, "_") => (ty, None),
+                    _ => (ty, Some(inner)),
+                }
+            }
+            [] => {
+                let span = types.first().map_or(arrow, |t| t.span());
+                return Err(Error::new(span, "expected a type"));
+            }
+            ty => (ty, None),
+        };
+        let consistent = match (&binding, &bound) {
+            _ if n == 0 => true,
+            (Some(a), Some(b)) => text(a) == text(b),
+            (None, None) => true,
+            _ => false,
+        };
+        if !consistent {
+            return Err(Error::new(ty[0].span(), "every alternative must bind the same name"));
+        }
+        if n == 0 {
+            binding = bound;
+        }
+        alternatives.push(ty);
+    }
+
+    if let Some(binding) = binding {
+        let span = binding[0].span();
+        out.extend(binding);
+        out.push(punct(':', Spacing::Alone, span));
+    }
+    for (n, ty) in alternatives.into_iter().enumerate() {
+        if n > 0 {
+            out.push(punct('|', Spacing::Alone, ty[0].span()));
+        }
+        out.extend(ty.iter().cloned());
+    }
+    out.extend(guard.iter().cloned());
+    Ok(out)
+}
+
+// ============================================================
+// DERIVE
+// ============================================================
+
+fn type_dispatch(input: TokenStream) -> Result<TokenStream, Error> {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let keyword = tokens
+        .iter()
+        .position(|t| is_ident(t, "enum") || is_ident(t, "struct") || is_ident(t, "union"))
+        .ok_or_else(|| Error::new(Span::call_site(), "expected an enum"))?;
+    if !is_ident(&tokens[keyword], "enum") {
+        return Err(Error::new(tokens[keyword].span(), "`TypeDispatch` can only be derived for enums"));
+    }
+    let name = match tokens.get(keyword + 1) {
+        Some(TokenTree::Ident(name)) => name.to_string(),
+        _ => return Err(Error::new(tokens[keyword].span(), "expected the enum's name")),
+    };
+    let variants = match tokens.get(keyword + 2) {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body.stream(),
+        Some(other) => return Err(Error::new(other.span(), "`TypeDispatch` does not support generic enums")),
+        None => return Err(Error::new(tokens[keyword].span(), "expected the enum's variants")),
+    };
+
+    let mut arms = String::new();
+    let mut unit = false;
+    let mut count = 0;
+    for variant in split_commas(variants.into_iter().collect()) {
+        let variant = strip_attributes(&variant);
+        let Some(TokenTree::Ident(ident)) = variant.first() else { continue };
+        count += 1;
+        let (fields, named) = match variant.get(1) {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => (g.stream(), false),
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => (g.stream(), true),
+            _ => {
+                unit = true;
+                continue;
+            }
+        };
+        let fields = split_commas(fields.into_iter().collect());
+        let mut payloads = fields.iter().enumerate().filter_map(|(i, field)| {
+            let field = strip_attributes(field);
+            let field = strip_visibility(field);
+            let (name, ty) = if named {
+                (field.first().map(|t| t.to_string())?, field.get(2..)?)
+            } else {
+                (String::new(), field)
+            };
+            is_boxed_any(ty).then_some((i, name))
+        });
+        match (payloads.next(), payloads.next()) {
+            (Some((i, field)), None) => {
+                let pattern = if named {
+                    format!("{{ {field}: payload, .. }}")
+                } else {
+                    format!("({} payload, ..)", "_, ".repeat(i))
+                };
+                arms += &format!("Self::{ident} {pattern} => f(&**payload),\n");
+            }
+            (Some(_), Some(_)) => {
+                return Err(Error::new(
+                    ident.span(),
+                    "`TypeDispatch` variants can have at most one `Box<dyn Any>` or `Arc<dyn Any>` field",
+                ));
+            }
+            _ => unit = true,
+        }
+    }
+
+    let body = match (count, arms.is_empty()) {
+        (0, _) => String::from("match *self {}"),
+        (_, true) => String::from("f(&())"),
+        (_, false) if unit => format!("match self {{ {arms} _ => f(&()), }}"),
+        (_, false) => format!("match self {{ {arms} }}"),
+    };
+    let code = format!(
+        "#[automatically_derived]
+        impl {name} {{
+            /// Calls `f` with this variant's `dyn Any` payload, or with `&()` if it has none.
+            pub fn dispatch<R>(&self, f: impl ::core::ops::FnOnce(&dyn ::core::any::Any) -> R) -> R {{
+                {body}
+            }}
+        }}"
+    );
+    Ok(code.parse().expect("generated `dispatch` should parse"))
+}
+
+/// Splits a list of variants or fields at its top-level commas. Commas inside
+/// `<...>` belong to a generic argument list.
+fn split_commas(tokens: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut items = vec![Vec::new()];
+    let mut depth = 0usize;
+    let mut arrow = false;
+    for token in tokens {
+        match &token {
+            t if is_punct(t, ',') && depth == 0 => {
+                items.push(Vec::new());
+                arrow = false;
+                continue;
+            }
+            t if is_punct(t, '<') => depth += 1,
+            // The `>` of a `->` return type does not close anything.
+            t if is_punct(t, '>') && !arrow => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        arrow = matches!(&token, TokenTree::Punct(p) if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+        items.last_mut().expect("starts with one item").push(token);
+    }
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Drops leading `#[...]` attributes, doc comments included.
+fn strip_attributes(mut tokens: &[TokenTree]) -> &[TokenTree] {
+    while let [pound, TokenTree::Group(g), rest @ ..] = tokens {
+        if !is_punct(pound, '#') || g.delimiter() != Delimiter::Bracket {
+            break;
+        }
+        tokens = rest;
+    }
+    tokens
+}
+
+/// Drops a leading `pub` or `pub(...)`.
+fn strip_visibility(tokens: &[TokenTree]) -> &[TokenTree] {
+    match tokens {
+        [vis, TokenTree::Group(g), rest @ ..] if is_ident(vis, "pub") && g.delimiter() == Delimiter::Parenthesis => rest,
+        [vis, rest @ ..] if is_ident(vis, "pub") => rest,
+        _ => tokens,
+    }
+}
+
+/// `Box<dyn Any>` or `Arc<dyn Any>`, through any path and with any `+ Send`
+/// or `+ Sync` bounds.
+fn is_boxed_any(ty: &[TokenTree]) -> bool {
+    let Some(open) = ty.iter().position(|t| is_punct(t, '<')) else { return false };
+    let (pointer, inner) = (&ty[..open], &ty[open + 1..]);
+    let end = inner.iter().position(|t| is_punct(t, '+') || is_punct(t, '>')).unwrap_or(inner.len());
+    let is_path = |tokens: &[TokenTree]| tokens.iter().all(|t| matches!(t, TokenTree::Ident(_)) || is_punct(t, ':'));
+    matches!(pointer.last(), Some(t) if is_ident(t, "Box") || is_ident(t, "Arc"))
+        && is_path(pointer)
+        && matches!(inner.first(), Some(t) if is_ident(t, "dyn"))
+        && matches!(inner[..end].last(), Some(t) if is_ident(t, "Any"))
+        && is_path(&inner[1..end])
+        && matches!(ty.last(), Some(t) if is_punct(t, '>'))
+}
+
+// ============================================================
+// TOKEN HELPERS
+// ============================================================
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+fn is_punct(token: &TokenTree, ch: char) -> bool {
+    matches!(token, TokenTree::Punct(p) if p.as_char() == ch)
+}
+
+/// A lone `:`, as opposed to either half of a `::` path separator.
+fn is_colon(token: &TokenTree) -> bool {
+    matches!(token, TokenTree::Punct(p) if p.as_char() == ':' && p.spacing() == Spacing::Alone)
+}
+
+fn is_fat_arrow(tokens: &[TokenTree], i: usize) -> bool {
+    matches!(&tokens[i], TokenTree::Punct(p) if p.as_char() == '=' && p.spacing() == Spacing::Joint)
+        && tokens.get(i + 1).is_some_and(|t| is_punct(t, '>'))
+}
+
+/// A `{ ... }` where a type is expected is almost certainly the arm's body
+/// with a forgotten `=>`. Braces right after `<` or `,` are const generic arguments,
+/// and braces followed by `:` are the fields of a destructuring pattern.
+fn is_body(tokens: &[TokenTree], start: usize, i: usize) -> bool {
+    matches!(&tokens[i], TokenTree::Group(g) if g.delimiter() == Delimiter::Brace)
+        && i > start
+        && !is_punct(&tokens[i - 1], '<')
+        && !is_punct(&tokens[i - 1], ',')
+        && !tokens.get(i + 1).is_some_and(is_colon)
+}
+
+/// The index of the `,` ending an expression arm body that starts at `i`, or
+/// the end of the input. `None` if the body has a top-level `<` or `|`, which
+/// could be a generic argument list or closure parameters hiding a comma.
+fn expr_end(tokens: &[TokenTree], i: usize) -> Option<usize> {
+    for (j, token) in tokens.iter().enumerate().skip(i) {
+        if is_punct(token, ',') {
+            return Some(j);
+        }
+        if is_punct(token, '<') || is_punct(token, '|') {
+            return None;
+        }
+    }
+    Some(tokens.len())
+}
+
+/// The index after `i`'s optional `,` or `;`, which may follow a block body.
+fn skip_separator(tokens: &[TokenTree], i: usize) -> usize {
+    match tokens.get(i) {
+        Some(t) if is_punct(t, ',') || is_punct(t, ';') => i + 1,
+        _ => i,
+    }
+}
+
+/// A block starting with `fallthrough;`.
+fn is_fallthrough(body: &Group) -> bool {
+    let mut tokens = body.stream().into_iter();
+    matches!(tokens.next(), Some(t) if is_ident(&t, "fallthrough"))
+        && matches!(tokens.next(), Some(t) if is_punct(&t, ';'))
+}
+
+fn text(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+fn punct(ch: char, spacing: Spacing, span: Span) -> TokenTree {
+    let mut p = Punct::new(ch, spacing);
+    p.set_span(span);
+    TokenTree::Punct(p)
+}
+
+/// `::a::b` as a list of tokens.
+fn path(segments: &[&str], span: Span) -> Vec<TokenTree> {
+    let mut tokens = Vec::new();
+    for segment in segments {
+        tokens.push(punct(':', Spacing::Joint, span));
+        tokens.push(punct(':', Spacing::Alone, span));
+        tokens.push(TokenTree::Ident(Ident::new(segment, span)));
+    }
+    tokens
+}